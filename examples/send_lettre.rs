@@ -0,0 +1,34 @@
+/*
+ * Copyright Stalwart Labs Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use lettre::{transport::stub::StubTransport, Transport};
+use mail_builder::MessageBuilder;
+
+fn main() {
+    // Build a message and its SMTP envelope
+    let (envelope, eml) = MessageBuilder::new()
+        .from(("John Doe", "john@doe.com"))
+        .to("jane@doe.com")
+        .bcc("james@doe.com")
+        .subject("Hello, world!")
+        .text_body("Message contents go here.")
+        .into_lettre()
+        .unwrap();
+
+    // Send it with any lettre transport, for example SmtpTransport
+    let mailer = StubTransport::new_ok();
+    mailer.send_raw(&envelope, &eml).unwrap();
+
+    // Print envelope and raw message
+    println!("From: {:?}", envelope.from());
+    println!("To: {:?}", envelope.to());
+    println!("{}", String::from_utf8(eml).unwrap());
+}
@@ -0,0 +1,41 @@
+/*
+ * Copyright Stalwart Labs Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use mail_builder::MessageBuilder;
+use mail_send::{smtp::message::IntoMessage, SmtpClientBuilder};
+
+#[tokio::main]
+async fn main() {
+    let builder = MessageBuilder::new()
+        .from(("John Doe", "john@doe.com"))
+        .to("jane@doe.com")
+        .bcc("james@doe.com")
+        .subject("Hello, world!")
+        .text_body("Message contents go here.");
+
+    // Print the SMTP envelope and raw message
+    let message = builder.clone().into_message().unwrap();
+    println!("From: {}", message.mail_from.email);
+    for rcpt in &message.rcpt_to {
+        println!("To: {}", rcpt.email);
+    }
+    println!("{}", String::from_utf8_lossy(&message.body));
+
+    // Send the message builder directly with a mail-send SMTP client
+    SmtpClientBuilder::new("localhost", 25)
+        .implicit_tls(false)
+        .connect_plain()
+        .await
+        .unwrap()
+        .send(builder)
+        .await
+        .unwrap();
+}
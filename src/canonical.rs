@@ -0,0 +1,161 @@
+/*
+ * Copyright Stalwart Labs Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::io::{self, Write};
+
+/// RFC 6376 canonicalization algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Canonicalization {
+    Relaxed,
+    Simple,
+}
+
+/// Canonicalizes a message body as described in RFC 6376 section 3.4.
+/// The body is expected to use CRLF line endings.
+pub fn canonicalize_body(
+    body: &[u8],
+    canonicalization: Canonicalization,
+    mut output: impl Write,
+) -> io::Result<()> {
+    let mut lines = body.split(|&ch| ch == b'\n').collect::<Vec<_>>();
+    if lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+
+    let mut canonical_lines = lines
+        .into_iter()
+        .map(|line| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            match canonicalization {
+                Canonicalization::Simple => line.to_vec(),
+                Canonicalization::Relaxed => reduce_whitespace(line),
+            }
+        })
+        .collect::<Vec<_>>();
+    while canonical_lines.last().is_some_and(|line| line.is_empty()) {
+        canonical_lines.pop();
+    }
+
+    if canonical_lines.is_empty() {
+        if canonicalization == Canonicalization::Simple {
+            output.write_all(b"\r\n")?;
+        }
+        return Ok(());
+    }
+    for line in canonical_lines {
+        output.write_all(&line)?;
+        output.write_all(b"\r\n")?;
+    }
+    Ok(())
+}
+
+/// Canonicalizes a header field, given its name and its unfolded or folded
+/// value, as described in RFC 6376 section 3.4. The result ends with CRLF.
+pub fn canonicalize_header(
+    name: &str,
+    value: &[u8],
+    canonicalization: Canonicalization,
+) -> Vec<u8> {
+    let mut output = Vec::with_capacity(name.len() + value.len() + 4);
+    match canonicalization {
+        Canonicalization::Simple => {
+            output.extend_from_slice(name.as_bytes());
+            output.extend_from_slice(b":");
+            output.extend_from_slice(value);
+        }
+        Canonicalization::Relaxed => {
+            output.extend_from_slice(name.trim_end().to_ascii_lowercase().as_bytes());
+            output.extend_from_slice(b":");
+            let unfolded = value
+                .iter()
+                .copied()
+                .filter(|&ch| ch != b'\r' && ch != b'\n')
+                .collect::<Vec<_>>();
+            let value = reduce_whitespace(&unfolded);
+            output.extend_from_slice(value.strip_prefix(b" ").unwrap_or(&value));
+        }
+    }
+    output.extend_from_slice(b"\r\n");
+    output
+}
+
+/// Reduces runs of spaces and tabs to a single space and removes trailing
+/// whitespace.
+fn reduce_whitespace(line: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(line.len());
+    let mut pending_space = false;
+    for &ch in line {
+        if ch == b' ' || ch == b'\t' {
+            pending_space = true;
+        } else {
+            if pending_space {
+                result.push(b' ');
+                pending_space = false;
+            }
+            result.push(ch);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonicalize_body, canonicalize_header, Canonicalization};
+
+    #[test]
+    fn canonicalize_bodies() {
+        for (body, relaxed, simple) in [
+            (
+                " C \r\nD \t E\r\n\r\n\r\n",
+                " C\r\nD E\r\n",
+                " C \r\nD \t E\r\n",
+            ),
+            ("", "", "\r\n"),
+            ("\r\n\r\n", "", "\r\n"),
+            ("no line ending", "no line ending\r\n", "no line ending\r\n"),
+        ] {
+            for (canonicalization, expected) in [
+                (Canonicalization::Relaxed, relaxed),
+                (Canonicalization::Simple, simple),
+            ] {
+                let mut output = Vec::new();
+                canonicalize_body(body.as_bytes(), canonicalization, &mut output).unwrap();
+                assert_eq!(
+                    std::str::from_utf8(&output).unwrap(),
+                    expected,
+                    "{:?} {:?}",
+                    body,
+                    canonicalization
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn canonicalize_headers() {
+        assert_eq!(
+            canonicalize_header(
+                "Subject",
+                b" Hello \r\n\t  world ",
+                Canonicalization::Relaxed
+            ),
+            b"subject:Hello world\r\n"
+        );
+        assert_eq!(
+            canonicalize_header(
+                "Subject",
+                b" Hello \r\n\t  world ",
+                Canonicalization::Simple
+            ),
+            b"Subject: Hello \r\n\t  world \r\n"
+        );
+    }
+}
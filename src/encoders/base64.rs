@@ -20,6 +20,26 @@ pub fn base64_encode(input: &[u8]) -> io::Result<Vec<u8>> {
     Ok(buf)
 }
 
+/// Encodes the input using the URL and filename safe alphabet from RFC 4648
+/// section 5, optionally without padding. Not intended for MIME bodies,
+/// which always use the standard alphabet.
+pub fn base64url_encode(input: &[u8], padding: bool) -> io::Result<Vec<u8>> {
+    let mut buf = base64_encode(input)?;
+    if !padding {
+        while buf.last() == Some(&CHARPAD) {
+            buf.pop();
+        }
+    }
+    for ch in buf.iter_mut() {
+        match ch {
+            b'+' => *ch = b'-',
+            b'/' => *ch = b'_',
+            _ => (),
+        }
+    }
+    Ok(buf)
+}
+
 pub fn base64_encode_mime(
     input: &[u8],
     mut output: impl Write,
@@ -130,6 +150,7 @@ pub fn base64_encode_mime(
 }
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod tests {
 
     #[test]
@@ -166,6 +187,26 @@ mod tests {
             assert_eq!(std::str::from_utf8(&output).unwrap(), expected_result);
         }
     }
+
+    #[test]
+    fn encode_base64url() {
+        for (input, padding, expected) in [
+            (&[0x3e, 0x3f, 0xff][..], true, "Pj__"),
+            (&[0xfb, 0xef, 0xbe, 0x3e][..], true, "----Pg=="),
+            (&[0xfb, 0xef, 0xbe, 0x3e][..], false, "----Pg"),
+            (
+                &b"Are you a Shimano or Campagnolo person?"[..],
+                false,
+                "QXJlIHlvdSBhIFNoaW1hbm8gb3IgQ2FtcGFnbm9sbyBwZXJzb24_",
+            ),
+        ] {
+            assert_eq!(
+                std::str::from_utf8(&super::base64url_encode(input, padding).unwrap()).unwrap(),
+                expected
+            );
+        }
+        assert_eq!(super::base64_encode(&[0x3e, 0x3f, 0xff]).unwrap(), b"Pj//");
+    }
 }
 
 /*
@@ -180,6 +221,7 @@ mod tests {
  *
  */
 
+#[allow(clippy::byte_char_slices)]
 pub static E0: &[u8] = &[
     b'A', b'A', b'A', b'A', b'B', b'B', b'B', b'B', b'C', b'C', b'C', b'C', b'D', b'D', b'D', b'D',
     b'E', b'E', b'E', b'E', b'F', b'F', b'F', b'F', b'G', b'G', b'G', b'G', b'H', b'H', b'H', b'H',
@@ -199,6 +241,7 @@ pub static E0: &[u8] = &[
     b'8', b'8', b'8', b'8', b'9', b'9', b'9', b'9', b'+', b'+', b'+', b'+', b'/', b'/', b'/', b'/',
 ];
 
+#[allow(clippy::byte_char_slices)]
 pub static E1: &[u8] = &[
     b'A', b'B', b'C', b'D', b'E', b'F', b'G', b'H', b'I', b'J', b'K', b'L', b'M', b'N', b'O', b'P',
     b'Q', b'R', b'S', b'T', b'U', b'V', b'W', b'X', b'Y', b'Z', b'a', b'b', b'c', b'd', b'e', b'f',
@@ -218,6 +261,7 @@ pub static E1: &[u8] = &[
     b'w', b'x', b'y', b'z', b'0', b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'+', b'/',
 ];
 
+#[allow(clippy::byte_char_slices)]
 pub static E2: &[u8] = &[
     b'A', b'B', b'C', b'D', b'E', b'F', b'G', b'H', b'I', b'J', b'K', b'L', b'M', b'N', b'O', b'P',
     b'Q', b'R', b'S', b'T', b'U', b'V', b'W', b'X', b'Y', b'Z', b'a', b'b', b'c', b'd', b'e', b'f',
@@ -0,0 +1,88 @@
+/*
+ * Copyright Stalwart Labs Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::io::{self, Write};
+
+/// Writer applying SMTP transparency (RFC 5321 section 4.5.2): lines
+/// starting with "." are prefixed with another ".". Call `finish` to write
+/// the terminating "CRLF.CRLF" sequence.
+pub struct DotStuffingWriter<W: Write> {
+    inner: W,
+    is_line_start: bool,
+}
+
+impl<W: Write> DotStuffingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            is_line_start: true,
+        }
+    }
+
+    /// Write the end of data sequence, preceded by a CRLF if the last line
+    /// is not terminated, and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.is_line_start {
+            self.inner.write_all(b"\r\n")?;
+        }
+        self.inner.write_all(b".\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for DotStuffingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut start = 0;
+        for (pos, &ch) in buf.iter().enumerate() {
+            if self.is_line_start && ch == b'.' {
+                self.inner.write_all(&buf[start..pos])?;
+                self.inner.write_all(b".")?;
+                start = pos;
+            }
+            self.is_line_start = ch == b'\n';
+        }
+        self.inner.write_all(&buf[start..])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::DotStuffingWriter;
+
+    #[test]
+    fn dot_stuffing() {
+        for (chunks, expected) in [
+            (
+                vec![".start\r\n", "middle.\r\n.", "\r\n..two\r\n"],
+                "..start\r\nmiddle.\r\n..\r\n...two\r\n.\r\n",
+            ),
+            (vec!["no line ending"], "no line ending\r\n.\r\n"),
+            (vec![], ".\r\n"),
+        ] {
+            let mut writer = DotStuffingWriter::new(Vec::new());
+            for chunk in chunks {
+                writer.write_all(chunk.as_bytes()).unwrap();
+            }
+            assert_eq!(
+                String::from_utf8(writer.finish().unwrap()).unwrap(),
+                expected
+            );
+        }
+    }
+}
@@ -19,6 +19,17 @@ pub enum EncodingType {
     None,
 }
 
+impl EncodingType {
+    /// Returns the Content-Transfer-Encoding name of this encoding.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EncodingType::Base64 => "base64",
+            EncodingType::QuotedPrintable(_) => "quoted-printable",
+            EncodingType::None => "7bit",
+        }
+    }
+}
+
 pub fn get_encoding_type(input: &[u8], is_inline: bool, is_body: bool) -> EncodingType {
     let base64_len = (input.len() * 4 / 3 + 3) & !3;
     let mut qp_len = if !is_inline { input.len() / 76 } else { 0 };
@@ -96,25 +107,161 @@ pub fn rfc2047_encode(input: &str, mut output: impl Write) -> io::Result<usize>
             }
             let bytes_written =
                 quoted_printable_encode(input.as_bytes(), &mut output, true, false)?
-                    + if is_ascii { 19 } else { 14 };
+                    + if is_ascii { 17 } else { 14 };
             output.write_all(b"?=\"")?;
             bytes_written
         }
         EncodingType::None => {
-            let mut bytes_written = 2;
-            output.write_all(b"\"")?;
-            for &ch in input.as_bytes() {
-                if ch == b'\\' || ch == b'"' {
-                    output.write_all(b"\\")?;
-                    bytes_written += 1;
-                } else if ch == b'\r' || ch == b'\n' {
-                    continue;
-                }
-                output.write_all(&[ch])?;
-                bytes_written += 1;
-            }
-            output.write_all(b"\"")?;
-            bytes_written
+            let quoted = quote_string(input);
+            output.write_all(quoted.as_bytes())?;
+            quoted.len()
         }
     })
 }
+
+/// Returns `input` as an RFC 5322 quoted-string, wrapped in double quotes
+/// with `"` and `\` escaped with a backslash. CR and LF characters are
+/// removed.
+///
+/// ```rust
+/// use mail_builder::encoders::encode::quote_string;
+///
+/// assert_eq!(quote_string(r#"say "hi" \ bye"#), r#""say \"hi\" \\ bye""#);
+/// ```
+pub fn quote_string(input: &str) -> String {
+    let mut quoted = String::with_capacity(input.len() + 2);
+    quoted.push('"');
+    for ch in input.chars() {
+        match ch {
+            '\\' | '"' => {
+                quoted.push('\\');
+                quoted.push(ch);
+            }
+            '\r' | '\n' => (),
+            _ => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Detects the charset of a text file: "us-ascii", "utf-8", "utf-16" when
+/// a byte order mark is present, "utf-16le" or "utf-16be" when every other
+/// byte is NUL, otherwise "iso-8859-1" or "windows-1252". Returns `None`
+/// when the contents are not text in any of these charsets, such as when
+/// they contain control characters other than tabs and line breaks.
+///
+/// ```rust
+/// use mail_builder::encoders::encode::detect_charset;
+///
+/// assert_eq!(detect_charset("Grüße".as_bytes()), Some("utf-8"));
+/// assert_eq!(detect_charset(b"Gr\xfc\xdfe"), Some("iso-8859-1"));
+/// assert_eq!(detect_charset(b"\x89PNG\r\n\x1a\n"), None);
+/// ```
+// `usize::is_multiple_of` requires Rust 1.87.
+#[allow(clippy::manual_is_multiple_of)]
+pub fn detect_charset(input: &[u8]) -> Option<&'static str> {
+    if let Some(input) = input.strip_prefix(b"\xef\xbb\xbf") {
+        return std::str::from_utf8(input).ok().map(|_| "utf-8");
+    } else if input.starts_with(b"\xff\xfe") || input.starts_with(b"\xfe\xff") {
+        return (input.len() % 2 == 0).then_some("utf-16");
+    }
+
+    if input.len() % 2 == 0 && !input.is_empty() {
+        let (mut even_nuls, mut odd_nuls) = (0, 0);
+        for pair in input.chunks_exact(2) {
+            even_nuls += usize::from(pair[0] == 0);
+            odd_nuls += usize::from(pair[1] == 0);
+        }
+        let units = input.len() / 2;
+        if even_nuls == 0 && odd_nuls * 2 > units {
+            return Some("utf-16le");
+        } else if odd_nuls == 0 && even_nuls * 2 > units {
+            return Some("utf-16be");
+        }
+    }
+
+    if input
+        .iter()
+        .any(|&ch| (ch < 0x20 && !matches!(ch, b'\t' | b'\n' | b'\r' | 0x0c)) || ch == 0x7f)
+    {
+        None
+    } else if input.is_ascii() {
+        Some("us-ascii")
+    } else if std::str::from_utf8(input).is_ok() {
+        Some("utf-8")
+    } else if !input.iter().any(|&ch| (0x80..=0x9f).contains(&ch)) {
+        Some("iso-8859-1")
+    } else if !input
+        .iter()
+        .any(|&ch| matches!(ch, 0x81 | 0x8d | 0x8f | 0x90 | 0x9d))
+    {
+        Some("windows-1252")
+    } else {
+        None
+    }
+}
+
+/// Writes an RFC 2231 extended parameter using the utf-8 charset, splitting
+/// it into numbered continuations (`key*0*`, `key*1*`, ...) when it does not
+/// fit on a single line. Each segment is preceded by either a space or
+/// `fold`, so callers only write the separating ";".
+/// Returns the length of the last line written.
+pub fn rfc2231_encode(
+    key: &str,
+    value: &str,
+    mut output: impl Write,
+    mut bytes_written: usize,
+    fold: &[u8],
+) -> io::Result<usize> {
+    // Leave room for the folding whitespace, the "*NN*=" suffix and ";".
+    let max_len = 76usize.saturating_sub(key.len() + 8).max(12);
+    let mut segments = Vec::new();
+    let mut segment = String::from("utf-8''");
+
+    for &ch in value.as_bytes() {
+        let encoded_len = if is_attr_char(ch) { 1 } else { 3 };
+        if segment.len() + encoded_len > max_len {
+            segments.push(std::mem::take(&mut segment));
+        }
+        if is_attr_char(ch) {
+            segment.push(ch as char);
+        } else {
+            segment.push_str(&format!("%{:02X}", ch));
+        }
+    }
+    segments.push(segment);
+
+    let is_single = segments.len() == 1;
+    for (pos, segment) in segments.iter().enumerate() {
+        let param = if is_single {
+            format!("{}*=", key)
+        } else {
+            format!("{}*{}*=", key, pos)
+        };
+        if pos > 0 {
+            output.write_all(b";")?;
+            bytes_written += 1;
+        }
+        if bytes_written + param.len() + segment.len() + 2 > 76 {
+            output.write_all(fold)?;
+            bytes_written = 1;
+        } else {
+            output.write_all(b" ")?;
+            bytes_written += 1;
+        }
+        output.write_all(param.as_bytes())?;
+        output.write_all(segment.as_bytes())?;
+        bytes_written += param.len() + segment.len();
+    }
+
+    Ok(bytes_written)
+}
+
+fn is_attr_char(ch: u8) -> bool {
+    ch.is_ascii_alphanumeric()
+        || matches!(
+            ch,
+            b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~'
+        )
+}
@@ -10,5 +10,6 @@
  */
 
 pub mod base64;
+pub mod dot_stuffing;
 pub mod encode;
 pub mod quoted_printable;
@@ -11,6 +11,11 @@
 
 use std::io::{self, Write};
 
+/// Encodes the input as quoted-printable: a body when `is_body` is set,
+/// keeping its line breaks, otherwise a binary attachment, or an RFC 2047
+/// "Q" encoded word when `is_inline` is set. Bytes from 0x7F up are always
+/// written as `=XX` escapes, so the output is 7bit even for input that could
+/// have been sent as 8bit. Returns the length of the last line written.
 pub fn quoted_printable_encode(
     input: &[u8],
     mut output: impl Write,
@@ -27,8 +32,10 @@ pub fn quoted_printable_encode(
                     || ((ch == b' ' || ch == b'\t')
                         && (matches!(input.get(pos + 1..), Some([b'\n', ..] | [b'\r', b'\n', ..]))
                             || (pos == input.len() - 1)))
+                    || ((bytes_written == 0 || bytes_written + 1 > 75)
+                        && is_unsafe_line_start(input, pos))
                 {
-                    if bytes_written + 3 > 76 {
+                    if bytes_written + 3 > 75 {
                         output.write_all(b"=\r\n")?;
                         bytes_written = 0;
                     }
@@ -43,7 +50,7 @@ pub fn quoted_printable_encode(
                     bytes_written = 0;
                 } else {
                     prev_ch = ch;
-                    if bytes_written + 1 > 76 {
+                    if bytes_written + 1 > 75 {
                         output.write_all(b"=\r\n")?;
                         bytes_written = 0;
                     }
@@ -57,15 +64,17 @@ pub fn quoted_printable_encode(
                     || ch >= 127
                     || (ch == b'\r' || ch == b'\n')
                     || ((ch == b' ' || ch == b'\t') && (pos == input.len() - 1))
+                    || ((bytes_written == 0 || bytes_written + 1 > 75)
+                        && is_unsafe_line_start(input, pos))
                 {
-                    if bytes_written + 3 > 76 {
+                    if bytes_written + 3 > 75 {
                         output.write_all(b"=\r\n")?;
                         bytes_written = 0;
                     }
                     output.write_all(format!("={:02X}", ch).as_bytes())?;
                     bytes_written += 3;
                 } else {
-                    if bytes_written + 1 > 76 {
+                    if bytes_written + 1 > 75 {
                         output.write_all(b"=\r\n")?;
                         bytes_written = 0;
                     }
@@ -92,6 +101,20 @@ pub fn quoted_printable_encode(
     Ok(bytes_written)
 }
 
+/// Returns true when the line starting at `pos` would be mistaken for an
+/// mbox "From " delimiter or an SMTP end-of-data marker.
+fn is_unsafe_line_start(input: &[u8], pos: usize) -> bool {
+    matches!(
+        input.get(pos..),
+        Some(
+            [b'F', b'r', b'o', b'm', b' ', ..]
+                | [b'.']
+                | [b'.', b'\n', ..]
+                | [b'.', b'\r', b'\n', ..]
+        )
+    )
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -150,12 +173,12 @@ mod tests {
                 " ".repeat(100),
                 concat!(
                     "                                            ",
-                    "                                =\r\n    ",
+                    "                               =\r\n     ",
                     "                   =20"
                 ),
                 concat!(
                     "                                            ",
-                    "                                =\r\n    ",
+                    "                               =\r\n     ",
                     "                   =20"
                 ),
                 concat!(
@@ -190,4 +213,68 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn escape_unsafe_line_starts() {
+        for (input, expected_result_body, expected_result_attachment) in [
+            (
+                "From here\n.\nFrom\n.x\n".to_string(),
+                "=46rom here\r\n=2E\r\nFrom\r\n.x\r\n",
+                "=46rom here=0A.=0AFrom=0A.x=0A",
+            ),
+            (
+                format!("{}From the start", "x".repeat(75)),
+                concat!(
+                    "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx=\r\n",
+                    "=46rom the start"
+                ),
+                concat!(
+                    "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx=\r\n",
+                    "=46rom the start"
+                ),
+            ),
+            (
+                format!("{}.", "x".repeat(75)),
+                concat!(
+                    "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx=\r\n",
+                    "=2E"
+                ),
+                concat!(
+                    "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx=\r\n",
+                    "=2E"
+                ),
+            ),
+        ] {
+            let mut output = Vec::new();
+            super::quoted_printable_encode(input.as_bytes(), &mut output, false, true).unwrap();
+            let output = std::str::from_utf8(&output).unwrap();
+            assert_eq!(output, expected_result_body, "body");
+            for line in output.split("\r\n") {
+                assert!(!line.starts_with("From ") && line != ".", "{:?}", output);
+            }
+
+            let mut output = Vec::new();
+            super::quoted_printable_encode(input.as_bytes(), &mut output, false, false).unwrap();
+            assert_eq!(
+                std::str::from_utf8(&output).unwrap(),
+                expected_result_attachment,
+                "attachment"
+            );
+        }
+    }
+
+    #[test]
+    fn encode_all_high_bit_bytes() {
+        let input = (0x80..=0xFFu8).collect::<Vec<_>>();
+        for (is_inline, is_body) in [(false, true), (false, false), (true, false)] {
+            let mut output = Vec::new();
+            super::quoted_printable_encode(&input, &mut output, is_inline, is_body).unwrap();
+            let output = String::from_utf8(output).unwrap().replace("=\r\n", "");
+            let expected = input
+                .iter()
+                .map(|ch| format!("={:02X}", ch))
+                .collect::<String>();
+            assert_eq!(output, expected, "inline: {}, body: {}", is_inline, is_body);
+        }
+    }
 }
@@ -13,7 +13,7 @@ use std::borrow::Cow;
 
 use crate::encoders::encode::rfc2047_encode;
 
-use super::Header;
+use super::{Header, FOLD};
 
 /// RFC5322 e-mail address
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -57,9 +57,31 @@ impl<'x> Address<'x> {
         })
     }
 
-    /// Create an address list
+    /// Create an RFC5322 group without addresses, written as `name:;`, such as
+    /// the "Undisclosed recipients" group used to hide the recipients of a
+    /// message sent with Bcc.
+    pub fn new_empty_group(name: impl Into<Cow<'x, str>>) -> Self {
+        Address::Group(GroupedAddresses {
+            name: Some(name.into()),
+            addresses: Vec::new(),
+        })
+    }
+
+    /// Create an address list. Nested lists are flattened, so that
+    /// `new_list([new_list([a, b]), c])` is the same as `new_list([a, b, c])`.
     pub fn new_list(items: Vec<Address<'x>>) -> Self {
-        Address::List(items)
+        fn flatten<'x>(items: Vec<Address<'x>>, list: &mut Vec<Address<'x>>) {
+            for item in items {
+                match item {
+                    Address::List(items) => flatten(items, list),
+                    item => list.push(item),
+                }
+            }
+        }
+
+        let mut list = Vec::with_capacity(items.len());
+        flatten(items, &mut list);
+        Address::List(list)
     }
 
     pub fn unwrap_address(&self) -> &EmailAddress<'x> {
@@ -130,55 +152,28 @@ where
 
 impl<'x> Header for Address<'x> {
     fn write_header(
+        &self,
+        output: impl std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with_fold(output, bytes_written, FOLD)
+    }
+
+    fn write_header_with_fold(
         &self,
         mut output: impl std::io::Write,
-        mut bytes_written: usize,
+        bytes_written: usize,
+        fold: &[u8],
     ) -> std::io::Result<usize> {
         match self {
             Address::Address(address) => {
-                address.write_header(&mut output, bytes_written)?;
+                address.write_header_with_fold(&mut output, bytes_written, fold)?;
             }
             Address::Group(group) => {
-                group.write_header(&mut output, bytes_written)?;
+                group.write_header_with_fold(&mut output, bytes_written, fold)?;
             }
             Address::List(list) => {
-                for (pos, address) in list.iter().enumerate() {
-                    if bytes_written
-                        + (match address {
-                            Address::Address(address) => {
-                                address.email.len()
-                                    + address.name.as_ref().map_or(0, |n| n.len() + 3)
-                                    + 2
-                            }
-                            Address::Group(group) => {
-                                group.name.as_ref().map_or(0, |name| name.len() + 2)
-                            }
-                            Address::List(_) => 0,
-                        })
-                        >= 76
-                    {
-                        output.write_all(b"\r\n\t")?;
-                        bytes_written = 1;
-                    }
-
-                    match address {
-                        Address::Address(address) => {
-                            bytes_written += address.write_header(&mut output, bytes_written)?;
-                            if pos < list.len() - 1 {
-                                output.write_all(b", ")?;
-                                bytes_written += 1;
-                            }
-                        }
-                        Address::Group(group) => {
-                            bytes_written += group.write_header(&mut output, bytes_written)?;
-                            if pos < list.len() - 1 {
-                                output.write_all(b"; ")?;
-                                bytes_written += 1;
-                            }
-                        }
-                        Address::List(_) => unreachable!(),
-                    }
-                }
+                write_address_list(list, &mut output, bytes_written, fold)?;
             }
         }
         output.write_all(b"\r\n")?;
@@ -186,16 +181,105 @@ impl<'x> Header for Address<'x> {
     }
 }
 
+impl<'x> EmailAddress<'x> {
+    /// Returns the display name, ignoring empty names.
+    fn display_name(&self) -> Option<&str> {
+        self.name.as_deref().filter(|name| !name.is_empty())
+    }
+
+    /// Returns the length of the address when written on a single line.
+    fn unfolded_len(&self) -> usize {
+        self.display_name().map_or(0, |name| {
+            rfc2047_encode(name, std::io::sink()).unwrap_or(name.len()) + 1
+        }) + self.email.len()
+            + 2
+    }
+}
+
+impl<'x> Address<'x> {
+    /// Returns true when any address has an empty display name.
+    pub(crate) fn has_empty_name(&self) -> bool {
+        match self {
+            Address::Address(address) => address.name.as_ref().is_some_and(|n| n.is_empty()),
+            Address::Group(group) => group.addresses.iter().any(|a| a.has_empty_name()),
+            Address::List(list) => list.iter().any(|a| a.has_empty_name()),
+        }
+    }
+
+    /// Returns the length of the first line this address would write.
+    fn unfolded_len(&self) -> usize {
+        match self {
+            Address::Address(address) => address.unfolded_len(),
+            Address::Group(group) => group.name.as_ref().map_or(0, |name| {
+                rfc2047_encode(name, std::io::sink()).unwrap_or(name.len())
+                    + 1
+                    + usize::from(group.is_empty_group())
+            }),
+            Address::List(_) => 0,
+        }
+    }
+}
+
+/// Writes a list of addresses folding between items, taking the length of
+/// the current line and returning the length of the last line written.
+fn write_address_list(
+    list: &[Address<'_>],
+    output: &mut dyn std::io::Write,
+    mut bytes_written: usize,
+    fold: &[u8],
+) -> std::io::Result<usize> {
+    for (pos, address) in list.iter().enumerate() {
+        if pos > 0 {
+            if matches!(&list[pos - 1], Address::Group(group) if !group.is_empty_group()) {
+                output.write_all(b";")?;
+            } else {
+                output.write_all(b",")?;
+            }
+            bytes_written += 1;
+
+            if bytes_written + address.unfolded_len() + 1 > 76 {
+                output.write_all(fold)?;
+                bytes_written = 1;
+            } else {
+                output.write_all(b" ")?;
+                bytes_written += 1;
+            }
+        }
+
+        bytes_written = match address {
+            Address::Address(address) => {
+                address.write_header_with_fold(&mut *output, bytes_written, fold)?
+            }
+            Address::Group(group) => {
+                group.write_header_with_fold(&mut *output, bytes_written, fold)?
+            }
+            Address::List(list) => write_address_list(list, output, bytes_written, fold)?,
+        };
+    }
+
+    Ok(bytes_written)
+}
+
 impl<'x> Header for EmailAddress<'x> {
+    /// Writes the address, returning the length of the last line written.
     fn write_header(
+        &self,
+        output: impl std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with_fold(output, bytes_written, FOLD)
+    }
+
+    fn write_header_with_fold(
         &self,
         mut output: impl std::io::Write,
         mut bytes_written: usize,
+        fold: &[u8],
     ) -> std::io::Result<usize> {
-        if let Some(name) = &self.name {
+        if let Some(name) = self.display_name() {
             bytes_written += rfc2047_encode(name, &mut output)?;
-            if bytes_written + self.email.len() + 2 >= 76 {
-                output.write_all(b"\r\n\t")?;
+            if bytes_written + self.email.len() + 3 > 76 {
+                output.write_all(fold)?;
                 bytes_written = 1;
             } else {
                 output.write_all(b" ")?;
@@ -211,37 +295,149 @@ impl<'x> Header for EmailAddress<'x> {
     }
 }
 
+impl<'x> GroupedAddresses<'x> {
+    /// Returns true for a named group without addresses, which is written
+    /// with its terminating ";".
+    fn is_empty_group(&self) -> bool {
+        self.name.is_some() && self.addresses.is_empty()
+    }
+}
+
 impl<'x> Header for GroupedAddresses<'x> {
+    /// Writes the group, returning the length of the last line written.
     fn write_header(
+        &self,
+        output: impl std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with_fold(output, bytes_written, FOLD)
+    }
+
+    fn write_header_with_fold(
         &self,
         mut output: impl std::io::Write,
         mut bytes_written: usize,
+        fold: &[u8],
     ) -> std::io::Result<usize> {
         if let Some(name) = &self.name {
-            bytes_written += rfc2047_encode(name, &mut output)? + 2;
-            output.write_all(b": ")?;
+            bytes_written += rfc2047_encode(name, &mut output)? + 1;
+            output.write_all(b":")?;
+            if self.addresses.is_empty() {
+                output.write_all(b";")?;
+                return Ok(bytes_written + 1);
+            }
+
+            if let Some(address) = self.addresses.first() {
+                if bytes_written + address.unfolded_len() + 1 > 76 {
+                    output.write_all(fold)?;
+                    bytes_written = 1;
+                } else {
+                    output.write_all(b" ")?;
+                    bytes_written += 1;
+                }
+            }
         }
 
-        for (pos, address) in self.addresses.iter().enumerate() {
-            let address = address.unwrap_address();
+        write_address_list(&self.addresses, &mut output, bytes_written, fold)
+    }
+}
 
-            if bytes_written
-                + address.email.len()
-                + address.name.as_ref().map_or(0, |n| n.len() + 3)
-                + 2
-                >= 76
-            {
-                output.write_all(b"\r\n\t")?;
-                bytes_written = 1;
+#[cfg(test)]
+mod tests {
+    use mail_parser::MessageParser;
+    use proptest::prelude::*;
+
+    use crate::headers::Header;
+
+    use super::Address;
+
+    proptest! {
+        #[test]
+        fn fold_address_list(
+            recipients in proptest::collection::vec(
+                (
+                    proptest::option::of("[a-zA-Zñé]{1,6}( [a-zA-Zñé]{1,5})?"),
+                    "[a-z]{1,10}@[a-z]{1,10}\\.com",
+                ),
+                1..30,
+            )
+        ) {
+            let mut output = b"To: ".to_vec();
+            Address::new_list(
+                recipients
+                    .iter()
+                    .map(|(name, email)| Address::new_address(name.as_deref(), email.as_str()))
+                    .collect(),
+            )
+            .write_header(&mut output, 4)
+            .unwrap();
+
+            let header = std::str::from_utf8(&output).unwrap();
+            for line in header.split("\r\n") {
+                prop_assert!(line.len() <= 78, "{:?}", header);
             }
 
-            bytes_written += address.write_header(&mut output, bytes_written)?;
-            if pos < self.addresses.len() - 1 {
-                output.write_all(b", ")?;
-                bytes_written += 2;
+            output.extend_from_slice(b"\r\n");
+            let message = MessageParser::new().parse(&output).unwrap();
+            let addresses = message.to().unwrap().as_list().unwrap();
+            prop_assert_eq!(addresses.len(), recipients.len());
+            for (address, (name, email)) in addresses.iter().zip(recipients.iter()) {
+                prop_assert_eq!(address.address(), Some(email.as_str()));
+                prop_assert_eq!(address.name(), name.as_deref());
             }
         }
+    }
+
+    #[test]
+    fn flatten_nested_lists() {
+        let nested = Address::new_list(vec![
+            Address::new_list(vec![
+                "a@doe.com".into(),
+                Address::new_list(vec![("B", "b@doe.com").into()]),
+            ]),
+            ("Group", vec!["c@doe.com"]).into(),
+            "d@doe.com".into(),
+        ]);
+        let flat = Address::new_list(vec![
+            "a@doe.com".into(),
+            ("B", "b@doe.com").into(),
+            ("Group", vec!["c@doe.com"]).into(),
+            "d@doe.com".into(),
+        ]);
+        assert_eq!(nested, flat);
+
+        let write = |address: &Address| {
+            let mut output = Vec::new();
+            address.write_header(&mut output, 4).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(write(&nested), write(&flat));
+    }
+
+    #[test]
+    fn write_empty_group() {
+        let write = |address: Address| {
+            let mut output = b"To: ".to_vec();
+            address.write_header(&mut output, 4).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(
+            write(Address::new_empty_group("Undisclosed recipients")),
+            "To: \"Undisclosed recipients\":;\r\n"
+        );
+        assert_eq!(
+            write(Address::new_list(vec![
+                Address::new_empty_group("Hidden"),
+                "a@doe.com".into(),
+            ])),
+            "To: \"Hidden\":;, <a@doe.com>\r\n"
+        );
 
-        Ok(bytes_written)
+        let mut output = write(Address::new_empty_group("Undisclosed recipients")).into_bytes();
+        output.extend_from_slice(b"\r\n");
+        let message = MessageParser::new().parse(&output).unwrap();
+        let group = &message.to().unwrap().as_group().unwrap()[0];
+        assert_eq!(group.name.as_deref(), Some("Undisclosed recipients"));
+        assert!(group.addresses.is_empty());
     }
 }
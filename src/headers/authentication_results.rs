@@ -0,0 +1,186 @@
+/*
+ * Copyright Stalwart Labs Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::borrow::Cow;
+
+use super::{Header, FOLD};
+
+/// RFC8601 Authentication-Results header
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AuthenticationResults<'x> {
+    pub authserv_id: Cow<'x, str>,
+    pub results: Vec<AuthenticationResult<'x>>,
+}
+
+/// Result of a single authentication method, such as `spf=pass`, followed
+/// by its properties, such as `smtp.mailfrom=example.org`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AuthenticationResult<'x> {
+    pub method: Cow<'x, str>,
+    pub result: Cow<'x, str>,
+    pub properties: Vec<(Cow<'x, str>, Cow<'x, str>)>,
+}
+
+impl<'x> AuthenticationResults<'x> {
+    /// Create a new Authentication-Results header for the given
+    /// authentication service identifier, usually the host name of the
+    /// server that performed the checks.
+    pub fn new(authserv_id: impl Into<Cow<'x, str>>) -> Self {
+        Self {
+            authserv_id: authserv_id.into(),
+            results: Vec::new(),
+        }
+    }
+
+    /// Add the result of an authentication method.
+    pub fn method(
+        mut self,
+        method: impl Into<Cow<'x, str>>,
+        result: impl Into<Cow<'x, str>>,
+    ) -> Self {
+        self.results.push(AuthenticationResult {
+            method: method.into(),
+            result: result.into(),
+            properties: Vec::new(),
+        });
+        self
+    }
+
+    /// Add a property, such as `header.d`, to the last method added.
+    pub fn property(
+        mut self,
+        property: impl Into<Cow<'x, str>>,
+        value: impl Into<Cow<'x, str>>,
+    ) -> Self {
+        if let Some(result) = self.results.last_mut() {
+            result.properties.push((property.into(), value.into()));
+        }
+        self
+    }
+
+    /// Add an SPF result for the given MAIL FROM domain or address.
+    pub fn spf(self, result: impl Into<Cow<'x, str>>, mail_from: impl Into<Cow<'x, str>>) -> Self {
+        self.method("spf", result)
+            .property("smtp.mailfrom", mail_from)
+    }
+
+    /// Add a DKIM result for the given signing domain.
+    pub fn dkim(self, result: impl Into<Cow<'x, str>>, domain: impl Into<Cow<'x, str>>) -> Self {
+        self.method("dkim", result).property("header.d", domain)
+    }
+
+    /// Add a DMARC result for the domain of the From header.
+    pub fn dmarc(
+        self,
+        result: impl Into<Cow<'x, str>>,
+        from_domain: impl Into<Cow<'x, str>>,
+    ) -> Self {
+        self.method("dmarc", result)
+            .property("header.from", from_domain)
+    }
+}
+
+impl<'x> Header for AuthenticationResults<'x> {
+    fn write_header(
+        &self,
+        output: impl std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with_fold(output, bytes_written, FOLD)
+    }
+
+    fn write_header_with_fold(
+        &self,
+        mut output: impl std::io::Write,
+        mut bytes_written: usize,
+        fold: &[u8],
+    ) -> std::io::Result<usize> {
+        output.write_all(self.authserv_id.as_bytes())?;
+        bytes_written += self.authserv_id.len();
+
+        if self.results.is_empty() {
+            output.write_all(b"; none\r\n")?;
+            return Ok(0);
+        }
+
+        for result in &self.results {
+            let tokens = std::iter::once(format!("{}={}", result.method, result.result)).chain(
+                result
+                    .properties
+                    .iter()
+                    .map(|(property, value)| format!("{}={}", property, value)),
+            );
+            for (pos, token) in tokens.enumerate() {
+                if pos == 0 {
+                    output.write_all(b";")?;
+                    bytes_written += 1;
+                }
+                if bytes_written + token.len() + 2 > 76 {
+                    output.write_all(fold)?;
+                    bytes_written = 1;
+                } else {
+                    output.write_all(b" ")?;
+                    bytes_written += 1;
+                }
+                output.write_all(token.as_bytes())?;
+                bytes_written += token.len();
+            }
+        }
+        output.write_all(b"\r\n")?;
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headers::Header;
+
+    use super::AuthenticationResults;
+
+    #[test]
+    fn write_authentication_results() {
+        for (header, expected) in [
+            (
+                AuthenticationResults::new("mx.example.com")
+                    .spf("pass", "example.net")
+                    .dkim("pass", "example.net")
+                    .property("header.s", "selector1"),
+                concat!(
+                    "Authentication-Results: mx.example.com; spf=pass smtp.mailfrom=example.net;\r\n",
+                    " dkim=pass header.d=example.net header.s=selector1\r\n"
+                ),
+            ),
+            (
+                AuthenticationResults::new("mx.example.com")
+                    .spf("pass", "example.net")
+                    .dkim("pass", "example.net")
+                    .dmarc("pass", "example.net"),
+                concat!(
+                    "Authentication-Results: mx.example.com; spf=pass smtp.mailfrom=example.net;\r\n",
+                    " dkim=pass header.d=example.net; dmarc=pass header.from=example.net\r\n"
+                ),
+            ),
+            (
+                AuthenticationResults::new("mx.example.com"),
+                "Authentication-Results: mx.example.com; none\r\n",
+            ),
+        ] {
+            let mut output = b"Authentication-Results: ".to_vec();
+            header.write_header(&mut output, 24).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(output, expected);
+            for line in output.split("\r\n") {
+                assert!(line.len() <= 78, "{}", output);
+            }
+        }
+    }
+}
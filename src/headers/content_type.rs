@@ -11,9 +11,9 @@
 
 use std::borrow::Cow;
 
-use crate::encoders::encode::rfc2047_encode;
+use crate::encoders::encode::{rfc2047_encode, rfc2231_encode};
 
-use super::Header;
+use super::{cow_memory_usage, vec_memory_usage, Header, FOLD};
 
 /// MIME Content-Type or Content-Disposition header
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -23,62 +23,464 @@ pub struct ContentType<'x> {
 }
 
 impl<'x> ContentType<'x> {
-    /// Create a new Content-Type or Content-Disposition header
+    /// Create a new Content-Type or Content-Disposition header. The type is
+    /// converted to lowercase, as MIME type names are case-insensitive.
     pub fn new(c_type: impl Into<Cow<'x, str>>) -> Self {
         Self {
-            c_type: c_type.into(),
+            c_type: to_lowercase(c_type.into()),
             attributes: Vec::new(),
         }
     }
 
-    /// Set a Content-Type / Content-Disposition attribute
+    /// Set a Content-Type / Content-Disposition attribute. Values are written
+    /// as quoted-strings, except for keys ending in `*` (such as `filename*`)
+    /// which are written as RFC 2231 extended parameters, split into
+    /// continuations when needed, or as unquoted tokens when their value
+    /// needs no encoding. Keys are converted to lowercase while values are
+    /// kept as is.
     pub fn attribute(
         mut self,
         key: impl Into<Cow<'x, str>>,
         value: impl Into<Cow<'x, str>>,
     ) -> Self {
-        self.attributes.push((key.into(), value.into()));
+        self.attributes
+            .push((to_lowercase(key.into()), value.into()));
         self
     }
 
+    /// Set a Content-Type / Content-Disposition attribute, choosing how the
+    /// value is written: as an unquoted token when it only contains ASCII
+    /// characters other than RFC 2045 tspecials, as a quoted-string when it
+    /// contains spaces or tspecials, and as an RFC 2231 extended parameter
+    /// when it contains non-ASCII characters.
+    ///
+    /// Tokens and non-ASCII values are stored under the extended key (such
+    /// as `filename*`), which is written unquoted when the value is a token.
+    pub fn attribute_auto(mut self, key: impl Into<Cow<'x, str>>, value: &str) -> Self {
+        let key = to_lowercase(key.into());
+        let key = key.strip_suffix('*').unwrap_or(&key);
+        let key = if !value.is_ascii() || is_token(value) {
+            format!("{}*", key)
+        } else {
+            key.to_string()
+        };
+        self.attributes.push((key.into(), value.to_string().into()));
+        self
+    }
+
+    /// Parse a raw Content-Type or Content-Disposition value such as
+    /// `multipart/related; type="text/html"` into its structured form.
+    pub fn parse(value: &str) -> ContentType<'static> {
+        let mut params = split_params(value).into_iter();
+        let mut content_type =
+            ContentType::new(params.next().unwrap_or_default().trim().to_string());
+
+        for param in params {
+            if let Some((key, value)) = param.split_once('=') {
+                let value = value.trim();
+                let value = if let Some(value) =
+                    value.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+                {
+                    let mut unquoted = String::with_capacity(value.len());
+                    let mut chars = value.chars();
+                    while let Some(ch) = chars.next() {
+                        if ch == '\\' {
+                            if let Some(ch) = chars.next() {
+                                unquoted.push(ch);
+                            }
+                        } else {
+                            unquoted.push(ch);
+                        }
+                    }
+                    unquoted
+                } else {
+                    value.to_string()
+                };
+                content_type
+                    .attributes
+                    .push((key.trim().to_string().into(), value.into()));
+            }
+        }
+
+        content_type
+    }
+
+    /// Removes the attributes with the given name, matched case-insensitively.
+    pub(crate) fn remove_attribute(&mut self, key: &str) {
+        let mut pos = 0;
+        while pos < self.attributes.len() {
+            if self.attributes[pos].0.eq_ignore_ascii_case(key) {
+                self.attributes.remove(pos);
+            } else {
+                pos += 1;
+            }
+        }
+    }
+
+    /// Returns an estimate of the heap bytes owned by the header.
+    pub(crate) fn memory_usage(&self) -> usize {
+        cow_memory_usage(&self.c_type)
+            + vec_memory_usage(&self.attributes)
+            + self
+                .attributes
+                .iter()
+                .map(|(key, value)| cow_memory_usage(key) + cow_memory_usage(value))
+                .sum::<usize>()
+    }
+
+    /// Returns the value of an attribute, matching its name case-insensitively
+    /// as required by RFC 2045, or else the value of its RFC 2231 extended
+    /// form (such as `filename*` for `filename`).
+    pub fn attribute_case_insensitive(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .or_else(|| {
+                self.attributes.iter().find(|(k, _)| {
+                    k.strip_suffix('*')
+                        .is_some_and(|k| k.eq_ignore_ascii_case(key))
+                })
+            })
+            .map(|(_, v)| v.as_ref())
+    }
+
     /// Returns true when the part is text/*
     pub fn is_text(&self) -> bool {
-        self.c_type.starts_with("text/")
+        has_prefix_ignore_case(&self.c_type, "text/")
     }
 
     /// Returns true when the part is an attachment
     pub fn is_attachment(&self) -> bool {
-        self.c_type == "attachment"
+        self.c_type.eq_ignore_ascii_case("attachment")
+    }
+
+    /// Returns true when the disposition is inline
+    pub fn is_inline(&self) -> bool {
+        self.c_type.eq_ignore_ascii_case("inline")
+    }
+
+    /// Returns true when the part is image/*
+    pub fn is_inline_image(&self) -> bool {
+        has_prefix_ignore_case(&self.c_type, "image/")
+    }
+}
+
+fn to_lowercase(value: Cow<'_, str>) -> Cow<'_, str> {
+    if value.bytes().any(|ch| ch.is_ascii_uppercase()) {
+        value.to_ascii_lowercase().into()
+    } else {
+        value
     }
 }
 
+/// Returns true if the value is a non-empty RFC 2045 token, which can be
+/// written without quotes.
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|ch| ch.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?=".contains(&ch))
+}
+
+fn has_prefix_ignore_case(value: &str, prefix: &str) -> bool {
+    value
+        .get(..prefix.len())
+        .is_some_and(|value| value.eq_ignore_ascii_case(prefix))
+}
+
+/// Splits a header value on semicolons that are not inside a quoted string.
+fn split_params(value: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut in_quote = false;
+    let mut is_escaped = false;
+    let mut start = 0;
+
+    for (pos, ch) in value.char_indices() {
+        match ch {
+            '\\' if in_quote && !is_escaped => {
+                is_escaped = true;
+                continue;
+            }
+            '"' if !is_escaped => in_quote = !in_quote,
+            ';' if !in_quote => {
+                params.push(&value[start..pos]);
+                start = pos + 1;
+            }
+            _ => (),
+        }
+        is_escaped = false;
+    }
+    params.push(&value[start..]);
+    params
+}
+
 impl<'x> Header for ContentType<'x> {
     fn write_header(
+        &self,
+        output: impl std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with_fold(output, bytes_written, FOLD)
+    }
+
+    fn write_header_with_fold(
         &self,
         mut output: impl std::io::Write,
         mut bytes_written: usize,
+        fold: &[u8],
     ) -> std::io::Result<usize> {
         output.write_all(self.c_type.as_bytes())?;
         bytes_written += self.c_type.len();
-        if !self.attributes.is_empty() {
-            output.write_all(b"; ")?;
-            bytes_written += 2;
-            for (pos, (key, value)) in self.attributes.iter().enumerate() {
-                if bytes_written + key.len() + value.len() + 3 >= 76 {
-                    output.write_all(b"\r\n\t")?;
-                    bytes_written = 1;
-                }
+        for (key, value) in &self.attributes {
+            output.write_all(b";")?;
+            bytes_written += 1;
 
-                output.write_all(key.as_bytes())?;
-                output.write_all(b"=")?;
-                bytes_written += rfc2047_encode(value, &mut output)? + key.len() + 1;
-                if pos < self.attributes.len() - 1 {
-                    output.write_all(b"; ")?;
-                    bytes_written += 2;
+            // Extended parameters are only encoded when their value is not
+            // a token, otherwise they are written as a plain token.
+            let extended_key = key.strip_suffix('*');
+            let key = match extended_key {
+                Some(key) if !is_token(value) => {
+                    bytes_written = rfc2231_encode(key, value, &mut output, bytes_written, fold)?;
+                    continue;
                 }
+                Some(key) => key,
+                None => key.as_ref(),
+            };
+
+            // Only fold between parameters, accounting for the leading
+            // space and the ";" that may follow.
+            let mut encoded_value = Vec::with_capacity(value.len() + 2);
+            if extended_key.is_some() {
+                encoded_value.extend_from_slice(value.as_bytes());
+            } else {
+                rfc2047_encode(value, &mut encoded_value)?;
             }
+            let param_len = key.len() + 1 + encoded_value.len();
+            if bytes_written + param_len + 2 > 76 {
+                output.write_all(fold)?;
+                bytes_written = 1;
+            } else {
+                output.write_all(b" ")?;
+                bytes_written += 1;
+            }
+
+            output.write_all(key.as_bytes())?;
+            output.write_all(b"=")?;
+            output.write_all(&encoded_value)?;
+            bytes_written += param_len;
         }
         output.write_all(b"\r\n")?;
         Ok(0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use mail_parser::{MessageParser, MimeHeaders};
+
+    use crate::headers::Header;
+
+    use super::ContentType;
+
+    #[test]
+    fn write_rfc2231_continuations() {
+        let filename = "Ředitelství společnosti — výroční zpráva za rok 2022 (finální verze).pdf";
+        let mut output = b"Content-Disposition: ".to_vec();
+        ContentType::new("attachment")
+            .attribute("filename*", filename)
+            .write_header(&mut output, 21)
+            .unwrap();
+        let header = String::from_utf8(output.clone()).unwrap();
+
+        let segments = header
+            .split(';')
+            .skip(1)
+            .map(|param| param.trim())
+            .collect::<Vec<_>>();
+        assert!(segments.len() > 2, "{}", header);
+        for (pos, segment) in segments.iter().enumerate() {
+            let (key, value) = segment.split_once('=').unwrap();
+            assert_eq!(key, format!("filename*{}*", pos));
+            assert_eq!(value.starts_with("utf-8''"), pos == 0, "{}", header);
+
+            let value = value.trim_start_matches("utf-8''").as_bytes();
+            for (pos, &ch) in value.iter().enumerate() {
+                if ch == b'%' {
+                    assert!(value.len() > pos + 2, "{}", header);
+                    assert!(value[pos + 1..pos + 3].iter().all(u8::is_ascii_hexdigit));
+                }
+            }
+        }
+        for line in header.split("\r\n") {
+            assert!(line.len() <= 76, "{}", header);
+        }
+
+        output.extend_from_slice(b"Content-Type: application/pdf\r\n\r\n");
+        let message = MessageParser::new().parse(&output).unwrap();
+        assert_eq!(message.attachment_name(), Some(filename));
+    }
+
+    #[test]
+    fn parse_content_type() {
+        assert_eq!(
+            ContentType::parse(
+                "multipart/related; type=\"text/html\"; start=<a@b>;boundary=\"a;\\\"b\""
+            ),
+            ContentType::new("multipart/related")
+                .attribute("type", "text/html")
+                .attribute("start", "<a@b>")
+                .attribute("boundary", "a;\"b")
+        );
+        assert_eq!(
+            ContentType::parse("text/plain"),
+            ContentType::new("text/plain")
+        );
+    }
+
+    #[test]
+    fn write_rfc2231_single_segment() {
+        let mut output = Vec::new();
+        ContentType::new("attachment")
+            .attribute("filename*", "ñandú.txt")
+            .write_header(&mut output, 21)
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            "attachment; filename*=utf-8''%C3%B1and%C3%BA.txt\r\n"
+        );
+    }
+
+    #[test]
+    fn fold_between_parameters() {
+        let content_type = ContentType::new("multipart/report")
+            .attribute("report-type", "disposition-notification")
+            .attribute("boundary", "0_4a2e8c1f9b7d3e56_0_4a2e8c1f9b7d3e56")
+            .attribute("x-first-long-parameter", "a".repeat(30))
+            .attribute("x-second-long-parameter", "b".repeat(40))
+            .attribute("x-third", "c".repeat(20));
+        let mut output = Vec::new();
+        content_type.write_header(&mut output, 0).unwrap();
+        let header = String::from_utf8(output).unwrap();
+
+        for (pos, line) in header.trim_end().split("\r\n").enumerate() {
+            assert!(
+                line.len() + if pos == 0 { 14 } else { 0 } <= 76,
+                "{}",
+                header
+            );
+            assert!(!line.ends_with(' '), "{}", header);
+            if pos > 0 {
+                let param = line.strip_prefix(' ').unwrap();
+                assert!(param.starts_with("x-") || param.starts_with("boundary="));
+            }
+        }
+        assert!(header.contains("\r\n "), "{}", header);
+        assert_eq!(
+            ContentType::parse(&header.trim_end().replace("\r\n ", " ")),
+            content_type
+        );
+    }
+
+    #[test]
+    fn case_insensitive_attributes() {
+        let content_type = ContentType::new("TEXT/Plain").attribute("CharSet", "UTF-8");
+        assert!(content_type.is_text());
+        assert_eq!(
+            content_type.attribute_case_insensitive("charset"),
+            Some("UTF-8")
+        );
+        assert_eq!(content_type.attribute_case_insensitive("boundary"), None);
+        assert!(ContentType::new("Attachment").is_attachment());
+        assert!(ContentType::new("INLINE").is_inline());
+        assert!(ContentType::new("Image/PNG").is_inline_image());
+    }
+
+    #[test]
+    fn normalize_case() {
+        let write = |content_type: ContentType| {
+            let mut output = Vec::new();
+            content_type.write_header(&mut output, 0).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(
+            write(ContentType::new("TEXT/PLAIN").attribute("CharSet", "UTF-8")),
+            write(ContentType::new("text/plain").attribute("charset", "UTF-8"))
+        );
+        assert_eq!(
+            write(ContentType::new("Text/HTML").attribute("Name", "Report.HTML")),
+            "text/html; name=\"Report.HTML\"\r\n"
+        );
+        assert_eq!(
+            ContentType::new("Multipart/Mixed").c_type,
+            "multipart/mixed"
+        );
+    }
+
+    #[test]
+    fn attribute_auto_encoding() {
+        let content_type = ContentType::new("attachment")
+            .attribute_auto("Filename", "report-2024.pdf")
+            .attribute_auto("x-spaces", "annual report.pdf")
+            .attribute_auto("x-specials", "a;b=c")
+            .attribute_auto("x-empty", "")
+            .attribute_auto("x-utf8", "ñandú.txt");
+        let mut output = Vec::new();
+        content_type.write_header(&mut output, 21).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            concat!(
+                "attachment; filename=report-2024.pdf;\r\n",
+                " x-spaces=\"annual report.pdf\"; x-specials=\"a;b=c\"; x-empty=\"\";\r\n",
+                " x-utf8*=utf-8''%C3%B1and%C3%BA.txt\r\n"
+            )
+        );
+
+        let mut output = b"Content-Type: application/pdf\r\nContent-Disposition: ".to_vec();
+        ContentType::new("attachment")
+            .attribute_auto("filename", "Ředitelství.pdf")
+            .write_header(&mut output, 21)
+            .unwrap();
+        output.extend_from_slice(b"\r\n");
+        let message = MessageParser::new().parse(&output).unwrap();
+        assert_eq!(message.attachment_name(), Some("Ředitelství.pdf"));
+    }
+
+    #[test]
+    fn attribute_auto_quoting_is_per_attribute() {
+        let content_type = ContentType::new("text/plain")
+            .attribute("a", "b")
+            .attribute_auto("charset", "utf-8")
+            .attribute("charset", "us-ascii")
+            .attribute_auto("delsp", "yes");
+        assert_eq!(
+            content_type.attributes,
+            [
+                ("a".into(), "b".into()),
+                ("charset*".into(), "utf-8".into()),
+                ("charset".into(), "us-ascii".into()),
+                ("delsp*".into(), "yes".into()),
+            ]
+        );
+        assert_eq!(
+            content_type,
+            ContentType {
+                c_type: "text/plain".into(),
+                attributes: content_type.attributes.clone(),
+            }
+        );
+        assert_eq!(
+            content_type.attribute_case_insensitive("charset"),
+            Some("us-ascii")
+        );
+        assert_eq!(
+            content_type.attribute_case_insensitive("Delsp"),
+            Some("yes")
+        );
+        let mut output = Vec::new();
+        content_type.write_header(&mut output, 0).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            "text/plain; a=\"b\"; charset=utf-8; charset=\"us-ascii\"; delsp=yes\r\n"
+        );
+    }
+}
@@ -14,7 +14,6 @@ use std::io::{self, Write};
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::SystemTime;
 
-
 pub static DOW: &[&str] = &["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
 pub static MONTH: &[&str] = &[
     "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
@@ -36,9 +35,7 @@ impl Date {
 
     #[cfg(target_arch = "wasm32")]
     pub fn now() -> Self {
-        Self {
-            date: 0,
-        }
+        Self { date: 0 }
     }
 
     #[cfg(not(target_arch = "wasm32"))]
@@ -92,6 +89,16 @@ impl From<i64> for Date {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl From<SystemTime> for Date {
+    fn from(datetime: SystemTime) -> Self {
+        Date::new(match datetime.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(err) => -(err.duration().as_secs() as i64),
+        })
+    }
+}
+
 impl From<u64> for Date {
     fn from(datetime: u64) -> Self {
         Date::new(datetime as i64)
@@ -13,7 +13,7 @@ use std::borrow::Cow;
 
 use crate::mime::make_boundary;
 
-use super::Header;
+use super::{Header, FOLD};
 
 /// RFC5322 Message ID header
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -93,14 +93,23 @@ pub fn generate_message_id_header(
 
 impl<'x> Header for MessageId<'x> {
     fn write_header(
+        &self,
+        output: impl std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with_fold(output, bytes_written, FOLD)
+    }
+
+    fn write_header_with_fold(
         &self,
         mut output: impl std::io::Write,
         mut bytes_written: usize,
+        fold: &[u8],
     ) -> std::io::Result<usize> {
         for (pos, id) in self.id.iter().enumerate() {
             if pos > 0 {
                 if bytes_written + id.len() + 2 >= 76 {
-                    output.write_all(b"\r\n\t")?;
+                    output.write_all(fold)?;
                     bytes_written = 1;
                 } else {
                     output.write_all(b" ")?;
@@ -10,22 +10,52 @@
  */
 
 pub mod address;
+pub mod authentication_results;
 pub mod content_type;
 pub mod date;
 pub mod message_id;
 pub mod raw;
+pub mod received;
 pub mod text;
 pub mod url;
 
-use std::io::{self, Write};
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
+
+use crate::Error;
 
 use self::{
-    address::Address, content_type::ContentType, date::Date, message_id::MessageId, raw::Raw,
-    text::Text, url::URL,
+    address::Address, authentication_results::AuthenticationResults, content_type::ContentType,
+    date::Date, message_id::MessageId, raw::Raw, received::Received, text::Text, url::URL,
 };
 
+/// Folding whitespace written by [`Header`] implementations when a header
+/// line is too long.
+pub const FOLD: &[u8] = b"\r\n ";
+
+/// Folding whitespace used instead of [`FOLD`] by messages written with
+/// [`WriteOptions::fold_with_tab`].
+///
+/// [`WriteOptions::fold_with_tab`]: crate::mime::WriteOptions::fold_with_tab
+pub const FOLD_WITH_TAB: &[u8] = b"\r\n\t";
+
 pub trait Header {
     fn write_header(&self, output: impl Write, bytes_written: usize) -> io::Result<usize>;
+
+    /// Write the header folding long lines with `fold`, either [`FOLD`] or
+    /// [`FOLD_WITH_TAB`]. Implementations that do not override this method
+    /// always fold with [`FOLD`].
+    fn write_header_with_fold(
+        &self,
+        output: impl Write,
+        bytes_written: usize,
+        fold: &[u8],
+    ) -> io::Result<usize> {
+        let _ = fold;
+        self.write_header(output, bytes_written)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -37,6 +67,8 @@ pub enum HeaderType<'x> {
     Text(Text<'x>),
     URL(URL<'x>),
     ContentType(ContentType<'x>),
+    Received(Received<'x>),
+    AuthenticationResults(AuthenticationResults<'x>),
 }
 
 impl<'x> From<Address<'x>> for HeaderType<'x> {
@@ -72,6 +104,18 @@ impl<'x> From<Text<'x>> for HeaderType<'x> {
     }
 }
 
+impl<'x> From<Received<'x>> for HeaderType<'x> {
+    fn from(value: Received<'x>) -> Self {
+        HeaderType::Received(value)
+    }
+}
+
+impl<'x> From<AuthenticationResults<'x>> for HeaderType<'x> {
+    fn from(value: AuthenticationResults<'x>) -> Self {
+        HeaderType::AuthenticationResults(value)
+    }
+}
+
 impl<'x> From<URL<'x>> for HeaderType<'x> {
     fn from(value: URL<'x>) -> Self {
         HeaderType::URL(value)
@@ -80,23 +124,252 @@ impl<'x> From<URL<'x>> for HeaderType<'x> {
 
 impl<'x> Header for HeaderType<'x> {
     fn write_header(&self, output: impl Write, bytes_written: usize) -> io::Result<usize> {
+        self.write_header_with_fold(output, bytes_written, FOLD)
+    }
+
+    fn write_header_with_fold(
+        &self,
+        output: impl Write,
+        bytes_written: usize,
+        fold: &[u8],
+    ) -> io::Result<usize> {
         match self {
-            HeaderType::Address(value) => value.write_header(output, bytes_written),
-            HeaderType::Date(value) => value.write_header(output, bytes_written),
-            HeaderType::MessageId(value) => value.write_header(output, bytes_written),
-            HeaderType::Raw(value) => value.write_header(output, bytes_written),
-            HeaderType::Text(value) => value.write_header(output, bytes_written),
-            HeaderType::URL(value) => value.write_header(output, bytes_written),
-            HeaderType::ContentType(value) => value.write_header(output, bytes_written),
+            HeaderType::Address(value) => value.write_header_with_fold(output, bytes_written, fold),
+            HeaderType::Date(value) => value.write_header_with_fold(output, bytes_written, fold),
+            HeaderType::MessageId(value) => {
+                value.write_header_with_fold(output, bytes_written, fold)
+            }
+            HeaderType::Raw(value) => value.write_header_with_fold(output, bytes_written, fold),
+            HeaderType::Text(value) => value.write_header_with_fold(output, bytes_written, fold),
+            HeaderType::URL(value) => value.write_header_with_fold(output, bytes_written, fold),
+            HeaderType::ContentType(value) => {
+                value.write_header_with_fold(output, bytes_written, fold)
+            }
+            HeaderType::Received(value) => {
+                value.write_header_with_fold(output, bytes_written, fold)
+            }
+            HeaderType::AuthenticationResults(value) => {
+                value.write_header_with_fold(output, bytes_written, fold)
+            }
         }
     }
 }
 
 impl<'x> HeaderType<'x> {
-    pub fn as_content_type(&self) -> Option<&ContentType> {
+    pub fn as_content_type(&self) -> Option<&ContentType<'x>> {
         match self {
             HeaderType::ContentType(value) => Some(value),
             _ => None,
         }
     }
+
+    /// Returns an estimate of the heap bytes owned by the header value.
+    pub(crate) fn memory_usage(&self) -> usize {
+        fn address_usage(address: &Address<'_>) -> usize {
+            match address {
+                Address::Address(address) => {
+                    address.name.as_ref().map_or(0, cow_memory_usage)
+                        + cow_memory_usage(&address.email)
+                }
+                Address::Group(group) => {
+                    group.name.as_ref().map_or(0, cow_memory_usage)
+                        + vec_memory_usage(&group.addresses)
+                        + group.addresses.iter().map(address_usage).sum::<usize>()
+                }
+                Address::List(list) => {
+                    vec_memory_usage(list) + list.iter().map(address_usage).sum::<usize>()
+                }
+            }
+        }
+        fn list_usage(list: &Vec<Cow<'_, str>>) -> usize {
+            vec_memory_usage(list) + list.iter().map(cow_memory_usage).sum::<usize>()
+        }
+
+        match self {
+            HeaderType::Address(value) => address_usage(value),
+            HeaderType::Date(_) => 0,
+            HeaderType::MessageId(value) => list_usage(&value.id),
+            HeaderType::Raw(value) => cow_memory_usage(&value.raw),
+            HeaderType::Text(value) => cow_memory_usage(&value.text),
+            HeaderType::URL(value) => list_usage(&value.url),
+            HeaderType::ContentType(value) => value.memory_usage(),
+            HeaderType::Received(value) => {
+                [&value.from, &value.by, &value.with, &value.id, &value.for_]
+                    .into_iter()
+                    .flatten()
+                    .map(cow_memory_usage)
+                    .sum()
+            }
+            HeaderType::AuthenticationResults(value) => {
+                cow_memory_usage(&value.authserv_id)
+                    + vec_memory_usage(&value.results)
+                    + value
+                        .results
+                        .iter()
+                        .map(|result| {
+                            cow_memory_usage(&result.method)
+                                + cow_memory_usage(&result.result)
+                                + vec_memory_usage(&result.properties)
+                                + result
+                                    .properties
+                                    .iter()
+                                    .map(|(key, value)| {
+                                        cow_memory_usage(key) + cow_memory_usage(value)
+                                    })
+                                    .sum::<usize>()
+                        })
+                        .sum::<usize>()
+            }
+        }
+    }
+}
+
+/// Returns the heap bytes owned by a string, zero when it is borrowed.
+#[allow(clippy::ptr_arg)]
+pub(crate) fn cow_memory_usage(value: &Cow<'_, str>) -> usize {
+    match value {
+        Cow::Borrowed(_) => 0,
+        Cow::Owned(value) => value.capacity(),
+    }
+}
+
+/// Returns the heap bytes used by the elements of a vector, not including
+/// the heap bytes they own.
+pub(crate) fn vec_memory_usage<T>(value: &Vec<T>) -> usize {
+    value.capacity() * std::mem::size_of::<T>()
+}
+
+/// Prepend headers to an already serialized message, such as the
+/// DKIM-Signature or trace headers that can only be generated once the
+/// message is known. The body is copied as-is and not re-serialized.
+pub fn prepend_headers<'x, T, U>(
+    message: &[u8],
+    headers: impl IntoIterator<Item = (T, U)>,
+) -> io::Result<Vec<u8>>
+where
+    T: Into<Cow<'x, str>>,
+    U: Into<HeaderType<'x>>,
+{
+    let mut output = Vec::with_capacity(message.len() + 1024);
+    for (header_name, header_value) in headers {
+        let header_name = header_name.into();
+        write_header_name(&header_name, &mut output)?;
+        header_value
+            .into()
+            .write_header(&mut output, header_name.len() + 2)?;
+    }
+    output.extend_from_slice(message);
+    Ok(output)
+}
+
+/// Writes a complete header line, folded with the same rules used for
+/// [`Raw`] headers and unencoded [`Text`] headers, so that headers generated
+/// outside this crate (such as ARC or DKIM signatures) match its output
+/// byte for byte.
+///
+/// The name is followed by ": " and the value is copied unchanged, except
+/// that a [`FOLD`] is inserted before a whitespace character once the
+/// current line is at least `max_line` bytes long, unless it is the last
+/// character of the value. The fold is written in addition to the
+/// whitespace character, so removing each CRLF and the space following it
+/// restores the original value. Lines are never split elsewhere, so words
+/// longer than `max_line` are written on a line of their own. The header
+/// ends with a CRLF. Headers written by this crate use a `max_line` of 76.
+///
+/// Returns an error if the name is not a valid header name or if the value
+/// contains CR, LF or NUL characters, in which case nothing is written.
+pub fn write_folded_header(
+    name: &str,
+    value: &str,
+    mut output: impl Write,
+    max_line: usize,
+) -> io::Result<()> {
+    if value.contains(['\r', '\n', '\0']) {
+        return Err(Error::InvalidHeaderValue.into());
+    }
+    write_header_name(name, &mut output)?;
+    write_folded(value, output, name.len() + 2, max_line, FOLD)
+}
+
+/// Writes a header value, folding it with `fold` as described in
+/// [`write_folded_header`], followed by a CRLF.
+pub(crate) fn write_folded(
+    value: &str,
+    mut output: impl Write,
+    mut bytes_written: usize,
+    max_line: usize,
+    fold: &[u8],
+) -> io::Result<()> {
+    for (pos, &ch) in value.as_bytes().iter().enumerate() {
+        if bytes_written >= max_line && ch.is_ascii_whitespace() && pos < value.len() - 1 {
+            output.write_all(fold)?;
+            bytes_written = 1;
+        }
+        output.write_all(&[ch])?;
+        bytes_written += 1;
+    }
+    output.write_all(b"\r\n")
+}
+
+/// Writes a header name followed by ": ", returning an error if the name is
+/// empty or contains characters other than printable US-ASCII excluding ":".
+pub(crate) fn write_header_name(name: &str, mut output: impl Write) -> io::Result<()> {
+    validate_header_name(name)?;
+    output.write_all(name.as_bytes())?;
+    output.write_all(b": ")
+}
+
+/// Returns an error if a header name is empty or contains characters other
+/// than printable US-ASCII excluding ":".
+pub(crate) fn validate_header_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || !name.bytes().all(|ch| ch.is_ascii_graphic() && ch != b':') {
+        Err(Error::InvalidHeaderName(name.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, MessageBuilder};
+
+    use super::{raw::Raw, text::Text, write_folded_header, Header, HeaderType};
+
+    #[test]
+    fn write_folded_header_matches_rendering() {
+        let value = "i=1; a=rsa-sha256; d=example.org; s=selector; \
+             h=from:to:subject:date:message-id; bh=2jUSOH9NhtVGCQWNr9BrIAPreKQjO6Sn7XIkfJVOzv8=; \
+             b=dzdVyOfAKCdLXdJOc9G2q8LoXSlEniSbav+yuU4zGeeruD00lszZVoG4ZHRNiYzR";
+        for (name, max_line) in [("ARC-Message-Signature", 76), ("X-Short", 40)] {
+            let mut output = Vec::new();
+            write_folded_header(name, value, &mut output, max_line).unwrap();
+            let text = String::from_utf8(output.clone()).unwrap();
+            assert!(text.lines().count() > 1, "{}", text);
+            assert_eq!(
+                text.replace("\r\n ", ""),
+                format!("{}: {}\r\n", name, value)
+            );
+            if max_line != 76 {
+                continue;
+            }
+
+            for header in [HeaderType::from(Raw::new(value)), Text::new(value).into()] {
+                let mut expected = format!("{}: ", name).into_bytes();
+                header.write_header(&mut expected, name.len() + 2).unwrap();
+                assert_eq!(output, expected);
+            }
+            let message = MessageBuilder::new()
+                .header(name, Raw::new(value))
+                .write_to_vec()
+                .unwrap();
+            assert!(message.starts_with(&output), "{}", text);
+        }
+        assert!(write_folded_header("Bad:Name", "value", Vec::new(), 76).is_err());
+        for value in ["a\r\nBcc: victim@example.com", "a\nb", "a\rb", "a\0b"] {
+            let mut output = Vec::new();
+            let err = write_folded_header("X-Test", value, &mut output, 76).unwrap_err();
+            assert_eq!(err.to_string(), Error::InvalidHeaderValue.to_string());
+            assert!(output.is_empty(), "{:?}", value);
+        }
+    }
 }
@@ -11,7 +11,9 @@
 
 use std::borrow::Cow;
 
-use super::Header;
+use crate::Error;
+
+use super::{write_folded, Header, FOLD};
 
 /// Raw e-mail header.
 /// Raw headers are not encoded, only line-wrapped.
@@ -25,6 +27,21 @@ impl<'x> Raw<'x> {
     pub fn new(raw: impl Into<Cow<'x, str>>) -> Self {
         Self { raw: raw.into() }
     }
+
+    /// Create a new raw header, returning an error if the value contains CR,
+    /// LF or NUL characters that could be used to inject headers. A single
+    /// trailing CRLF is removed.
+    pub fn try_new(raw: impl Into<Cow<'x, str>>) -> Result<Self, Error> {
+        let mut raw = raw.into();
+        if let Some(value) = raw.strip_suffix("\r\n") {
+            raw = value.to_string().into();
+        }
+        if raw.contains(['\r', '\n', '\0']) {
+            Err(Error::InvalidHeaderValue)
+        } else {
+            Ok(Self { raw })
+        }
+    }
 }
 
 impl<'x, T> From<T> for Raw<'x>
@@ -39,18 +56,46 @@ where
 impl<'x> Header for Raw<'x> {
     fn write_header(
         &self,
-        mut output: impl std::io::Write,
-        mut bytes_written: usize,
+        output: impl std::io::Write,
+        bytes_written: usize,
     ) -> std::io::Result<usize> {
-        for (pos, &ch) in self.raw.as_bytes().iter().enumerate() {
-            if bytes_written >= 76 && ch.is_ascii_whitespace() && pos < self.raw.len() - 1 {
-                output.write_all(b"\r\n\t")?;
-                bytes_written = 1;
-            }
-            output.write_all(&[ch])?;
-            bytes_written += 1;
-        }
-        output.write_all(b"\r\n")?;
+        self.write_header_with_fold(output, bytes_written, FOLD)
+    }
+
+    fn write_header_with_fold(
+        &self,
+        output: impl std::io::Write,
+        bytes_written: usize,
+        fold: &[u8],
+    ) -> std::io::Result<usize> {
+        write_folded(&self.raw, output, bytes_written, 76, fold)?;
         Ok(0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::Error;
+
+    use super::Raw;
+
+    #[test]
+    fn try_new_rejects_injection() {
+        assert_eq!(Raw::try_new("1.0").unwrap().raw, "1.0");
+        assert_eq!(Raw::try_new("5.2\r\n").unwrap().raw, "5.2");
+        for value in [
+            "a\r\nBcc: victim@example.com",
+            "a\nb",
+            "a\rb",
+            "a\0b",
+            "a\r\n\r\n",
+        ] {
+            assert_eq!(
+                Raw::try_new(value),
+                Err(Error::InvalidHeaderValue),
+                "{:?}",
+                value
+            );
+        }
+    }
+}
@@ -0,0 +1,167 @@
+/*
+ * Copyright Stalwart Labs Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::borrow::Cow;
+
+use super::{date::Date, Header, FOLD};
+
+/// RFC5322 Received trace header
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Received<'x> {
+    pub from: Option<Cow<'x, str>>,
+    pub by: Option<Cow<'x, str>>,
+    pub with: Option<Cow<'x, str>>,
+    pub id: Option<Cow<'x, str>>,
+    pub for_: Option<Cow<'x, str>>,
+    pub date: Date,
+}
+
+impl<'x> Received<'x> {
+    /// Create a new Received header with the given date.
+    pub fn new(date: impl Into<Date>) -> Self {
+        Self {
+            from: None,
+            by: None,
+            with: None,
+            id: None,
+            for_: None,
+            date: date.into(),
+        }
+    }
+
+    /// Set the host the message was received from.
+    pub fn from(mut self, host: impl Into<Cow<'x, str>>) -> Self {
+        self.from = Some(host.into());
+        self
+    }
+
+    /// Set the host that received the message.
+    pub fn by(mut self, host: impl Into<Cow<'x, str>>) -> Self {
+        self.by = Some(host.into());
+        self
+    }
+
+    /// Set the protocol the message was received with, such as `ESMTP`.
+    pub fn with(mut self, protocol: impl Into<Cow<'x, str>>) -> Self {
+        self.with = Some(protocol.into());
+        self
+    }
+
+    /// Set the queue id assigned to the message.
+    pub fn id(mut self, id: impl Into<Cow<'x, str>>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Set the recipient the message was received for.
+    pub fn for_(mut self, recipient: impl Into<Cow<'x, str>>) -> Self {
+        self.for_ = Some(recipient.into());
+        self
+    }
+}
+
+impl<'x> Header for Received<'x> {
+    fn write_header(
+        &self,
+        output: impl std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with_fold(output, bytes_written, FOLD)
+    }
+
+    fn write_header_with_fold(
+        &self,
+        mut output: impl std::io::Write,
+        mut bytes_written: usize,
+        fold: &[u8],
+    ) -> std::io::Result<usize> {
+        let recipient = self.for_.as_ref().map(|rcpt| format!("<{}>", rcpt));
+        let mut is_first = true;
+
+        for (token, value) in [
+            ("from", self.from.as_deref()),
+            ("by", self.by.as_deref()),
+            ("with", self.with.as_deref()),
+            ("id", self.id.as_deref()),
+            ("for", recipient.as_deref()),
+        ] {
+            if let Some(value) = value {
+                let len = token.len() + value.len() + 1;
+                if is_first {
+                    is_first = false;
+                } else if bytes_written + len + 1 > 76 {
+                    output.write_all(fold)?;
+                    bytes_written = 1;
+                } else {
+                    output.write_all(b" ")?;
+                    bytes_written += 1;
+                }
+                output.write_all(token.as_bytes())?;
+                output.write_all(b" ")?;
+                output.write_all(value.as_bytes())?;
+                bytes_written += len;
+            }
+        }
+
+        let date = self.date.to_rfc822();
+        output.write_all(b";")?;
+        if bytes_written + date.len() + 2 > 76 {
+            output.write_all(fold)?;
+        } else {
+            output.write_all(b" ")?;
+        }
+        output.write_all(date.as_bytes())?;
+        output.write_all(b"\r\n")?;
+
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use crate::headers::Header;
+
+    use super::Received;
+
+    #[test]
+    fn write_received() {
+        for (received, expected) in [
+            (
+                Received::new(0i64)
+                    .from("mx.example.com")
+                    .by("mail.example.org")
+                    .with("ESMTP")
+                    .id("4a2e8c1f")
+                    .for_("jane@example.org"),
+                concat!(
+                    "Received: from mx.example.com by mail.example.org with ESMTP id 4a2e8c1f\r\n",
+                    " for <jane@example.org>; Thu, 1 Jan 1970 00:00:00 +0000\r\n"
+                ),
+            ),
+            (
+                Received::new(SystemTime::UNIX_EPOCH)
+                    .by("localhost")
+                    .with("LMTP"),
+                "Received: by localhost with LMTP; Thu, 1 Jan 1970 00:00:00 +0000\r\n",
+            ),
+        ] {
+            let mut output = b"Received: ".to_vec();
+            received.write_header(&mut output, 10).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(output, expected);
+            for line in output.split("\r\n") {
+                assert!(line.len() <= 78, "{}", output);
+            }
+        }
+    }
+}
@@ -17,7 +17,7 @@ use crate::encoders::{
     quoted_printable::quoted_printable_encode,
 };
 
-use super::Header;
+use super::{write_folded, Header, FOLD};
 
 /// Unstructured text e-mail header.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -43,48 +43,99 @@ where
 
 impl<'x> Header for Text<'x> {
     fn write_header(
+        &self,
+        output: impl std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with_fold(output, bytes_written, FOLD)
+    }
+
+    fn write_header_with_fold(
         &self,
         mut output: impl std::io::Write,
-        mut bytes_written: usize,
+        bytes_written: usize,
+        fold: &[u8],
     ) -> std::io::Result<usize> {
         match get_encoding_type(self.text.as_bytes(), true, false) {
             EncodingType::Base64 => {
-                for (pos, chunk) in self.text.as_bytes().chunks(76 - bytes_written).enumerate() {
+                for (pos, chunk) in
+                    utf8_chunks(&self.text, 76_usize.saturating_sub(bytes_written)).enumerate()
+                {
                     if pos > 0 {
-                        output.write_all(b"\t")?;
+                        output.write_all(fold)?;
                     }
                     output.write_all(b"=?utf-8?B?")?;
-                    base64_encode_mime(chunk, &mut output, true)?;
-                    output.write_all(b"?=\r\n")?;
+                    base64_encode_mime(chunk.as_bytes(), &mut output, true)?;
+                    output.write_all(b"?=")?;
                 }
+                output.write_all(b"\r\n")?;
             }
             EncodingType::QuotedPrintable(is_ascii) => {
-                for (pos, chunk) in self.text.as_bytes().chunks(76 - bytes_written).enumerate() {
+                for (pos, chunk) in
+                    utf8_chunks(&self.text, 76_usize.saturating_sub(bytes_written)).enumerate()
+                {
                     if pos > 0 {
-                        output.write_all(b"\t")?;
+                        output.write_all(fold)?;
                     }
                     if !is_ascii {
                         output.write_all(b"=?utf-8?Q?")?;
                     } else {
                         output.write_all(b"=?us-ascii?Q?")?;
                     }
-                    quoted_printable_encode(chunk, &mut output, true, false)?;
-                    output.write_all(b"?=\r\n")?;
+                    quoted_printable_encode(chunk.as_bytes(), &mut output, true, false)?;
+                    output.write_all(b"?=")?;
                 }
+                output.write_all(b"\r\n")?;
             }
             EncodingType::None => {
-                for (pos, &ch) in self.text.as_bytes().iter().enumerate() {
-                    if bytes_written >= 76 && ch.is_ascii_whitespace() && pos < self.text.len() - 1
-                    {
-                        output.write_all(b"\r\n\t")?;
-                        bytes_written = 1;
-                    }
-                    output.write_all(&[ch])?;
-                    bytes_written += 1;
-                }
-                output.write_all(b"\r\n")?;
+                write_folded(&self.text, output, bytes_written, 76, fold)?;
             }
         }
         Ok(0)
     }
 }
+
+/// Splits text into chunks of at most `max_len` bytes without splitting
+/// characters, as each encoded-word must contain whole characters
+/// (RFC 2047, section 5). Chunks contain at least one character.
+fn utf8_chunks(text: &str, max_len: usize) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let mut end = rest.len().min(max_len.max(1));
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest.chars().next()?.len_utf8();
+        }
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use mail_parser::MessageParser;
+
+    use crate::headers::Header;
+
+    use super::Text;
+
+    #[test]
+    fn encode_without_splitting_characters() {
+        for subject in ["ñ".repeat(60), format!("a{}", "日本".repeat(30))] {
+            let mut output = b"Subject: ".to_vec();
+            Text::new(subject.as_str())
+                .write_header(&mut output, 9)
+                .unwrap();
+            output.extend_from_slice(b"\r\n");
+            assert_eq!(
+                MessageParser::new().parse(&output).unwrap().subject(),
+                Some(subject.as_str()),
+                "{}",
+                String::from_utf8_lossy(&output)
+            );
+        }
+    }
+}
@@ -11,7 +11,7 @@
 
 use std::borrow::Cow;
 
-use super::Header;
+use super::{Header, FOLD};
 
 /// URL header, used mostly on List-* headers
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -80,14 +80,23 @@ where
 
 impl<'x> Header for URL<'x> {
     fn write_header(
+        &self,
+        output: impl std::io::Write,
+        bytes_written: usize,
+    ) -> std::io::Result<usize> {
+        self.write_header_with_fold(output, bytes_written, FOLD)
+    }
+
+    fn write_header_with_fold(
         &self,
         mut output: impl std::io::Write,
         mut bytes_written: usize,
+        fold: &[u8],
     ) -> std::io::Result<usize> {
         for (pos, url) in self.url.iter().enumerate() {
             if pos > 0 {
                 if bytes_written + url.len() + 2 >= 76 {
-                    output.write_all(b"\r\n\t")?;
+                    output.write_all(fold)?;
                     bytes_written = 1;
                 } else {
                     output.write_all(b" ")?;
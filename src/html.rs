@@ -0,0 +1,588 @@
+/*
+ * Copyright Stalwart Labs Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+/// Converts an HTML document into a plain text alternative: tags are
+/// stripped, entities decoded, line breaks and paragraphs preserved and
+/// link targets written after the link text.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut href: Option<String> = None;
+    let mut skip_until: Option<&str> = None;
+    let mut pending_space = false;
+    let mut chars = html.char_indices().peekable();
+
+    while let Some((pos, ch)) = chars.next() {
+        match ch {
+            '<' => {
+                let end = html[pos..].find('>').map_or(html.len(), |end| pos + end);
+                let tag = &html[pos + 1..end];
+                while chars.peek().is_some_and(|&(next_pos, _)| next_pos <= end) {
+                    chars.next();
+                }
+
+                let is_closing = tag.starts_with('/');
+                let name = tag
+                    .trim_start_matches('/')
+                    .split(|c: char| c.is_ascii_whitespace() || c == '/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_ascii_lowercase();
+
+                if let Some(skip_tag) = skip_until {
+                    if is_closing && name == skip_tag {
+                        skip_until = None;
+                    }
+                    continue;
+                }
+
+                match (name.as_str(), is_closing) {
+                    ("script", false) => skip_until = Some("script"),
+                    ("style", false) => skip_until = Some("style"),
+                    ("head", false) => skip_until = Some("head"),
+                    ("br", _) => {
+                        text.push('\n');
+                        pending_space = false;
+                    }
+                    (
+                        "p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "table"
+                        | "blockquote" | "ul" | "ol",
+                        _,
+                    ) => {
+                        end_paragraph(&mut text);
+                        pending_space = false;
+                    }
+                    ("tr", _) => {
+                        end_line(&mut text);
+                        pending_space = false;
+                    }
+                    ("li", false) => {
+                        end_line(&mut text);
+                        text.push_str("- ");
+                        pending_space = false;
+                    }
+                    ("a", false) => {
+                        href = attribute(tag, "href").map(|url| decode_entities(&url));
+                    }
+                    ("a", true) => {
+                        if let Some(url) = href.take() {
+                            if !url.is_empty() && !text.ends_with(url.as_str()) {
+                                text.push_str(" (");
+                                text.push_str(&url);
+                                text.push(')');
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            _ if skip_until.is_some() => (),
+            _ if ch.is_whitespace() => {
+                pending_space = true;
+            }
+            '&' => {
+                let end = html[pos..]
+                    .char_indices()
+                    .take(12)
+                    .find(|(_, c)| *c == ';')
+                    .map(|(end, _)| pos + end);
+                let decoded = end.and_then(|end| decode_entity(&html[pos + 1..end]));
+                if pending_space && !text.is_empty() && !text.ends_with(['\n', ' ']) {
+                    text.push(' ');
+                }
+                pending_space = false;
+                if let (Some(end), Some(decoded)) = (end, decoded) {
+                    text.push(decoded);
+                    while chars.peek().is_some_and(|&(next_pos, _)| next_pos <= end) {
+                        chars.next();
+                    }
+                } else {
+                    text.push('&');
+                }
+            }
+            _ => {
+                if pending_space && !text.is_empty() && !text.ends_with(['\n', ' ']) {
+                    text.push(' ');
+                }
+                pending_space = false;
+                text.push(ch);
+            }
+        }
+    }
+
+    let mut text = text.trim().to_string();
+    text.push('\n');
+    text
+}
+
+/// Renders a Markdown document, including tables and strikethrough, to
+/// HTML. Raw HTML in the document is copied to the output when
+/// `keep_html` is set and removed otherwise.
+#[cfg(feature = "markdown")]
+pub fn markdown_to_html(markdown: &str, keep_html: bool) -> String {
+    use pulldown_cmark::{Event, Options, Parser};
+
+    let parser = Parser::new_ext(
+        markdown,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    )
+    .filter(|event| keep_html || !matches!(event, Event::Html(_) | Event::InlineHtml(_)));
+    let mut html = String::with_capacity(markdown.len() * 3 / 2);
+    pulldown_cmark::html::push_html(&mut html, parser);
+    html
+}
+
+/// Elements removed together with their contents by [`sanitize_html`].
+#[cfg(feature = "sanitize")]
+const UNSAFE_CONTAINERS: &[&str] = &["script", "style", "object", "applet"];
+
+/// Void elements removed by [`sanitize_html`].
+#[cfg(feature = "sanitize")]
+const UNSAFE_VOID_ELEMENTS: &[&str] = &["embed", "base"];
+
+/// Removes active content from an HTML document: `<script>`, `<style>`,
+/// `<object>` and `<applet>` elements and their contents, `<embed>` and
+/// `<base>` tags and attributes containing `javascript:` URLs. The rest of
+/// the document is copied unchanged.
+#[cfg(feature = "sanitize")]
+pub fn sanitize_html(html: &str) -> String {
+    let lower = html.to_ascii_lowercase();
+    let mut output = String::with_capacity(html.len());
+    let mut pos = 0;
+
+    while let Some(start) = html[pos..].find('<').map(|start| pos + start) {
+        output.push_str(&html[pos..start]);
+        if html[start..].starts_with("<!--") {
+            let end = html[start..]
+                .find("-->")
+                .map_or(html.len(), |end| start + end + 3);
+            output.push_str(&html[start..end]);
+            pos = end;
+            continue;
+        }
+
+        let end = tag_end(html, start);
+        let tag = &html[start + 1..end];
+        pos = (end + 1).min(html.len());
+
+        let is_closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_ascii_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            // Not a tag, such as "a < b".
+            output.push('<');
+            pos = start + 1;
+        } else if UNSAFE_CONTAINERS.contains(&name.as_str()) {
+            if !is_closing {
+                pos = lower[pos..]
+                    .find(&format!("</{}", name))
+                    .map_or(html.len(), |close| {
+                        (tag_end(html, pos + close) + 1).min(html.len())
+                    });
+            }
+        } else if !UNSAFE_VOID_ELEMENTS.contains(&name.as_str()) {
+            write_safe_tag(tag, &mut output);
+        }
+    }
+    output.push_str(&html[pos..]);
+
+    output
+}
+
+/// Returns the position of the `>` closing the tag starting at `start`,
+/// skipping quoted attribute values, or the length of `html`.
+#[cfg(feature = "sanitize")]
+fn tag_end(html: &str, start: usize) -> usize {
+    let mut quote = None;
+    let mut after_equals = false;
+
+    for (pos, ch) in html[start..].char_indices() {
+        match quote {
+            Some(quote_char) if ch == quote_char => quote = None,
+            Some(_) => (),
+            None if ch == '>' => return start + pos,
+            None if after_equals && matches!(ch, '"' | '\'') => quote = Some(ch),
+            None => (),
+        }
+        after_equals = ch == '=' || (after_equals && ch.is_ascii_whitespace());
+    }
+
+    html.len()
+}
+
+/// Writes a tag, given the text between `<` and `>`, omitting attributes
+/// with a `javascript:` URL as their value.
+#[cfg(feature = "sanitize")]
+fn write_safe_tag(tag: &str, output: &mut String) {
+    let name_start = usize::from(tag.starts_with('/'));
+    let name_end = tag[name_start..]
+        .find(|c: char| c.is_ascii_whitespace() || c == '/')
+        .map_or(tag.len(), |end| name_start + end);
+    output.push('<');
+    output.push_str(&tag[..name_end]);
+
+    let mut rest = &tag[name_end..];
+    loop {
+        let attribute = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        let separator = &rest[..rest.len() - attribute.len()];
+        if attribute.is_empty() {
+            output.push_str(separator);
+            break;
+        }
+
+        let name_len = attribute
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+            .unwrap_or(attribute.len());
+        let mut attribute_len = name_len;
+        let mut value = None;
+        if let Some(after_equals) = attribute[name_len..].trim_start().strip_prefix('=') {
+            let value_str = after_equals.trim_start();
+            let value_start = attribute.len() - value_str.len();
+            let value_len = match value_str.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let value_end = value_str[1..]
+                        .find(quote)
+                        .map_or(value_str.len(), |end| end + 1);
+                    value = Some(&value_str[1..value_end]);
+                    (value_end + 1).min(value_str.len())
+                }
+                _ => {
+                    let value_end = value_str
+                        .find(|c: char| c.is_ascii_whitespace())
+                        .unwrap_or(value_str.len());
+                    value = Some(&value_str[..value_end]);
+                    value_end
+                }
+            };
+            attribute_len = value_start + value_len;
+        }
+
+        if !value.is_some_and(is_javascript_url) {
+            output.push_str(separator);
+            output.push_str(&attribute[..attribute_len]);
+        }
+        rest = &attribute[attribute_len..];
+    }
+
+    output.push('>');
+}
+
+/// Returns whether an attribute value is a `javascript:` URL, ignoring the
+/// entities, whitespace and control characters browsers would ignore.
+#[cfg(feature = "sanitize")]
+fn is_javascript_url(value: &str) -> bool {
+    decode_entities(value)
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .take(11)
+        .collect::<String>()
+        .eq_ignore_ascii_case("javascript:")
+}
+
+fn end_line(text: &mut String) {
+    while text.ends_with(' ') {
+        text.pop();
+    }
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+fn end_paragraph(text: &mut String) {
+    end_line(text);
+    if !text.is_empty() && !text.ends_with("\n\n") {
+        text.push('\n');
+    }
+}
+
+/// Returns the Content-IDs referenced by `cid:` URLs (RFC 2392) in an HTML
+/// document, such as `<img src="cid:logo">` or `url(cid:logo)` in styles,
+/// percent-decoded and in order of appearance without duplicates.
+pub fn cid_references(html: &str) -> Vec<String> {
+    let lower = html.to_ascii_lowercase();
+    let mut references: Vec<String> = Vec::new();
+    let mut offset = 0;
+
+    while let Some(pos) = lower[offset..].find("cid:") {
+        let start = offset + pos + 4;
+        offset = start;
+        if lower[..start - 4]
+            .chars()
+            .last()
+            .is_some_and(|ch| ch.is_ascii_alphanumeric())
+        {
+            continue;
+        }
+        let end = html[start..]
+            .find(|ch: char| ch.is_ascii_whitespace() || matches!(ch, '"' | '\'' | ')' | '>'))
+            .map_or(html.len(), |end| start + end);
+        let cid = percent_decode(&decode_entities(&html[start..end]));
+        if !cid.is_empty() && !references.contains(&cid) {
+            references.push(cid);
+        }
+    }
+
+    references
+}
+
+/// Returns the `<img>` tags whose `src` is a `cid:` URL as the position
+/// just past the tag and the percent-decoded Content-ID it references, in
+/// order of appearance.
+pub(crate) fn cid_image_tags(html: &str) -> Vec<(usize, String)> {
+    let lower = html.to_ascii_lowercase();
+    let mut tags = Vec::new();
+    let mut offset = 0;
+
+    while let Some(pos) = lower[offset..].find("<img") {
+        let start = offset + pos;
+        let Some(end) = html[start..].find('>').map(|end| start + end + 1) else {
+            break;
+        };
+        offset = end;
+        let Some(src) = attribute(&html[start..end], "src") else {
+            continue;
+        };
+        let src = decode_entities(src.trim());
+        if let Some(cid) = src
+            .get(..4)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("cid:"))
+            .map(|_| percent_decode(&src[4..]))
+            .filter(|cid| !cid.is_empty())
+        {
+            tags.push((end, cid));
+        }
+    }
+
+    tags
+}
+
+fn percent_decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&ch, tail)) = rest.split_first() {
+        match tail
+            .get(..2)
+            .filter(|hex| ch == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())
+        {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(ch);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Returns the value of an attribute in an HTML tag.
+pub(crate) fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut offset = 0;
+
+    while let Some(pos) = lower[offset..].find(name) {
+        let pos = offset + pos;
+        offset = pos + name.len();
+        let is_boundary = lower[..pos]
+            .chars()
+            .last()
+            .is_some_and(|c| c.is_ascii_whitespace());
+        let rest = lower[offset..].trim_start();
+        if !is_boundary || !rest.starts_with('=') {
+            continue;
+        }
+
+        let value_start = tag.len() - rest.len() + 1;
+        let value = tag[value_start..].trim_start();
+        return Some(match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..]
+                .split(quote)
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            _ => value
+                .split(|c: char| c.is_ascii_whitespace() || c == '>')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        });
+    }
+
+    None
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(decoded) = rest
+            .find(';')
+            .and_then(|end| decode_entity(&rest[1..end]).map(|ch| (ch, end)))
+        {
+            result.push(decoded.0);
+            rest = &rest[decoded.1 + 1..];
+        } else {
+            result.push('&');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        "copy" => Some('©'),
+        "reg" => Some('®'),
+        "hellip" => Some('…'),
+        "mdash" => Some('—'),
+        "ndash" => Some('–'),
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            if let Some(hex) = code.strip_prefix(['x', 'X']) {
+                u32::from_str_radix(hex, 16).ok()
+            } else {
+                code.parse().ok()
+            }
+            .and_then(char::from_u32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cid_references, html_to_text};
+
+    #[test]
+    fn convert_html_to_text() {
+        for (html, expected) in [
+            (
+                "<p>Hello,<br>world!</p><p>Second   paragraph</p>",
+                "Hello,\nworld!\n\nSecond paragraph\n",
+            ),
+            (
+                "<html><head><title>T</title><style>p { color: red; }</style></head><body><p>Visit <a href=\"https://example.com/?a=1&amp;b=2\">our site</a> today.</p></body></html>",
+                "Visit our site (https://example.com/?a=1&b=2) today.\n",
+            ),
+            (
+                "<ul><li>One</li><li>Two &amp; three</li></ul>",
+                "- One\n- Two & three\n",
+            ),
+            (
+                "Caf&eacute; &lt;tag&gt; &#169; &#x263A; &quot;quoted&quot;",
+                "Caf&eacute; <tag> © ☺ \"quoted\"\n",
+            ),
+            (
+                "<a href='https://example.com'>https://example.com</a>",
+                "https://example.com\n",
+            ),
+        ] {
+            assert_eq!(html_to_text(html), expected, "{}", html);
+        }
+    }
+
+    #[cfg(feature = "sanitize")]
+    #[test]
+    fn sanitize_html() {
+        for (html, expected) in [
+            (
+                "<p>Hello</p><script type=\"text/javascript\">alert('<p>');</script><p>world</p>",
+                "<p>Hello</p><p>world</p>",
+            ),
+            (
+                "<HEAD><BASE href=\"https://evil.example\"><Style>p {}</STYLE></HEAD>",
+                "<HEAD></HEAD>",
+            ),
+            (
+                "<object data=\"x.swf\"><param name=\"a\"></object><embed src=\"x.swf\"/>ok<applet code=\"A\">fallback</applet>",
+                "ok",
+            ),
+            (
+                "<a href=\"javascript:alert(1)\" title=\"x > y\">link</a>",
+                "<a title=\"x > y\">link</a>",
+            ),
+            (
+                "<a HREF = ' JaVa&#x53;cript:alert(1)' class=link>a</a><a href=https://example.com/>b</a>",
+                "<a class=link>a</a><a href=https://example.com/>b</a>",
+            ),
+            (
+                "<img src=\"cid:logo\" /> 1 < 2 <!-- <script> -->",
+                "<img src=\"cid:logo\" /> 1 < 2 <!-- <script> -->",
+            ),
+            ("<p>Unterminated <script>alert(1)", "<p>Unterminated "),
+        ] {
+            assert_eq!(super::sanitize_html(html), expected, "{}", html);
+        }
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn markdown_to_html() {
+        let markdown = concat!(
+            "# Release notes\n\n",
+            "See [the docs](https://example.com/docs) or <b>ask</b> us.\n\n",
+            "- First\n",
+            "- `second`\n\n",
+            "```rust\n",
+            "let a = 1 < 2;\n",
+            "```\n\n",
+            "<div>raw block</div>\n",
+        );
+        let html = super::markdown_to_html(markdown, true);
+        for snippet in [
+            "<h1>Release notes</h1>\n",
+            "<a href=\"https://example.com/docs\">the docs</a>",
+            "<b>ask</b>",
+            "<ul>\n<li>First</li>\n<li><code>second</code></li>\n</ul>\n",
+            "<pre><code class=\"language-rust\">let a = 1 &lt; 2;\n</code></pre>\n",
+            "<div>raw block</div>",
+        ] {
+            assert!(html.contains(snippet), "{:?} {}", snippet, html);
+        }
+
+        let html = super::markdown_to_html(markdown, false);
+        assert!(
+            html.contains(
+                "<p>See <a href=\"https://example.com/docs\">the docs</a> or ask us.</p>"
+            ),
+            "{}",
+            html
+        );
+        assert!(!html.contains("<div>") && !html.contains("<b>"), "{}", html);
+    }
+
+    #[test]
+    fn find_cid_references() {
+        assert_eq!(
+            cid_references(concat!(
+                "<p style=\"background: url(cid:bg@example.com)\">",
+                "<img src=\"cid:logo%40example.com\"><img SRC='CID:photo'>",
+                "<img src=\"cid:logo@example.com\"><a href=\"http://acid:80/\">x</a></p>"
+            )),
+            ["bg@example.com", "logo@example.com", "photo"]
+        );
+        assert!(cid_references("<p>No images</p>").is_empty());
+    }
+}
@@ -210,24 +210,198 @@
 //!
 //! [COPYING]: https://github.com/stalwartlabs/mail-builder/blob/main/COPYING
 //!
+#[cfg(feature = "proptest")]
+pub mod arbitrary;
+pub mod canonical;
 pub mod encoders;
 pub mod headers;
+pub mod html;
 pub mod mime;
+pub mod parser;
 
 use std::{
     borrow::Cow,
+    fmt::Display,
+    fs,
     io::{self, Write},
+    path::{Path, PathBuf},
 };
 
+use canonical::{canonicalize_body, canonicalize_header, Canonicalization};
+use encoders::dot_stuffing::DotStuffingWriter;
 use headers::{
-    address::Address,
-    content_type::ContentType,
-    date::Date,
-    message_id::{generate_message_id_header, MessageId},
-    text::Text,
-    Header, HeaderType,
+    address::Address, authentication_results::AuthenticationResults, content_type::ContentType,
+    date::Date, message_id::MessageId, raw::Raw, received::Received, text::Text, url::URL,
+    validate_header_name, write_header_name, Header, HeaderType,
 };
-use mime::{BodyPart, MimePart};
+use mime::{
+    make_boundary, make_deterministic_boundary, BodyPart, CalendarMethod, InvalidBoundaryError,
+    MimePart, SmimeType, WriteOptions,
+};
+
+/// Errors raised while building a message. When returned from a writer
+/// method they are wrapped in an `io::Error` of kind `InvalidInput`.
+///
+/// In strict mode (see [`WriteOptions::strict`]) the following checks,
+/// which are otherwise fixed or ignored silently, also return errors:
+///
+/// - [`Error::EmptyMultipart`]: a multipart/* part with no body parts,
+///   otherwise written with an empty text/plain part.
+/// - [`Error::Invalid7Bit`]: a part declared as `Content-Transfer-Encoding: 7bit`
+///   whose contents are not 7bit, otherwise written as is.
+/// - [`Error::EmptyDisplayName`]: an address with an empty display name in
+///   the message headers or in the headers of any part, otherwise written
+///   without a display name.
+/// - [`Error::BinaryTextPart`]: a text/* part whose contents look binary,
+///   otherwise encoded as text.
+/// - [`Error::InvalidContentType`]: a Content-Type without a subtype,
+///   otherwise given a default subtype such as text/plain.
+/// - [`Error::MissingContentType`]: a body part of a multipart/* part that
+///   has headers but no Content-Type, otherwise written without one so that
+///   readers assume text/plain.
+///
+/// Two other fixes are not covered by strict mode. Adding a body part to a
+/// part that is not multipart/* happens before writing, and
+/// [`MimePart::add_part`] already reports it by returning `None`. Multipart
+/// boundaries that do not conform to RFC 2046 are never regenerated; they
+/// return [`Error::InvalidBoundary`] in both modes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A multipart/* part has no body parts (strict mode only).
+    EmptyMultipart,
+    /// A part declared as 7bit contains 8bit data, NUL characters or lines
+    /// longer than 998 characters (strict mode only).
+    Invalid7Bit,
+    /// An address in the message or part headers has an empty display name
+    /// (strict mode only).
+    EmptyDisplayName,
+    /// The Content-Type header of a multipart/* part is neither a
+    /// structured content type nor a raw value.
+    UnsupportedContentType,
+    /// A header name is empty or contains whitespace, control, non-ASCII or
+    /// ":" characters.
+    InvalidHeaderName(String),
+    /// Multipart/* parts are nested deeper than the configured limit.
+    NestingTooDeep,
+    /// The message has more MIME parts than the configured limit.
+    TooManyParts,
+    /// A multipart boundary does not conform to RFC 2046.
+    InvalidBoundary(InvalidBoundaryError),
+    /// A header value contains CR, LF or NUL characters.
+    InvalidHeaderValue,
+    /// The message or the named part has more headers than the configured
+    /// limit.
+    TooManyHeaders(String),
+    /// The encoded headers of the message or the named part are larger than
+    /// the configured limit.
+    HeadersTooLarge(String),
+    /// A part declared as text/* contains binary data, identified by the
+    /// detected signature (strict mode only).
+    BinaryTextPart {
+        part: String,
+        signature: &'static str,
+    },
+    /// An address could not be converted to an SMTP envelope address.
+    InvalidAddress(String),
+    /// The message has no To, Cc or Bcc recipients.
+    NoRecipients,
+    /// A Content-Type has no subtype (strict mode only).
+    InvalidContentType(String),
+    /// A body part of a multipart/* part has headers but no Content-Type
+    /// (strict mode only).
+    MissingContentType,
+    /// A text/enriched part has a malformed, mismatched or unclosed
+    /// formatting command (`validate_enriched` feature only).
+    InvalidEnrichedText(String),
+    /// Writing the message failed with an I/O error other than the ones
+    /// above, described by the message.
+    Io(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::EmptyMultipart => write!(f, "Multipart with zero parts."),
+            Error::Invalid7Bit => write!(f, "Part declared as 7bit contains non 7bit data."),
+            Error::EmptyDisplayName => write!(f, "Address with an empty display name."),
+            Error::UnsupportedContentType => {
+                write!(f, "Unsupported multipart Content-Type header value.")
+            }
+            Error::InvalidHeaderName(name) => write!(f, "Invalid header name {:?}.", name),
+            Error::NestingTooDeep => write!(f, "Maximum MIME nesting depth exceeded."),
+            Error::TooManyParts => write!(f, "Maximum number of MIME parts exceeded."),
+            Error::InvalidBoundary(err) => write!(f, "Invalid MIME boundary: {}", err),
+            Error::InvalidHeaderValue => write!(f, "Header value contains CR, LF or NUL."),
+            Error::TooManyHeaders(part) => write!(f, "Too many headers in {:?}.", part),
+            Error::HeadersTooLarge(part) => write!(f, "Headers of {:?} are too large.", part),
+            Error::BinaryTextPart { part, signature } => {
+                write!(
+                    f,
+                    "Text part {:?} contains binary data ({}).",
+                    part, signature
+                )
+            }
+            Error::InvalidAddress(address) => write!(f, "Invalid address {:?}.", address),
+            Error::NoRecipients => write!(f, "Message has no recipients."),
+            Error::InvalidContentType(c_type) => {
+                write!(f, "Content type {:?} has no subtype.", c_type)
+            }
+            Error::MissingContentType => write!(f, "Body part without a Content-Type."),
+            Error::InvalidEnrichedText(command) => {
+                write!(f, "Invalid text/enriched command {:?}.", command)
+            }
+            Error::Io(message) => write!(f, "I/O error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+impl From<io::Error> for Error {
+    /// Unwraps the [`Error`] returned by a writer method, or describes any
+    /// other I/O error with [`Error::Io`].
+    fn from(err: io::Error) -> Self {
+        match err.get_ref().and_then(|err| err.downcast_ref::<Error>()) {
+            Some(err) => err.clone(),
+            None => Error::Io(err.to_string()),
+        }
+    }
+}
+
+/// Position of an alternative body added with
+/// [`MessageBuilder::alternative_body_at`] in the multipart/alternative part,
+/// relative to the text and HTML bodies. Calendar bodies are always last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlternativePosition {
+    BeforeText,
+    BeforeHtml,
+    AfterHtml,
+}
+
+/// Arrangement of the bodies, inline images and attachments of a message,
+/// see [`MessageBuilder::layout`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Inline images are placed in a multipart/related part with the HTML
+    /// body and attachments follow the bodies in a multipart/mixed part, as
+    /// preferred by most clients.
+    #[default]
+    Standard,
+    /// The parts are placed in a single multipart/mixed part which Apple
+    /// Mail displays in order: the HTML body is split after each `<img>`
+    /// tag referencing an inline image, and each image follows the HTML
+    /// segment that references it. The first segment stays an alternative
+    /// of the text body; images that are not referenced and attachments
+    /// come last. Inline images and attachments all get an `inline`
+    /// disposition and a file name.
+    Interleaved,
+}
 
 /// Builds an RFC5322 compliant MIME email message.
 #[derive(Clone, Debug)]
@@ -236,7 +410,14 @@ pub struct MessageBuilder<'x> {
     pub html_body: Option<MimePart<'x>>,
     pub text_body: Option<MimePart<'x>>,
     pub attachments: Option<Vec<MimePart<'x>>>,
+    pub inline_images: Option<Vec<MimePart<'x>>>,
+    pub calendar_body: Option<MimePart<'x>>,
+    pub alternative_bodies: Option<Vec<(AlternativePosition, MimePart<'x>)>>,
+    pub layout: Layout,
     pub body: Option<MimePart<'x>>,
+    pub header_order: Option<Vec<String>>,
+    pub sign_headers: Option<Vec<String>>,
+    pub write_options: WriteOptions,
 }
 
 impl<'x> Default for MessageBuilder<'x> {
@@ -253,7 +434,14 @@ impl<'x> MessageBuilder<'x> {
             html_body: None,
             text_body: None,
             attachments: None,
+            inline_images: None,
+            calendar_body: None,
+            alternative_bodies: None,
+            layout: Layout::Standard,
             body: None,
+            header_order: None,
+            sign_headers: None,
+            write_options: WriteOptions::default(),
         }
     }
 
@@ -273,6 +461,45 @@ impl<'x> MessageBuilder<'x> {
         self.header("References", value.into())
     }
 
+    /// Set the threading headers of a reply: In-Reply-To is set to the
+    /// original Message-ID, which is also appended to the original
+    /// References, and the original subject is prefixed with "Re: " unless
+    /// it already has that prefix.
+    pub fn reply_to_message(
+        self,
+        original_id: impl Into<Cow<'x, str>>,
+        original_references: impl Into<MessageId<'x>>,
+        original_subject: &str,
+    ) -> Self {
+        let original_id = original_id.into();
+        let mut references = original_references.into();
+        references.id.push(original_id.clone());
+        let subject = format!("Re: {}", strip_subject_prefixes(original_subject, &["re"]));
+
+        self.in_reply_to(MessageId::new(original_id))
+            .references(references)
+            .subject(subject)
+    }
+
+    /// Forward a message: the original subject is prefixed with "Fwd: "
+    /// unless it already has that prefix and the original message is
+    /// attached as a message/rfc822 part, or as a message/global part when
+    /// its headers are internationalized, see [`MimePart::new_message`].
+    pub fn forward_message(
+        mut self,
+        original: impl Into<Cow<'x, [u8]>>,
+        original_subject: &str,
+    ) -> Self {
+        self.attachments
+            .get_or_insert_with(Vec::new)
+            .push(MimePart::new_message(original).attachment("forwarded.eml"));
+        let subject = format!(
+            "Fwd: {}",
+            strip_subject_prefixes(original_subject, &["fwd", "fw"])
+        );
+        self.subject(subject)
+    }
+
     /// Set the Sender header.
     pub fn sender(self, value: impl Into<Address<'x>>) -> Self {
         self.header("Sender", value.into())
@@ -314,6 +541,42 @@ impl<'x> MessageBuilder<'x> {
         self.header("Date", value.into())
     }
 
+    /// Set the List-Id header (RFC 2919), either from a list identifier
+    /// or from a `(description, identifier)` tuple.
+    pub fn list_id(self, value: impl Into<Address<'x>>) -> Self {
+        self.header("List-Id", value.into())
+    }
+
+    /// Set the List-Post header (RFC 2369).
+    pub fn list_post(self, value: impl Into<URL<'x>>) -> Self {
+        self.header("List-Post", value.into())
+    }
+
+    /// Set the List-Archive header (RFC 2369).
+    pub fn list_archive(self, value: impl Into<URL<'x>>) -> Self {
+        self.header("List-Archive", value.into())
+    }
+
+    /// Set the List-Help header (RFC 2369).
+    pub fn list_help(self, value: impl Into<URL<'x>>) -> Self {
+        self.header("List-Help", value.into())
+    }
+
+    /// Add a Received trace header. Received headers are prepended, so the
+    /// most recently added one is written first.
+    pub fn add_received(mut self, value: Received<'x>) -> Self {
+        self.headers.insert(0, ("Received".into(), value.into()));
+        self
+    }
+
+    /// Add an Authentication-Results header (RFC 8601). Like Received
+    /// headers, it is prepended to the existing headers.
+    pub fn add_authentication_results(mut self, value: AuthenticationResults<'x>) -> Self {
+        self.headers
+            .insert(0, ("Authentication-Results".into(), value.into()));
+        self
+    }
+
     /// Add a custom header.
     pub fn header(
         mut self,
@@ -324,6 +587,24 @@ impl<'x> MessageBuilder<'x> {
         self
     }
 
+    /// Set a header not otherwise supported by this crate, such as
+    /// `Errors-To` or `X-Spam-Score`, replacing any existing headers with
+    /// the same name. Returns an error if the name is not a valid header
+    /// name or the value contains CR, LF or NUL characters.
+    pub fn set_header_raw(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Self, Error> {
+        let name = name.into();
+        validate_header_name(&name)?;
+        let value = Raw::try_new(value.into())?;
+        self.headers
+            .retain(|(header_name, _)| !header_name.eq_ignore_ascii_case(&name));
+        self.headers.push((name.into(), value.into()));
+        Ok(self)
+    }
+
     /// Set custom headers.
     pub fn headers<T, U, V>(mut self, header: T, values: U) -> Self
     where
@@ -356,6 +637,132 @@ impl<'x> MessageBuilder<'x> {
         self
     }
 
+    /// Set the text and HTML bodies of the message from a Markdown document:
+    /// the HTML body is rendered from it and the text body is the document
+    /// itself. Raw HTML in the document is copied to the HTML body.
+    #[cfg(feature = "markdown")]
+    pub fn markdown_body(self, value: impl Into<String>) -> Self {
+        self.markdown_bodies(value.into(), true)
+    }
+
+    /// Set the text and HTML bodies of the message from a Markdown document,
+    /// as [`MessageBuilder::markdown_body`] does, removing any raw HTML
+    /// from the rendered HTML body.
+    #[cfg(feature = "markdown")]
+    pub fn markdown_body_without_html(self, value: impl Into<String>) -> Self {
+        self.markdown_bodies(value.into(), false)
+    }
+
+    #[cfg(feature = "markdown")]
+    fn markdown_bodies(self, markdown: String, keep_html: bool) -> Self {
+        let html = html::markdown_to_html(&markdown, keep_html);
+        self.text_body(markdown).html_body(html)
+    }
+
+    /// Set how the bodies, inline images and attachments are arranged when
+    /// the message is written. Has no effect when a custom body has been
+    /// set with `body`.
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Add an alternative representation of the text and HTML bodies, such
+    /// as text/markdown, placed before the HTML body. Alternatives added
+    /// this way keep the order in which they were added, regardless of when
+    /// the text and HTML bodies are set. A UTF-8 charset is added unless the
+    /// content type has one.
+    pub fn alternative_body(
+        self,
+        content_type: impl Into<String>,
+        contents: impl Into<String>,
+    ) -> Self {
+        self.alternative_body_at(AlternativePosition::BeforeHtml, content_type, contents)
+    }
+
+    /// Add an alternative representation of the text and HTML bodies at the
+    /// given position, see [`MessageBuilder::alternative_body`].
+    pub fn alternative_body_at(
+        mut self,
+        position: AlternativePosition,
+        content_type: impl Into<String>,
+        contents: impl Into<String>,
+    ) -> Self {
+        let mut content_type = ContentType::parse(&content_type.into());
+        if content_type.attribute_case_insensitive("charset").is_none() {
+            content_type = content_type.attribute("charset", "utf-8");
+        }
+        self.alternative_bodies.get_or_insert_with(Vec::new).push((
+            position,
+            MimePart::new(content_type, BodyPart::Text(contents.into().into())),
+        ));
+        self
+    }
+
+    /// Add a calendar invitation or update: the iCalendar object, such as the
+    /// output of `Calendar::to_string` from the icalendar crate, is added
+    /// as a text/calendar alternative to the text and HTML bodies with the
+    /// given method, and as an .ics attachment named after the event UID.
+    /// The iCalendar object is written unchanged and should include a
+    /// METHOD property matching `method`.
+    pub fn attach_event(mut self, ics: impl Into<Cow<'x, str>>, method: CalendarMethod) -> Self {
+        let ics = ics.into();
+        let filename = match ics_uid(&ics) {
+            Some(uid) => format!(
+                "{}.ics",
+                mime::sanitize_filename(&uid, mime::MAX_FILENAME_LEN - 4)
+            ),
+            None => "invite.ics".to_string(),
+        };
+
+        self.attachments.get_or_insert_with(Vec::new).push(
+            MimePart::new(
+                ContentType::new("application/ics").attribute("name", filename.clone()),
+                BodyPart::Text(ics.clone()),
+            )
+            .attachment(filename),
+        );
+        self.calendar_body = Some(MimePart::new(
+            ContentType::new("text/calendar").attribute("method", method.as_str()),
+            BodyPart::Text(ics),
+        ));
+        self
+    }
+
+    /// Set the HTML body of the message along with a plain text alternative
+    /// generated from it, which strips tags, decodes entities and keeps
+    /// line breaks, paragraphs and link targets.
+    pub fn html_with_autotext(self, value: impl Into<Cow<'x, str>>) -> Self {
+        let value = value.into();
+        let text = html::html_to_text(&value);
+        self.text_body(text).html_body(value)
+    }
+
+    /// Append a 1x1 tracking image pointing to `url` to the HTML body,
+    /// just before the closing `</body>` tag or at the end of the document.
+    /// This has no effect if no HTML body has been set or if the same
+    /// tracking image was already added.
+    pub fn tracking_pixel(mut self, url: impl AsRef<str>) -> Self {
+        if let Some(MimePart {
+            contents: BodyPart::Text(html),
+            ..
+        }) = &mut self.html_body
+        {
+            let pixel = format!(
+                "<img src=\"{}\" width=\"1\" height=\"1\" border=\"0\" alt=\"\">",
+                url.as_ref().replace('&', "&amp;").replace('"', "&quot;")
+            );
+            if !html.contains(&pixel) {
+                let pos = html
+                    .to_ascii_lowercase()
+                    .rfind("</body>")
+                    .unwrap_or(html.len());
+                html.to_mut().insert_str(pos, &pixel);
+            }
+        }
+        self
+    }
+
     /// Add a binary attachment to the message.
     pub fn attachment(
         mut self,
@@ -382,92 +789,707 @@ impl<'x> MessageBuilder<'x> {
         self
     }
 
-    /// Set a custom MIME body structure.
+    /// Attach a file to the message, using its file name as the attachment
+    /// name and guessing its content type from the extension, or as in
+    /// [`MimePart::new_attachment_auto`] when the `mime-types` feature is
+    /// enabled. The charset of text files is detected from their contents,
+    /// see [`encoders::encode::detect_charset`], and files with an unknown
+    /// charset are attached as application/octet-stream. When a custom body
+    /// has been set with `body`, it is wrapped in a multipart/mixed part if
+    /// it is not one already.
+    pub fn attach_file(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read(path)?;
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        #[cfg(feature = "mime-types")]
+        let part = MimePart::new_attachment_auto(filename, contents);
+        #[cfg(not(feature = "mime-types"))]
+        let part = MimePart::new(mime::guess_content_type(path), contents).attachment(filename);
+        let part = part.detect_text_charset();
+
+        match self.body.take() {
+            Some(mut body)
+                if body
+                    .content_type()
+                    .is_some_and(|ct| ct.c_type.eq_ignore_ascii_case("multipart/mixed")) =>
+            {
+                body.add_part(part);
+                self.body = Some(body);
+            }
+            Some(body) => {
+                self.body = Some(MimePart::new("multipart/mixed", vec![body, part]));
+            }
+            None => {
+                self.attachments.get_or_insert_with(Vec::new).push(part);
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Embed an image file to be referenced from the HTML body, returning
+    /// the `cid:` URL to use in `<img src="...">`. When `cid` is `None`, a
+    /// unique Content-ID is generated. The HTML body is wrapped in a
+    /// multipart/related part together with its embedded images when the
+    /// message is written.
+    pub fn embed_image(
+        mut self,
+        path: impl AsRef<Path>,
+        cid: Option<&str>,
+    ) -> io::Result<(Self, String)> {
+        let path = path.as_ref();
+        let contents = fs::read(path)?;
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut part = MimePart::new(mime::guess_content_type(path), contents)
+            .attachment(filename)
+            .inline();
+        let cid = part.set_cid(cid.unwrap_or_default());
+        self.inline_images.get_or_insert_with(Vec::new).push(part);
+        Ok((self, cid))
+    }
+
+    /// Embed several images to be referenced from the HTML body, returning
+    /// their generated `cid:` URLs in the same order as `images`. As with
+    /// `embed_image`, the HTML body is wrapped in a multipart/related part
+    /// together with the images.
+    pub fn inline_images(
+        mut self,
+        images: Vec<(impl Into<ContentType<'x>>, impl Into<BodyPart<'x>>)>,
+    ) -> (Self, Vec<String>) {
+        let inline_images = self.inline_images.get_or_insert_with(Vec::new);
+        let mut cids = Vec::with_capacity(images.len());
+        for (content_type, contents) in images {
+            let mut part = MimePart::new(content_type, contents).inline();
+            cids.push(part.set_cid(""));
+            inline_images.push(part);
+        }
+        (self, cids)
+    }
+
+    /// Set a custom MIME body structure. The headers of `value` are written
+    /// together with the top-level message headers, replacing any message
+    /// header with the same name.
     pub fn body(mut self, value: MimePart<'x>) -> Self {
         self.body = Some(value);
         self
     }
 
-    /// Build the message.
-    pub fn write_to(self, mut output: impl Write) -> io::Result<()> {
-        let mut has_date = false;
-        let mut has_message_id = false;
+    /// Replace the body of the message, including any text, HTML,
+    /// attachment and inline parts, with an opaque S/MIME
+    /// application/pkcs7-mime part holding the DER encoded CMS object.
+    /// Top-level headers are kept.
+    pub fn smime_wrap(mut self, smime_type: SmimeType, der_bytes: Vec<u8>) -> Self {
+        self.text_body = None;
+        self.html_body = None;
+        self.attachments = None;
+        self.inline_images = None;
+        self.calendar_body = None;
+        self.alternative_bodies = None;
+        self.body = Some(MimePart::new_smime(smime_type, der_bytes));
+        self
+    }
+
+    /// Returns the body of the message exactly as it will be written, which
+    /// is the content to sign before calling [`MessageBuilder::pgp_signed`].
+    /// MIME boundaries are generated by this call and reused afterwards.
+    pub fn pgp_signed_content(&mut self) -> io::Result<Vec<u8>> {
+        let mut body = self.take_body();
+        body.assign_boundaries(&self.write_options)?;
+        let mut output = Vec::new();
+        body.clone()
+            .write_part_with_options(&mut output, &self.write_options)?;
+        self.body = Some(body);
+        Ok(output)
+    }
+
+    /// Wrap the body of the message in an RFC 3156 multipart/signed part,
+    /// followed by the given armored OpenPGP signature of the bytes returned
+    /// by [`MessageBuilder::pgp_signed_content`], which are preserved
+    /// exactly.
+    pub fn pgp_signed(mut self, signature: Vec<u8>) -> io::Result<Self> {
+        let content = self.pgp_signed_content()?;
+        self.body = Some(MimePart::new(
+            ContentType::new("multipart/signed")
+                .attribute("protocol", "application/pgp-signature")
+                .attribute("micalg", "pgp-sha256"),
+            vec![
+                MimePart::raw(content),
+                MimePart::new("application/pgp-signature", signature),
+            ],
+        ));
+        Ok(self)
+    }
+
+    /// Replace the body of the message with an RFC 3156 multipart/encrypted
+    /// part holding the given armored OpenPGP ciphertext. Top-level headers
+    /// are kept.
+    pub fn pgp_encrypted(mut self, ciphertext: Vec<u8>) -> Self {
+        self.text_body = None;
+        self.html_body = None;
+        self.attachments = None;
+        self.inline_images = None;
+        self.calendar_body = None;
+        self.alternative_bodies = None;
+        self.body = Some(MimePart::new(
+            ContentType::new("multipart/encrypted")
+                .attribute("protocol", "application/pgp-encrypted"),
+            vec![
+                MimePart::new("application/pgp-encrypted", b"Version: 1\r\n".to_vec()),
+                MimePart::new("application/octet-stream", ciphertext)
+                    .attachment("encrypted.asc")
+                    .inline(),
+            ],
+        ));
+        self
+    }
+
+    /// Write the listed headers first and in the given order, including the
+    /// generated Message-ID and Date headers. Headers not in the list follow
+    /// in insertion order and listed names that are not present are skipped.
+    pub fn header_order(mut self, names: Vec<String>) -> Self {
+        self.header_order = Some(names);
+        self
+    }
+
+    /// Declare the headers to be signed with DKIM, in the order they will be
+    /// listed in the "h=" tag of the DKIM-Signature header.
+    pub fn sign_headers(mut self, names: Vec<String>) -> Self {
+        self.sign_headers = Some(names);
+        self
+    }
+
+    /// Returns the headers declared with `sign_headers` as lowercased name
+    /// and unfolded value pairs, canonicalized with the DKIM "relaxed"
+    /// algorithm and in the order they are hashed. When a name is listed
+    /// more than once, instances are selected from the last one upwards as
+    /// required by RFC 6376; names that are not present are skipped.
+    ///
+    /// Headers are taken from the header block exactly as `write_to` will
+    /// write it, so generated headers such as Message-ID, Date, MIME-Version
+    /// and the Content-Type of the top-level part are included. These
+    /// headers and all MIME boundaries are generated by this call and reused
+    /// when the message is written afterwards. Returns an error when the
+    /// headers cannot be written.
+    pub fn canonicalized_headers_for_signing(&mut self) -> io::Result<Vec<(String, String)>> {
+        let header_block = self.frozen_header_block()?;
+        let fields = header_fields(&header_block);
+        let mut is_signed = vec![false; fields.len()];
+        let mut result = Vec::new();
+        for name in self.sign_headers.iter().flatten() {
+            let Some((pos, (name, value))) = fields
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(pos, _)| !is_signed[*pos])
+                .find_map(|(pos, field)| Some((pos, header_field(field, name)?)))
+            else {
+                continue;
+            };
+            is_signed[pos] = true;
 
-        for (header_name, header_value) in &self.headers {
-            if !has_date && header_name == "Date" {
-                has_date = true;
-            } else if !has_message_id && header_name == "Message-ID" {
-                has_message_id = true;
+            let header = canonicalize_header(name, value, Canonicalization::Relaxed);
+            let header = String::from_utf8_lossy(&header);
+            if let Some((name, value)) = header.trim_end_matches("\r\n").split_once(':') {
+                result.push((name.to_string(), value.to_string()));
             }
+        }
+        Ok(result)
+    }
 
-            output.write_all(header_name.as_bytes())?;
-            output.write_all(b": ")?;
-            header_value.write_header(&mut output, header_name.len() + 2)?;
+    /// Returns a SHA-256 digest of the named headers, the Content-Type
+    /// without its boundary and number of children of each multipart/* part,
+    /// and the Content-Type, Content-Disposition and decoded contents of each
+    /// leaf part, for deduplicating messages or deriving idempotency keys
+    /// without writing them.
+    ///
+    /// Headers are hashed in the order given, each instance canonicalized
+    /// with the DKIM "relaxed" algorithm, so the digest does not depend on
+    /// the order headers were added to the builder or on line folding.
+    /// Names that are not present are skipped. Parts are hashed before any
+    /// Content-Transfer-Encoding is applied and generated headers such as
+    /// Message-ID or Date are not included.
+    ///
+    /// Returns an error if a hashed header has an invalid name or cannot be
+    /// written, or if the Content-Type of a multipart/* part is neither a
+    /// structured content type nor a raw value.
+    #[cfg(feature = "sha2")]
+    pub fn content_hash(&self, headers: &[&str]) -> io::Result<[u8; 32]> {
+        use sha2::{Digest, Sha256};
+
+        fn hash_header(hasher: &mut Sha256, name: &str, value: &HeaderType<'_>) -> io::Result<()> {
+            validate_header_name(name)?;
+            let mut raw_value = b" ".to_vec();
+            value.write_header(&mut raw_value, name.len() + 2)?;
+            hasher.update(canonicalize_header(
+                name,
+                &raw_value,
+                Canonicalization::Relaxed,
+            ));
+            Ok(())
+        }
+
+        let mut hasher = Sha256::new();
+        for name in headers {
+            for (header_name, value) in self
+                .headers
+                .iter()
+                .filter(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            {
+                hash_header(&mut hasher, header_name, value)?;
+            }
         }
+        hasher.update(b"\r\n");
 
-        if !has_message_id {
-            output.write_all(b"Message-ID: ")?;
-            generate_message_id_header(
-                &mut output,
+        for part in self.parts() {
+            if let BodyPart::Multipart(children) = &part.contents {
+                // Boundaries are excluded as they may be random, the number of
+                // children is included so that the tree shape is hashed.
+                let content_type = part
+                    .headers
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"));
+                if let Some((name, value)) = content_type {
+                    let mut content_type = match value {
+                        HeaderType::ContentType(ct) => ct.clone(),
+                        HeaderType::Raw(raw) => ContentType::parse(&raw.raw),
+                        _ => return Err(Error::UnsupportedContentType.into()),
+                    };
+                    content_type.remove_attribute("boundary");
+                    hash_header(&mut hasher, name, &content_type.into())?;
+                }
+                hasher.update((children.len() as u64).to_be_bytes());
+                continue;
+            }
+            let Some(contents) = part.body_bytes() else {
+                continue;
+            };
+            for name in ["Content-Type", "Content-Disposition"] {
+                for (header_name, value) in part
+                    .headers
+                    .iter()
+                    .filter(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
                 {
+                    hash_header(&mut hasher, header_name, value)?;
+                }
+            }
+            hasher.update((contents.len() as u64).to_be_bytes());
+            hasher.update(contents);
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// Returns the Content-IDs referenced by `cid:` URLs in the HTML parts
+    /// of the message, as found by [`html::cid_references`], that do not
+    /// match the Content-ID of any part, such as inline images that were
+    /// never added.
+    pub fn dangling_cid_references(&self) -> Vec<String> {
+        let parts = self.parts();
+        let mut content_ids = Vec::new();
+        for (_, value) in parts
+            .iter()
+            .flat_map(|part| &part.headers)
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Content-ID"))
+        {
+            match value {
+                HeaderType::MessageId(id) => content_ids.extend(id.id.iter().map(|id| id.as_ref())),
+                HeaderType::Raw(Raw { raw: id }) | HeaderType::Text(Text { text: id }) => {
+                    content_ids.push(id.trim().trim_start_matches('<').trim_end_matches('>'))
+                }
+                _ => (),
+            }
+        }
+
+        let mut dangling = Vec::new();
+        for part in parts {
+            let (Some(ct), Some(html)) = (part.content_type(), part.body_bytes()) else {
+                continue;
+            };
+            if !ct.c_type.eq_ignore_ascii_case("text/html") {
+                continue;
+            }
+            for cid in html::cid_references(&String::from_utf8_lossy(html)) {
+                if !content_ids.contains(&cid.as_str()) && !dangling.contains(&cid) {
+                    dangling.push(cid);
+                }
+            }
+        }
+        dangling
+    }
+
+    /// Returns all parts of the message, including multipart/* parts and
+    /// their children, in depth-first order.
+    fn parts(&self) -> Vec<&MimePart<'x>> {
+        let mut stack = [
+            &self.body,
+            &self.text_body,
+            &self.html_body,
+            &self.calendar_body,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(
+            self.alternative_bodies
+                .iter()
+                .flatten()
+                .map(|(_, part)| part),
+        )
+        .chain(self.inline_images.iter().flatten())
+        .chain(self.attachments.iter().flatten())
+        .collect::<Vec<_>>();
+        stack.reverse();
+
+        let mut parts = Vec::with_capacity(stack.len());
+        while let Some(part) = stack.pop() {
+            if let BodyPart::Multipart(children) = &part.contents {
+                stack.extend(children.iter().rev());
+            }
+            parts.push(part);
+        }
+        parts
+    }
+
+    /// Produce reproducible output: MIME boundaries are generated from a
+    /// fixed sequence and, unless set explicitly, the Message-ID and Date
+    /// headers receive fixed values. Intended for snapshot testing.
+    pub fn deterministic(mut self) -> Self {
+        self.write_options.deterministic = true;
+        self
+    }
+
+    /// Return errors instead of silently fixing invalid messages, see
+    /// [`Error`] for the list of checks.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.write_options.strict = strict;
+        self
+    }
+
+    /// Set the options used when writing the message.
+    pub fn write_options(mut self, options: WriteOptions) -> Self {
+        self.write_options = options;
+        self
+    }
+
+    /// Add the Message-ID and Date headers when they are not set.
+    fn add_generated_headers(&mut self) {
+        let headers = &mut self.headers;
+
+        if !headers.iter().any(|(name, _)| name == "Message-ID") {
+            let message_id = if self.write_options.deterministic {
+                format!("{}@localhost", make_deterministic_boundary(".", 0))
+            } else {
+                format!("{}@{}", make_boundary("."), {
                     #[cfg(not(target_arch = "wasm32"))]
-                    { gethostname::gethostname().to_str().unwrap_or("localhost") }
-                
-                    #[cfg(target_arch = "wasm32")]                
-                    { "localhost" }
-                },
-            )?;
-            output.write_all(b"\r\n")?;
+                    {
+                        gethostname::gethostname()
+                            .to_str()
+                            .unwrap_or("localhost")
+                            .to_string()
+                    }
+
+                    #[cfg(target_arch = "wasm32")]
+                    {
+                        "localhost"
+                    }
+                })
+            };
+            headers.push(("Message-ID".into(), MessageId::new(message_id).into()));
+        }
+
+        if !headers.iter().any(|(name, _)| name == "Date") {
+            let date = if self.write_options.deterministic {
+                Date::new(0)
+            } else {
+                Date::now()
+            };
+            headers.push(("Date".into(), date.into()));
+        }
+    }
+
+    /// Build the message.
+    pub fn write_to(mut self, mut output: impl Write) -> io::Result<()> {
+        self.add_generated_headers();
+        let body = self.take_body();
+        let headers = self.message_headers(&body);
+        self.write_message_headers(&headers, &mut output)?;
+
+        if writes_mime_version(&headers, &body) {
+            body.write_as_top_level_message_with_options(output, &self.write_options)?;
+        } else {
+            body.write_part_with_options(output, &self.write_options)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the message headers written before the headers of `body`,
+    /// skipping those replaced by a header of `body` with the same name and
+    /// sorted as set with `header_order`.
+    fn message_headers<'y>(
+        &'y self,
+        body: &MimePart<'x>,
+    ) -> Vec<&'y (Cow<'x, str>, HeaderType<'x>)> {
+        let mut headers = self
+            .headers
+            .iter()
+            .filter(|(name, _)| {
+                !body
+                    .headers
+                    .iter()
+                    .any(|(part_name, _)| part_name.eq_ignore_ascii_case(name))
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(header_order) = &self.header_order {
+            headers.sort_by_key(|(name, _)| {
+                header_order
+                    .iter()
+                    .position(|ordered| ordered.eq_ignore_ascii_case(name))
+                    .unwrap_or(header_order.len())
+            });
         }
+        headers
+    }
 
-        if !has_date {
-            output.write_all(b"Date: ")?;
-            output.write_all(Date::now().to_rfc822().as_bytes())?;
-            output.write_all(b"\r\n")?;
+    /// Write the message headers returned by `message_headers`, enforcing
+    /// the header limits and strict mode checks of the write options.
+    fn write_message_headers(
+        &self,
+        headers: &[&(Cow<'x, str>, HeaderType<'x>)],
+        mut output: impl Write,
+    ) -> io::Result<()> {
+        if headers.len() > self.write_options.max_headers {
+            return Err(Error::TooManyHeaders("message".into()).into());
         }
 
-        self.write_body(output)
+        let mut header_block = Vec::with_capacity(1024);
+        for (header_name, header_value) in headers {
+            if self.write_options.strict
+                && matches!(header_value, HeaderType::Address(address) if address.has_empty_name())
+            {
+                return Err(Error::EmptyDisplayName.into());
+            }
+            #[cfg(feature = "log")]
+            log::trace!("Writing header {:?}", header_name);
+            write_header_name(header_name, &mut header_block)?;
+            header_value.write_header_with_fold(
+                &mut header_block,
+                header_name.len() + 2,
+                self.write_options.fold(),
+            )?;
+            if header_block.len() > self.write_options.max_header_size {
+                return Err(Error::HeadersTooLarge("message".into()).into());
+            }
+        }
+        output.write_all(&header_block)
     }
 
     /// Write the message body without headers.
-    pub fn write_body(self, output: impl Write) -> io::Result<()> {
-        (if let Some(body) = self.body {
-            body
-        } else {
-            match (self.text_body, self.html_body, self.attachments) {
-                (Some(text), Some(html), Some(attachments)) => {
-                    let mut parts = Vec::with_capacity(attachments.len() + 1);
-                    parts.push(MimePart::new("multipart/alternative", vec![text, html]));
-                    parts.extend(attachments);
+    pub fn write_body(mut self, output: impl Write) -> io::Result<()> {
+        self.take_body()
+            .write_part_with_options(output, &self.write_options)?;
 
-                    MimePart::new("multipart/mixed", parts)
-                }
-                (Some(text), Some(html), None) => {
-                    MimePart::new("multipart/alternative", vec![text, html])
-                }
-                (Some(text), None, Some(attachments)) => {
-                    let mut parts = Vec::with_capacity(attachments.len() + 1);
-                    parts.push(text);
-                    parts.extend(attachments);
-                    MimePart::new("multipart/mixed", parts)
-                }
-                (Some(text), None, None) => text,
-                (None, Some(html), Some(attachments)) => {
-                    let mut parts = Vec::with_capacity(attachments.len() + 1);
-                    parts.push(html);
-                    parts.extend(attachments);
-                    MimePart::new("multipart/mixed", parts)
+        Ok(())
+    }
+
+    /// Take the text, HTML, attachment and inline image parts and combine
+    /// them into the MIME tree written as the message body.
+    fn take_body(&mut self) -> MimePart<'x> {
+        let mut body = self.body.take();
+        let mut html_body = self.html_body.take();
+        let mut attachments = self.attachments.take();
+        if self.layout == Layout::Interleaved && body.is_none() {
+            let inline_images = self.inline_images.take().unwrap_or_default();
+            let num_images = inline_images.len();
+            let mut parts = inline_images
+                .into_iter()
+                .chain(attachments.take().unwrap_or_default())
+                .enumerate()
+                .map(|(pos, part)| match part.filename() {
+                    Some(_) => part.inline(),
+                    None => {
+                        let filename = default_filename(&part, pos + 1);
+                        part.attachment(filename).inline()
+                    }
+                })
+                .collect::<Vec<_>>();
+            let mut images = parts.drain(..num_images).collect::<Vec<_>>();
+            let mut interleaved = Vec::with_capacity(parts.len() + num_images);
+            if let Some(html) = html_body.take() {
+                let (segments, unreferenced) = interleave_images(html, images);
+                let mut segments = segments.into_iter();
+                html_body = segments.next();
+                interleaved.extend(segments);
+                images = unreferenced;
+            }
+            interleaved.extend(images);
+            interleaved.extend(parts);
+            if !interleaved.is_empty() {
+                attachments = Some(interleaved);
+            }
+        }
+        if let Some(inline_images) = self.inline_images.take() {
+            let is_html = |part: &MimePart| {
+                part.content_type()
+                    .is_some_and(|ct| ct.c_type.eq_ignore_ascii_case("text/html"))
+            };
+            let related = |html: MimePart<'x>| {
+                let mut parts = Vec::with_capacity(inline_images.len() + 1);
+                parts.push(html);
+                parts.extend(inline_images.iter().cloned());
+                MimePart::new("multipart/related", parts)
+            };
+            match (body.take(), html_body.take()) {
+                (None, Some(html)) => html_body = Some(related(html)),
+                (Some(html), _) if is_html(&html) => body = Some(related(html)),
+                (custom_body, html) => {
+                    body = custom_body;
+                    html_body = html;
+                    attachments
+                        .get_or_insert_with(Vec::new)
+                        .extend(inline_images.iter().cloned());
                 }
-                (None, Some(html), None) => html,
-                (None, None, Some(attachments)) => MimePart::new("multipart/mixed", attachments),
-                (None, None, None) => MimePart::new("text/plain", "\n"),
             }
-        })
-        .write_part(output)?;
+        }
 
+        if let Some(body) = body {
+            return body;
+        }
+
+        // Alternatives are ordered from the least to the most preferred.
+        let mut alternatives = self
+            .alternative_bodies
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(position, part)| {
+                let rank = match position {
+                    AlternativePosition::BeforeText => 0,
+                    AlternativePosition::BeforeHtml => 2,
+                    AlternativePosition::AfterHtml => 4,
+                };
+                (rank, part)
+            })
+            .chain(
+                [
+                    (1, self.text_body.take()),
+                    (3, html_body),
+                    (5, self.calendar_body.take()),
+                ]
+                .into_iter()
+                .filter_map(|(rank, part)| Some((rank, part?))),
+            )
+            .collect::<Vec<_>>();
+        alternatives.sort_by_key(|(rank, _)| *rank);
+        let mut alternatives = alternatives
+            .into_iter()
+            .map(|(_, part)| part)
+            .collect::<Vec<_>>();
+        let body = match alternatives.len() {
+            0 | 1 => alternatives.pop(),
+            _ => Some(MimePart::new("multipart/alternative", alternatives)),
+        };
+        match (body, attachments) {
+            (Some(body), Some(attachments)) => {
+                let mut parts = Vec::with_capacity(attachments.len() + 1);
+                parts.push(body);
+                parts.extend(attachments);
+                MimePart::new("multipart/mixed", parts)
+            }
+            (Some(body), None) => body,
+            (None, Some(attachments)) => MimePart::new("multipart/mixed", attachments),
+            // Header-only message: just the blank line ending the headers.
+            (None, None) => MimePart::raw("\r\n"),
+        }
+    }
+
+    /// Generate the Message-ID and Date headers, if missing, and all MIME
+    /// boundaries, so that the message is written identically every time.
+    fn freeze(&mut self) -> io::Result<()> {
+        self.add_generated_headers();
+        let mut body = self.take_body();
+        body.assign_boundaries(&self.write_options)?;
+        self.body = Some(body);
         Ok(())
     }
 
+    /// Freeze the message and return its header block exactly as `write_to`
+    /// will write it, including the headers of the top-level MIME part but
+    /// not the blank line that ends them. Only the headers are written, the
+    /// body is not encoded.
+    fn frozen_header_block(&mut self) -> io::Result<Vec<u8>> {
+        self.freeze()?;
+        let body = self.take_body();
+        let mut output = Vec::new();
+        let headers = self.message_headers(&body);
+        let result = self
+            .write_message_headers(&headers, &mut output)
+            .and_then(|_| {
+                if writes_mime_version(&headers, &body) && !body.is_top_level_message() {
+                    output.write_all(b"MIME-Version: 1.0\r\n")?;
+                }
+                body.write_headers_with_options(&mut output, &self.write_options)
+            });
+        self.body = Some(body);
+        result.map(|_| output)
+    }
+
+    /// Write the body of the message, exactly as `write_to` will write it,
+    /// canonicalized as described in RFC 6376 for computing DKIM body hashes.
+    ///
+    /// The Message-ID and Date headers and all MIME boundaries are generated
+    /// by this call and reused when the message is written afterwards.
+    pub fn write_canonical_body(
+        &mut self,
+        output: impl Write,
+        canonicalization: Canonicalization,
+    ) -> io::Result<()> {
+        self.freeze()?;
+        let body = self.take_body();
+        // The message header block always ends with a CRLF, so the body
+        // starts after the first empty line following it.
+        let mut message = b"\r\n".to_vec();
+        let result = body
+            .clone()
+            .write_part_with_options(&mut message, &self.write_options);
+        self.body = Some(body);
+        result?;
+
+        let body_start = message
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map_or(message.len(), |pos| pos + 4);
+        canonicalize_body(&message[body_start..], canonicalization, output)
+    }
+
+    /// Returns the last header with the given name, exactly as `write_to`
+    /// will write it, canonicalized as described in RFC 6376 for DKIM
+    /// signing. Headers of the top-level MIME part, such as Content-Type,
+    /// are included.
+    ///
+    /// The Message-ID and Date headers and all MIME boundaries are generated
+    /// by this call and reused when the message is written afterwards.
+    pub fn canonical_header(
+        &mut self,
+        name: &str,
+        canonicalization: Canonicalization,
+    ) -> io::Result<Option<String>> {
+        let header_block = self.frozen_header_block()?;
+        Ok(header_fields(&header_block)
+            .into_iter()
+            .rev()
+            .find_map(|field| header_field(field, name))
+            .map(|(name, value)| {
+                String::from_utf8_lossy(&canonicalize_header(name, value, canonicalization))
+                    .into_owned()
+            }))
+    }
+
     /// Build message to a Vec<u8>.
     pub fn write_to_vec(self) -> io::Result<Vec<u8>> {
         let mut output = Vec::new();
@@ -475,47 +1497,434 @@ impl<'x> MessageBuilder<'x> {
         Ok(output)
     }
 
+    /// Build the message for an SMTP DATA command: lines starting with "."
+    /// are dot-stuffed and the data is terminated with "CRLF.CRLF".
+    pub fn write_smtp_data(self, output: impl Write) -> io::Result<()> {
+        let mut output = DotStuffingWriter::new(output);
+        self.write_to(&mut output)?;
+        output.finish().map(|_| ())
+    }
+
+    /// Build the message and its SMTP envelope for sending with lettre.
+    /// The envelope sender is taken from the Sender header or, when missing,
+    /// from the first From address, and the recipients from the To, Cc and
+    /// Bcc headers, including group members. Bcc headers are not written
+    /// to the message.
+    #[cfg(feature = "lettre")]
+    pub fn into_lettre(self) -> Result<(lettre::address::Envelope, Vec<u8>), Error> {
+        let (from, to, message) = self.into_envelope()?;
+        let parse = |email: String| {
+            email
+                .parse::<lettre::Address>()
+                .map_err(|_| Error::InvalidAddress(email))
+        };
+        let envelope = lettre::address::Envelope::new(
+            from.map(parse).transpose()?,
+            to.into_iter().map(parse).collect::<Result<_, _>>()?,
+        )
+        .map_err(|_| Error::NoRecipients)?;
+        Ok((envelope, message))
+    }
+
+    /// Returns the envelope sender and recipients of the message, as
+    /// described in [`MessageBuilder::into_lettre`], and builds it without
+    /// its Bcc headers.
+    #[cfg(any(feature = "lettre", feature = "mail-send"))]
+    #[allow(clippy::type_complexity)]
+    fn into_envelope(mut self) -> io::Result<(Option<String>, Vec<String>, Vec<u8>)> {
+        let mut from = None;
+        let mut to = Vec::new();
+        for name in ["Sender", "From"] {
+            if from.is_none() {
+                from = envelope_addresses(&self.headers, name)?.into_iter().next();
+            }
+        }
+        for name in ["To", "Cc", "Bcc"] {
+            to.extend(envelope_addresses(&self.headers, name)?);
+        }
+
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case("Bcc"));
+        let message = self.write_to_vec()?;
+        Ok((from, to, message))
+    }
+
     /// Build message to a String.
     pub fn write_to_string(self) -> io::Result<String> {
         let mut output = Vec::new();
         self.write_to(&mut output)?;
-        String::from_utf8(output).map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        String::from_utf8(output).map_err(io::Error::other)
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Build the message in a normalized form intended for comparing it
+    /// against golden files in tests, not for sending: headers are sorted by
+    /// name and unfolded onto single lines, boundaries are replaced with
+    /// `BOUNDARY-1`, `BOUNDARY-2`... in the order their parts appear, and
+    /// the Date header is replaced with a placeholder unless it was set
+    /// explicitly. Message-IDs are generated as in [`MessageBuilder::deterministic`].
+    pub fn write_normalized_to(mut self, mut output: impl Write) -> io::Result<()> {
+        if !self
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("Date"))
+        {
+            self.headers.push(("Date".into(), Raw::new("DATE").into()));
+        }
+        self.write_options.deterministic = true;
+        self.freeze()?;
+        if let Some(body) = &mut self.body {
+            number_boundaries(body, &mut 0);
+        }
+        let message = self.write_to_vec()?;
 
-    use mail_parser::MessageParser;
+        // Normalize the message header block and the header block following
+        // each boundary delimiter line.
+        let mut rest = message.as_slice();
+        let mut is_header_block = true;
+        loop {
+            if is_header_block {
+                let block_len = if rest.starts_with(b"\r\n") {
+                    0
+                } else {
+                    find_bytes(rest, b"\r\n\r\n").unwrap_or(rest.len())
+                };
+                output.write_all(&normalize_header_block(&rest[..block_len]))?;
+                rest = &rest[block_len..];
+            }
 
-    use crate::{
-        headers::{address::Address, url::URL},
-        mime::MimePart,
-        MessageBuilder,
+            let Some(start) = find_bytes(rest, b"\r\n--BOUNDARY-").map(|pos| pos + 14) else {
+                break;
+            };
+            let digits = rest[start..]
+                .iter()
+                .take_while(|ch| ch.is_ascii_digit())
+                .count();
+            output.write_all(&rest[..start + digits])?;
+            rest = &rest[start + digits..];
+            // Closing delimiters are followed by "--" instead of a part.
+            is_header_block = rest.starts_with(b"\r\n");
+            if is_header_block {
+                output.write_all(b"\r\n")?;
+                rest = &rest[2..];
+            }
+        }
+        output.write_all(rest)
+    }
+
+    /// Build the message and save it as an `.eml` file, returning the path
+    /// written to. The `.eml` extension is added when the path has none and
+    /// missing parent directories are created when `create_dirs` is set.
+    /// The message is always written with CRLF line endings.
+    pub fn save_eml(self, path: impl AsRef<Path>, create_dirs: bool) -> io::Result<PathBuf> {
+        let mut path = path.as_ref().to_path_buf();
+        if path.extension().is_none() {
+            path.set_extension("eml");
+        }
+        if create_dirs {
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let mut output = io::BufWriter::new(fs::File::create(&path)?);
+        self.write_to(&mut output)?;
+        output.flush()?;
+        Ok(path)
+    }
+}
+
+/// Splits an HTML body after each `<img>` tag referencing one of `images`
+/// by Content-ID and returns the HTML segments alternating with the images
+/// in the order they are referenced, followed by the images that are not
+/// referenced. Each segment keeps the headers of the HTML body.
+fn interleave_images<'x>(
+    html: MimePart<'x>,
+    mut images: Vec<MimePart<'x>>,
+) -> (Vec<MimePart<'x>>, Vec<MimePart<'x>>) {
+    let BodyPart::Text(text) = &html.contents else {
+        return (vec![html], images);
     };
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (end, cid) in html::cid_image_tags(text) {
+        let Some(pos) = images
+            .iter()
+            .position(|image| image.content_id() == Some(cid.as_str()))
+        else {
+            continue;
+        };
+        let mut segment = MimePart::raw(text[start..end].to_string());
+        segment.headers = html.headers.clone();
+        parts.push(segment);
+        parts.push(images.remove(pos));
+        start = end;
+    }
+    if parts.is_empty() {
+        return (vec![html], images);
+    }
+    if !text[start..].trim().is_empty() {
+        let mut segment = MimePart::raw(text[start..].to_string());
+        segment.headers = html.headers;
+        parts.push(segment);
+    }
+    (parts, images)
+}
 
-    #[test]
-    fn build_nested_message() {
-        let output = MessageBuilder::new()
-            .from(Address::new_address("John Doe".into(), "john@doe.com"))
-            .to(Address::new_address("Jane Doe".into(), "jane@doe.com"))
-            .subject("RFC 8621 Section 4.1.4 test")
-            .body(MimePart::new(
-                "multipart/mixed",
-                vec![
-                    MimePart::new("text/plain", "Part A contents go here...").inline(),
-                    MimePart::new(
-                        "multipart/mixed",
-                        vec![
-                            MimePart::new(
-                                "multipart/alternative",
-                                vec![
-                                    MimePart::new(
-                                        "multipart/mixed",
-                                        vec![
-                                            MimePart::new(
-                                                "text/plain",
-                                                "Part B contents go here...",
+/// Returns a file name for an unnamed part, such as "image1.png", made of its
+/// main type, its number and its subtype.
+fn default_filename(part: &MimePart<'_>, number: usize) -> String {
+    let (main_type, subtype) = part
+        .content_type_str()
+        .and_then(|c_type| c_type.split_once('/'))
+        .unwrap_or(("part", "bin"));
+    let subtype = subtype
+        .split('+')
+        .next()
+        .unwrap_or(subtype)
+        .trim_start_matches("x-");
+    format!("{}{}.{}", main_type, number, subtype)
+}
+
+/// Removes any leading reply or forward prefixes (such as "Re:" or
+/// "RE :") matching the given tags from a subject.
+fn strip_subject_prefixes<'a>(mut subject: &'a str, tags: &[&str]) -> &'a str {
+    'outer: loop {
+        subject = subject.trim_start();
+        for tag in tags {
+            if let Some(rest) = subject
+                .get(..tag.len())
+                .filter(|prefix| prefix.eq_ignore_ascii_case(tag))
+                .and_then(|_| subject[tag.len()..].trim_start().strip_prefix(':'))
+            {
+                subject = rest;
+                continue 'outer;
+            }
+        }
+        return subject;
+    }
+}
+
+/// Returns the e-mail addresses of all headers with the given name,
+/// including group members.
+#[cfg(any(feature = "lettre", feature = "mail-send"))]
+fn envelope_addresses(
+    headers: &[(Cow<'_, str>, HeaderType<'_>)],
+    name: &str,
+) -> Result<Vec<String>, Error> {
+    fn collect(address: &Address<'_>, result: &mut Vec<String>) {
+        match address {
+            Address::Address(address) => result.push(address.email.trim().to_string()),
+            Address::Group(group) => {
+                for address in &group.addresses {
+                    collect(address, result);
+                }
+            }
+            Address::List(list) => {
+                for address in list {
+                    collect(address, result);
+                }
+            }
+        }
+    }
+
+    let mut result = Vec::new();
+    for (_, value) in headers
+        .iter()
+        .filter(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+    {
+        if let HeaderType::Address(address) = value {
+            collect(address, &mut result);
+        } else {
+            let mut raw_value = Vec::new();
+            let _ = value.write_header(&mut raw_value, 0);
+            return Err(Error::InvalidAddress(
+                String::from_utf8_lossy(&raw_value).trim().to_string(),
+            ));
+        }
+    }
+    Ok(result)
+}
+
+/// Sorts the fields of a header block by name, keeping the relative order of
+/// fields with the same name, and unfolds their values.
+fn normalize_header_block(block: &[u8]) -> Vec<u8> {
+    let mut fields: Vec<Vec<u8>> = Vec::new();
+    for line in block.split(|&ch| ch == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        match fields.last_mut() {
+            Some(field) if line.starts_with(b" ") || line.starts_with(b"\t") => {
+                field.truncate(field.trim_ascii_end().len());
+                field.push(b' ');
+                field.extend_from_slice(line.trim_ascii());
+            }
+            _ if line.is_empty() => (),
+            _ => fields.push(line.to_vec()),
+        }
+    }
+    fields.sort_by_cached_key(|field| {
+        let name_len = field
+            .iter()
+            .position(|&ch| ch == b':')
+            .unwrap_or(field.len());
+        field[..name_len].to_ascii_lowercase()
+    });
+    fields.join(&b"\r\n"[..])
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Replaces the boundaries of a frozen MIME tree with `BOUNDARY-1`,
+/// `BOUNDARY-2`... in the order their parts appear.
+fn number_boundaries(part: &mut MimePart<'_>, count: &mut usize) {
+    let BodyPart::Multipart(parts) = &mut part.contents else {
+        return;
+    };
+    *count += 1;
+    if let Some((_, HeaderType::ContentType(ct))) = part
+        .headers
+        .iter_mut()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+    {
+        if let Some((_, boundary)) = ct
+            .attributes
+            .iter_mut()
+            .find(|(name, _)| name.eq_ignore_ascii_case("boundary"))
+        {
+            *boundary = format!("BOUNDARY-{}", count).into();
+        }
+    }
+    for part in parts {
+        number_boundaries(part, count);
+    }
+}
+
+/// Allows passing a [`MessageBuilder`] to the `send` methods of a
+/// `mail-send` SMTP client. The envelope is extracted as described in
+/// [`MessageBuilder::into_lettre`] and Bcc headers are not written to the
+/// message.
+#[cfg(feature = "mail-send")]
+impl<'x> mail_send::smtp::message::IntoMessage<'x> for MessageBuilder<'_> {
+    fn into_message(self) -> mail_send::Result<mail_send::smtp::message::Message<'x>> {
+        let (from, to, message) = self.into_envelope().map_err(mail_send::Error::Io)?;
+        if to.is_empty() {
+            return Err(mail_send::Error::MissingRcptTo);
+        }
+
+        Ok(mail_send::smtp::message::Message {
+            mail_from: from.ok_or(mail_send::Error::MissingMailFrom)?.into(),
+            rcpt_to: to.into_iter().map(Into::into).collect(),
+            body: message.into(),
+        })
+    }
+}
+
+/// Returns the value of the first UID property of an iCalendar object,
+/// unfolding its content lines as described in RFC 5545 section 3.1.
+/// Property parameters, such as `UID;X-PARAM="a:b":value`, are skipped.
+fn ics_uid(ics: &str) -> Option<String> {
+    let unfolded = ics
+        .replace("\r\n ", "")
+        .replace("\r\n\t", "")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+    unfolded.lines().find_map(|line| {
+        let name_end = line.find([';', ':'])?;
+        if !line[..name_end].eq_ignore_ascii_case("UID") {
+            return None;
+        }
+        let mut in_quotes = false;
+        let value_start = line[name_end..].char_indices().find_map(|(pos, ch)| {
+            match ch {
+                '"' => in_quotes = !in_quotes,
+                ':' if !in_quotes => return Some(name_end + pos + 1),
+                _ => (),
+            }
+            None
+        })?;
+        Some(line[value_start..].trim().to_string()).filter(|uid| !uid.is_empty())
+    })
+}
+
+/// Splits a header block ending with a CRLF into its header fields, each
+/// including its folded continuation lines but not its final CRLF.
+fn header_fields(header_block: &[u8]) -> Vec<&[u8]> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    for (pos, window) in header_block.windows(3).enumerate() {
+        if window.starts_with(b"\r\n") && !matches!(window[2], b' ' | b'\t') {
+            fields.push(&header_block[start..pos]);
+            start = pos + 2;
+        }
+    }
+    if let Some(field) = header_block[start..].strip_suffix(b"\r\n") {
+        fields.push(field);
+    }
+    fields
+}
+
+/// Returns true when `write_to` writes the top-level MIME part `body` as a
+/// complete message, preceded by a MIME-Version header unless one is set.
+/// Raw bodies, such as the blank line of header-only messages, carry their
+/// own headers.
+fn writes_mime_version(headers: &[&(Cow<str>, HeaderType)], body: &MimePart) -> bool {
+    !body.headers.is_empty()
+        && !headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("MIME-Version"))
+}
+
+/// Splits a header field into its name and value when the name matches.
+fn header_field<'y>(field: &'y [u8], name: &str) -> Option<(&'y str, &'y [u8])> {
+    let colon = field.iter().position(|&ch| ch == b':')?;
+    let field_name = std::str::from_utf8(&field[..colon]).ok()?;
+    if field_name.eq_ignore_ascii_case(name) {
+        Some((field_name, &field[colon + 1..]))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use mail_parser::{MessageParser, MimeHeaders};
+
+    use crate::{
+        canonical::Canonicalization,
+        headers::{
+            address::Address, authentication_results::AuthenticationResults, message_id::MessageId,
+            prepend_headers, raw::Raw, received::Received, text::Text, url::URL, HeaderType,
+        },
+        mime::{BodyPart, CalendarMethod, MimePart, SmimeType, WriteOptions},
+        AlternativePosition, Error, Layout, MessageBuilder,
+    };
+
+    #[test]
+    fn build_nested_message() {
+        let output = MessageBuilder::new()
+            .from(Address::new_address("John Doe".into(), "john@doe.com"))
+            .to(Address::new_address("Jane Doe".into(), "jane@doe.com"))
+            .subject("RFC 8621 Section 4.1.4 test")
+            .body(MimePart::new(
+                "multipart/mixed",
+                vec![
+                    MimePart::new("text/plain", "Part A contents go here...").inline(),
+                    MimePart::new(
+                        "multipart/mixed",
+                        vec![
+                            MimePart::new(
+                                "multipart/alternative",
+                                vec![
+                                    MimePart::new(
+                                        "multipart/mixed",
+                                        vec![
+                                            MimePart::new(
+                                                "text/plain",
+                                                "Part B contents go here...",
                                             )
                                             .inline(),
                                             MimePart::new(
@@ -610,4 +2019,2045 @@ mod tests {
             .unwrap();
         MessageParser::new().parse(&output).unwrap();
     }
+
+    #[test]
+    fn build_deterministic_message() {
+        let build = || {
+            MessageBuilder::new()
+                .from(("John Doe", "john@doe.com"))
+                .to("jane@doe.com")
+                .subject("Deterministic output")
+                .text_body("Hello, world!\n")
+                .html_body("<p>Hello, world!</p>")
+                .attachment("image/png", "image.png", [1, 2, 3, 4].as_ref())
+                .deterministic()
+                .write_to_vec()
+                .unwrap()
+        };
+
+        let output = build();
+        assert_eq!(output, build());
+        assert!(std::str::from_utf8(&output)
+            .unwrap()
+            .contains("Date: Thu, 1 Jan 1970 00:00:00 +0000\r\n"));
+        MessageParser::new().parse(&output).unwrap();
+    }
+
+    #[test]
+    fn prepend_message_headers() {
+        let message = MessageBuilder::new()
+            .from(("John Doe", "john@doe.com"))
+            .to("jane@doe.com")
+            .subject("Hello, world!")
+            .text_body("Hello, world!")
+            .write_to_vec()
+            .unwrap();
+
+        let output = prepend_headers(
+            &message,
+            [
+                (
+                    "DKIM-Signature",
+                    HeaderType::from(Raw::new("v=1; a=rsa-sha256; d=doe.com; s=default")),
+                ),
+                ("Received", Text::new("from localhost by mx.doe.com").into()),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            std::str::from_utf8(&output)
+                .unwrap()
+                .split("\r\n")
+                .take(3)
+                .collect::<Vec<_>>(),
+            [
+                "DKIM-Signature: v=1; a=rsa-sha256; d=doe.com; s=default",
+                "Received: from localhost by mx.doe.com",
+                "From: \"John Doe\" <john@doe.com>",
+            ]
+        );
+        assert!(output.ends_with(&message));
+    }
+
+    #[test]
+    fn message_ends_with_crlf() {
+        let output = MessageBuilder::new()
+            .from(("John Doe", "john@doe.com"))
+            .to("jane@doe.com")
+            .subject("Hello, world!")
+            .text_body("Hello, world!")
+            .write_to_string()
+            .unwrap();
+        assert!(output.ends_with("\r\nHello, world!\r\n"), "{:?}", output);
+    }
+
+    #[test]
+    fn build_html_with_autotext() {
+        let output = MessageBuilder::new()
+            .from(("John Doe", "john@doe.com"))
+            .to("jane@doe.com")
+            .subject("Hello, world!")
+            .html_with_autotext(concat!(
+                "<html><body><p>Hello <b>world</b>!<br>",
+                "Read the <a href=\"https://example.com/news\">news</a> ",
+                "&amp; the <a href=\"https://example.com/blog\">blog</a>.</p></body></html>"
+            ))
+            .write_to_vec()
+            .unwrap();
+        let message = MessageParser::new().parse(&output).unwrap();
+
+        let text = message.body_text(0).unwrap();
+        assert!(text.contains("https://example.com/news"), "{}", text);
+        assert!(text.contains("https://example.com/blog"), "{}", text);
+        assert!(text.contains("& the"), "{}", text);
+        assert!(!text.contains('<'), "{}", text);
+        assert!(message
+            .body_html(0)
+            .unwrap()
+            .contains("<a href=\"https://example.com/news\">"));
+    }
+
+    #[test]
+    fn add_tracking_pixel() {
+        for (html, expected) in [
+            (
+                "<html><body><p>Hello</p></body></html>",
+                concat!(
+                    "<html><body><p>Hello</p><img src=\"https://example.com/open?id=1&amp;u=2\" ",
+                    "width=\"1\" height=\"1\" border=\"0\" alt=\"\"></body></html>"
+                ),
+            ),
+            (
+                "<p>Hello</p>",
+                concat!(
+                    "<p>Hello</p><img src=\"https://example.com/open?id=1&amp;u=2\" ",
+                    "width=\"1\" height=\"1\" border=\"0\" alt=\"\">"
+                ),
+            ),
+        ] {
+            let builder = MessageBuilder::new()
+                .html_body(html)
+                .tracking_pixel("https://example.com/open?id=1&u=2")
+                .tracking_pixel("https://example.com/open?id=1&u=2");
+            match builder.html_body.as_ref().map(|part| &part.contents) {
+                Some(BodyPart::Text(html)) => {
+                    assert_eq!(html, expected);
+                    assert_eq!(html.matches("https://example.com/open").count(), 1);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn list_headers() {
+        let output = MessageBuilder::new()
+            .list_id(("Discussion of the example project", "dev.example.com"))
+            .list_post("mailto:dev@example.com")
+            .list_archive("https://lists.example.com/archives/dev/")
+            .list_help(vec![
+                "mailto:dev-request@example.com?subject=help",
+                "https://lists.example.com/help/dev/",
+            ])
+            .text_body("Hello")
+            .write_to_string()
+            .unwrap();
+
+        for expected in [
+            "List-Id: \"Discussion of the example project\" <dev.example.com>\r\n",
+            "List-Post: <mailto:dev@example.com>\r\n",
+            "List-Archive: <https://lists.example.com/archives/dev/>\r\n",
+            concat!(
+                "List-Help: <mailto:dev-request@example.com?subject=help>,\r\n",
+                " <https://lists.example.com/help/dev/>\r\n"
+            ),
+        ] {
+            assert!(output.contains(expected), "{}", output);
+        }
+
+        let output = MessageBuilder::new()
+            .list_id("dev.example.com")
+            .text_body("Hello")
+            .write_to_string()
+            .unwrap();
+        assert!(
+            output.contains("List-Id: <dev.example.com>\r\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn save_eml() {
+        let dir = std::env::temp_dir().join(format!("mail-builder-{}", std::process::id()));
+        let path = MessageBuilder::new()
+            .from("john@doe.com")
+            .subject("Draft")
+            .text_body("Line one\nLine two\n")
+            .deterministic()
+            .save_eml(dir.join("drafts").join("draft"), true)
+            .unwrap();
+        assert_eq!(path, dir.join("drafts").join("draft.eml"));
+
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            contents,
+            MessageBuilder::new()
+                .from("john@doe.com")
+                .subject("Draft")
+                .text_body("Line one\nLine two\n")
+                .deterministic()
+                .write_to_vec()
+                .unwrap()
+        );
+        let contents = String::from_utf8(contents).unwrap();
+        assert!(
+            contents.contains("Line one\r\nLine two\r\n"),
+            "{}",
+            contents
+        );
+        assert!(!contents.replace("\r\n", "").contains('\n'));
+
+        assert!(MessageBuilder::new()
+            .text_body("Hello")
+            .save_eml(dir.join("missing").join("draft.eml"), false)
+            .is_err());
+    }
+
+    #[test]
+    fn attach_file() {
+        let dir = std::env::temp_dir().join(format!("mail-builder-attach-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.pdf");
+        std::fs::write(&path, b"%PDF-1.4 contents").unwrap();
+
+        let text_message = MessageBuilder::new()
+            .text_body("See attached.")
+            .attach_file(&path)
+            .unwrap()
+            .write_to_vec()
+            .unwrap();
+        let body_message = MessageBuilder::new()
+            .body(MimePart::new("text/plain", "See attached."))
+            .attach_file(&path)
+            .unwrap()
+            .write_to_vec()
+            .unwrap();
+        assert!(MessageBuilder::new()
+            .attach_file(dir.join("missing.pdf"))
+            .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        for output in [text_message, body_message] {
+            let message = MessageParser::new().parse(&output).unwrap();
+            assert_eq!(message.body_text(0).unwrap(), "See attached.");
+            let attachment = message.attachment(0).unwrap();
+            assert_eq!(attachment.attachment_name(), Some("report.pdf"));
+            assert_eq!(attachment.content_type().unwrap().subtype(), Some("pdf"));
+            assert_eq!(attachment.contents(), b"%PDF-1.4 contents");
+        }
+    }
+
+    #[test]
+    fn attach_text_file_charset() {
+        let dir = std::env::temp_dir().join(format!("mail-builder-charset-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = [
+            (
+                "utf8.txt",
+                "Grüße, 日本\n".as_bytes(),
+                "text/plain; charset=utf-8",
+            ),
+            (
+                "latin1.txt",
+                b"Gr\xfc\xdfe, se\xf1or\n".as_ref(),
+                "text/plain; charset=iso-8859-1",
+            ),
+            (
+                "utf16.txt",
+                b"\xff\xfeH\0i\0".as_ref(),
+                "text/plain; charset=utf-16",
+            ),
+            (
+                "binary.txt",
+                b"\0\x01\x02\xff\xfe".as_ref(),
+                "application/octet-stream",
+            ),
+        ];
+        let mut builder = MessageBuilder::new().text_body("See attached.");
+        for (name, contents, _) in files {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            builder = builder.attach_file(&path).unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let output = builder.write_to_vec().unwrap();
+        let message = MessageParser::new().parse(&output).unwrap();
+        for (pos, (name, contents, content_type)) in files.into_iter().enumerate() {
+            let attachment = message.attachment(pos).unwrap();
+            let ct = attachment.content_type().unwrap();
+            let parsed = match (ct.subtype(), ct.attribute("charset")) {
+                (Some(subtype), Some(charset)) => {
+                    format!("{}/{}; charset={}", ct.ctype(), subtype, charset)
+                }
+                (Some(subtype), None) => format!("{}/{}", ct.ctype(), subtype),
+                _ => unreachable!(),
+            };
+            assert_eq!(parsed, content_type, "{}", name);
+            assert_eq!(attachment.attachment_name(), Some(name));
+            if !content_type.starts_with("text/") {
+                assert_eq!(attachment.contents(), contents);
+            }
+        }
+    }
+
+    #[test]
+    fn reply_to_message() {
+        for subject in ["Meeting", "Re: Meeting", "RE: re: Meeting", "Re : Meeting"] {
+            let output = MessageBuilder::new()
+                .reply_to_message("original@example.com", vec!["first@example.com"], subject)
+                .text_body("Sounds good.")
+                .write_to_string()
+                .unwrap();
+
+            assert!(output.contains("Subject: Re: Meeting\r\n"), "{}", output);
+            assert!(
+                output.contains("In-Reply-To: <original@example.com>\r\n"),
+                "{}",
+                output
+            );
+            assert!(
+                output.contains("References: <first@example.com> <original@example.com>\r\n"),
+                "{}",
+                output
+            );
+        }
+
+        let output = MessageBuilder::new()
+            .reply_to_message("original@example.com", Vec::<&str>::new(), "Rebooting")
+            .text_body("Done.")
+            .write_to_string()
+            .unwrap();
+        assert!(output.contains("Subject: Re: Rebooting\r\n"), "{}", output);
+        assert!(output.contains("References: <original@example.com>\r\n"));
+    }
+
+    #[test]
+    fn embed_image() {
+        let dir = std::env::temp_dir().join(format!("mail-builder-embed-{}", std::process::id()));
+        let other_dir = dir.join("other");
+        std::fs::create_dir_all(&other_dir).unwrap();
+        let path = dir.join("logo.png");
+        let other_path = other_dir.join("logo.png");
+        std::fs::write(&path, [0x89, b'P', b'N', b'G', 0, 1, 2, 3]).unwrap();
+        std::fs::write(&other_path, [0x89, b'P', b'N', b'G', 4, 5, 6, 7]).unwrap();
+
+        let (builder, cid) = MessageBuilder::new()
+            .text_body("Hello")
+            .embed_image(&path, None)
+            .unwrap();
+        let (builder, other_cid) = builder.embed_image(&other_path, None).unwrap();
+        let (builder, copy_cid) = builder.embed_image(&path, Some("<logo-copy>")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_ne!(cid, other_cid);
+        assert_eq!(copy_cid, "cid:logo-copy");
+
+        let html = format!(
+            "<p>Hello <img src=\"{}\"><img src=\"{}\"></p>",
+            cid, other_cid
+        );
+        let output = builder.html_body(html.clone()).write_to_vec().unwrap();
+
+        let message = MessageParser::new().parse(&output).unwrap();
+        let output = std::str::from_utf8(&output).unwrap();
+        assert!(
+            output.contains("Content-Type: multipart/related;"),
+            "{}",
+            output
+        );
+        assert!(output.contains("Content-ID: <logo-copy>\r\n"), "{}", output);
+        assert_eq!(message.body_html(0).unwrap(), html);
+        assert_eq!(message.body_text(0).unwrap(), "Hello");
+        let image = message.attachment(0).unwrap();
+        assert_eq!(image.content_id(), cid.strip_prefix("cid:"));
+        assert_eq!(image.attachment_name(), Some("logo.png"));
+        assert_eq!(image.content_type().unwrap().subtype(), Some("png"));
+        assert_eq!(
+            message.attachment(1).unwrap().content_id(),
+            other_cid.strip_prefix("cid:")
+        );
+    }
+
+    #[test]
+    fn forward_message() {
+        let original = MessageBuilder::new()
+            .from("jane@doe.com")
+            .to("john@doe.com")
+            .subject("Quarterly report")
+            .text_body("Numbers are up.")
+            .write_to_vec()
+            .unwrap();
+
+        for subject in [
+            "Quarterly report",
+            "Fwd: Quarterly report",
+            "FW: fwd: Quarterly report",
+        ] {
+            let output = MessageBuilder::new()
+                .from("john@doe.com")
+                .to("bob@doe.com")
+                .forward_message(original.as_slice(), subject)
+                .text_body("See below.")
+                .write_to_vec()
+                .unwrap();
+            let text = std::str::from_utf8(&output).unwrap();
+            assert!(
+                text.contains("Subject: Fwd: Quarterly report\r\n"),
+                "{}",
+                text
+            );
+            assert!(
+                text.contains("Content-Type: message/rfc822\r\n"),
+                "{}",
+                text
+            );
+            assert!(!text.contains("base64"), "{}", text);
+
+            let message = MessageParser::new().parse(&output).unwrap();
+            let forwarded = message.attachment(0).unwrap().message().unwrap();
+            assert_eq!(forwarded.subject(), Some("Quarterly report"));
+            assert_eq!(
+                forwarded.body_text(0).unwrap().trim_end(),
+                "Numbers are up."
+            );
+        }
+    }
+
+    #[test]
+    fn reject_header_name_injection() {
+        let err = MessageBuilder::new()
+            .header("X-Data: foo\r\nX-Inject", Raw::new("evil"))
+            .text_body("Hello")
+            .write_to_vec()
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(prepend_headers(b"", [("Bad Name", Raw::new("value"))]).is_err());
+    }
+
+    #[test]
+    fn header_order() {
+        let output = MessageBuilder::new()
+            .subject("Hello")
+            .header("X-Custom", Raw::new("1"))
+            .to("jane@doe.com")
+            .message_id("id@example.com")
+            .from("john@doe.com")
+            .header_order(
+                ["From", "To", "Subject", "Date", "Cc"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+            )
+            .deterministic()
+            .text_body("Hello")
+            .write_to_string()
+            .unwrap();
+        let names = output
+            .split("\r\n\r\n")
+            .next()
+            .unwrap()
+            .split("\r\n")
+            .filter_map(|line| line.split_once(':').map(|(name, _)| name))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "From",
+                "To",
+                "Subject",
+                "Date",
+                "X-Custom",
+                "Message-ID",
+                "MIME-Version",
+                "Content-Type",
+                "Content-Transfer-Encoding"
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_empty_display_name() {
+        let output = MessageBuilder::new()
+            .from(("", "john@doe.com"))
+            .to(vec![("Jane", "jane@doe.com"), ("", "bob@doe.com")])
+            .text_body("Hello")
+            .write_to_string()
+            .unwrap();
+        assert!(output.contains("From: <john@doe.com>\r\n"), "{}", output);
+        assert!(
+            output.contains("To: \"Jane\" <jane@doe.com>, <bob@doe.com>\r\n"),
+            "{}",
+            output
+        );
+
+        let err = MessageBuilder::new()
+            .from(("", "john@doe.com"))
+            .text_body("Hello")
+            .strict(true)
+            .write_to_vec()
+            .unwrap_err();
+        assert_eq!(
+            err.get_ref()
+                .and_then(|err| err.downcast_ref::<crate::Error>()),
+            Some(&crate::Error::EmptyDisplayName)
+        );
+
+        let part = || {
+            MimePart::new("message/rfc822", "Hello").header(
+                "From",
+                crate::headers::address::Address::new_address(Some(""), "bob@doe.com"),
+            )
+        };
+        let builder = || {
+            MessageBuilder::new()
+                .from(("John", "john@doe.com"))
+                .body(MimePart::new("multipart/mixed", vec![part()]))
+        };
+        let output = builder().write_to_string().unwrap();
+        assert!(output.contains("From: <bob@doe.com>\r\n"), "{}", output);
+        let err = builder().strict(true).write_to_vec().unwrap_err();
+        assert_eq!(
+            err.get_ref()
+                .and_then(|err| err.downcast_ref::<crate::Error>()),
+            Some(&crate::Error::EmptyDisplayName)
+        );
+    }
+
+    #[test]
+    fn build_header_only_message() {
+        let output = MessageBuilder::new()
+            .from("john@doe.com")
+            .to("jane@doe.com")
+            .subject("Probe")
+            .deterministic()
+            .write_to_string()
+            .unwrap();
+        assert_eq!(
+            output,
+            concat!(
+                "From: <john@doe.com>\r\n",
+                "To: <jane@doe.com>\r\n",
+                "Subject: Probe\r\n",
+                "Message-ID: <0.0.0@localhost>\r\n",
+                "Date: Thu, 1 Jan 1970 00:00:00 +0000\r\n",
+                "\r\n"
+            )
+        );
+        let message = MessageParser::new().parse(output.as_bytes()).unwrap();
+        assert_eq!(message.subject(), Some("Probe"));
+        assert_eq!(message.body_text(0).as_deref(), Some(""));
+        assert!(message.content_type().is_none());
+    }
+
+    #[test]
+    fn add_received_headers() {
+        let output = MessageBuilder::new()
+            .from("john@doe.com")
+            .add_received(
+                Received::new(0i64)
+                    .from("mx1.example.com")
+                    .by("mx2.example.com"),
+            )
+            .add_received(
+                Received::new(60i64)
+                    .from("mx2.example.com")
+                    .by("mail.example.com"),
+            )
+            .text_body("Hello")
+            .write_to_string()
+            .unwrap();
+        assert!(
+            output.starts_with(concat!(
+                "Received: from mx2.example.com by mail.example.com;\r\n",
+                " Thu, 1 Jan 1970 00:01:00 +0000\r\n",
+                "Received: from mx1.example.com by mx2.example.com;\r\n",
+                " Thu, 1 Jan 1970 00:00:00 +0000\r\n",
+                "From: <john@doe.com>\r\n"
+            )),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn consistent_folding_whitespace() {
+        for (fold_with_tab, fold, other) in [(false, ' ', '\t'), (true, '\t', ' ')] {
+            let recipients = (0..6)
+                .map(|n| {
+                    Address::new_address(
+                        Some(format!("Recipient {}", n)),
+                        format!("r{}@example.com", n),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let output = MessageBuilder::new()
+                .from(("John Doe", "john@doe.com"))
+                .to(Address::new_list(recipients.clone()))
+                .cc(Address::new_group(
+                    Some("Undisclosed team"),
+                    recipients.clone(),
+                ))
+                .reply_to(Address::new_list(vec![
+                    Address::new_address(Some("John Doe"), "john@doe.com"),
+                    Address::new_group(
+                        Some("Team"),
+                        (0..3)
+                            .map(|n| {
+                                Address::new_address(
+                                    Some(format!(
+                                        "A recipient with a rather long display name number {}",
+                                        n
+                                    )),
+                                    format!("r{}@example.com", n),
+                                )
+                            })
+                            .collect::<Vec<_>>(),
+                    ),
+                ]))
+                .subject(
+                    "Résumé of the quarterly planning meeting held with the whole engineering team",
+                )
+                .references(MessageId::new_list((0..4).map(|n| {
+                    format!("message-{}-with-a-long-identifier@example.com", n)
+                })))
+                .list_help(URL::new_list(
+                    [
+                        "mailto:dev-request@example.com?subject=help",
+                        "https://lists.example.com/help/dev/",
+                    ]
+                    .into_iter(),
+                ))
+                .header(
+                    "X-Comment",
+                    Raw::new("a comment ".repeat(12).trim_end().to_string()),
+                )
+                .text_body("Hello world")
+                .attachment(
+                    "application/pdf",
+                    "a quite long attachment file name for the annual report.pdf",
+                    b"%PDF-1.7".to_vec(),
+                )
+                .write_options(
+                    crate::mime::WriteOptions::new()
+                        .deterministic(true)
+                        .fold_with_tab(fold_with_tab),
+                )
+                .write_to_string()
+                .unwrap();
+
+            let folded = output
+                .split("\r\n")
+                .filter(|line| line.starts_with([' ', '\t']))
+                .collect::<Vec<_>>();
+            assert!(folded.len() > 6, "{}", output);
+            for line in folded {
+                assert!(line.starts_with(fold), "{:?} in {}", line, output);
+                assert!(!line.starts_with(other), "{:?} in {}", line, output);
+            }
+        }
+    }
+
+    #[test]
+    fn header_limits() {
+        let err_of = |result: std::io::Result<Vec<u8>>| {
+            result
+                .unwrap_err()
+                .get_ref()
+                .and_then(|e| e.downcast_ref::<Error>())
+                .cloned()
+        };
+
+        let mut builder = MessageBuilder::new()
+            .from("john@doe.com")
+            .text_body("Hello");
+        for n in 0..500 {
+            builder = builder.header(format!("X-Header-{}", n), Raw::new("value"));
+        }
+        assert_eq!(
+            err_of(builder.write_to_vec()),
+            Some(Error::TooManyHeaders("message".into()))
+        );
+
+        let builder = MessageBuilder::new()
+            .from("john@doe.com")
+            .header("X-Large", Raw::new("a ".repeat(50 * 1024)))
+            .text_body("Hello");
+        assert_eq!(
+            err_of(builder.write_to_vec()),
+            Some(Error::HeadersTooLarge("message".into()))
+        );
+
+        let mut part =
+            MimePart::new("application/pdf", b"%PDF-1.7".to_vec()).attachment("report.pdf");
+        for n in 0..500 {
+            part = part.header(format!("X-Header-{}", n), Raw::new("value"));
+        }
+        let builder = MessageBuilder::new()
+            .from("john@doe.com")
+            .body(MimePart::new(
+                "multipart/mixed",
+                vec![MimePart::new("text/plain", "Hello"), part],
+            ));
+        assert_eq!(
+            err_of(builder.write_to_vec()),
+            Some(Error::TooManyHeaders("report.pdf".into()))
+        );
+
+        // A large newsletter stays well within the default limits.
+        let recipients = (0..200)
+            .map(|n| Address::new_address(None::<&str>, format!("subscriber{}@example.com", n)))
+            .collect::<Vec<_>>();
+        let mut builder = MessageBuilder::new()
+            .from(("Newsletter", "news@example.com"))
+            .bcc(Address::new_list(recipients))
+            .subject("Our monthly newsletter")
+            .list_id(("Monthly newsletter", "news.example.com"))
+            .list_help("https://example.com/help")
+            .html_body("<p>News</p>".repeat(10_000))
+            .text_body("News\n".repeat(10_000));
+        for n in 0..40 {
+            builder = builder.header(format!("X-Campaign-{}", n), Raw::new("spring-2024"));
+        }
+        for n in 0..20 {
+            builder = builder.attachment("image/png", format!("image{}.png", n), vec![0u8; 1024]);
+        }
+        builder.write_to_vec().unwrap();
+    }
+
+    #[test]
+    fn canonical_body_and_headers() {
+        let mut builder = MessageBuilder::new()
+            .from(("John Doe", "john@doe.com"))
+            .to("jane@doe.com")
+            .subject("Quarterly   report\tattached")
+            .text_body("Hello Jane,  \n\nPlease find the\treport attached.\n\n\n")
+            .html_body("<p>Hello Jane,</p>   \n<p>Please find the report attached.</p>")
+            .attachment("application/pdf", "report.pdf", b"%PDF-1.7 report".to_vec());
+
+        let mut relaxed = Vec::new();
+        builder
+            .write_canonical_body(&mut relaxed, Canonicalization::Relaxed)
+            .unwrap();
+        let mut simple = Vec::new();
+        builder
+            .write_canonical_body(&mut simple, Canonicalization::Simple)
+            .unwrap();
+        let subject = builder
+            .canonical_header("subject", Canonicalization::Relaxed)
+            .unwrap();
+        let content_type = builder
+            .canonical_header("Content-Type", Canonicalization::Simple)
+            .unwrap();
+        let message_id = builder
+            .canonical_header("Message-ID", Canonicalization::Relaxed)
+            .unwrap();
+        assert_eq!(
+            builder
+                .canonical_header("X-Missing", Canonicalization::Relaxed)
+                .unwrap(),
+            None
+        );
+
+        let output = builder.write_to_string().unwrap();
+        let (header_block, body) = output.split_once("\r\n\r\n").unwrap();
+
+        let mut lines = body
+            .split("\r\n")
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>();
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        let expected_simple = format!("{}\r\n", lines.join("\r\n"));
+        let expected_relaxed = lines
+            .iter()
+            .map(|line| {
+                let mut line = line.replace('\t', " ");
+                while line.contains("  ") {
+                    line = line.replace("  ", " ");
+                }
+                format!("{}\r\n", line.trim_end())
+            })
+            .collect::<String>();
+        assert_eq!(std::str::from_utf8(&simple).unwrap(), expected_simple);
+        assert_eq!(std::str::from_utf8(&relaxed).unwrap(), expected_relaxed);
+
+        let unfolded = header_block.replace("\r\n ", " ").replace("\r\n\t", "\t");
+        let field = |name: &str| {
+            unfolded
+                .split("\r\n")
+                .find(|line| line.starts_with(name))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(subject.unwrap(), "subject:Quarterly report attached\r\n");
+        assert_eq!(
+            message_id.unwrap(),
+            format!(
+                "message-id:{}\r\n",
+                field("Message-ID: ").trim_start_matches("Message-ID: ")
+            )
+        );
+        let content_type = content_type.unwrap();
+        assert!(content_type.starts_with("Content-Type: multipart/mixed;"));
+        assert!(
+            header_block.contains(content_type.trim_end()),
+            "{}",
+            header_block
+        );
+    }
+
+    #[test]
+    fn write_smtp_data() {
+        let mut output = Vec::new();
+        MessageBuilder::new()
+            .from("john@doe.com")
+            .subject("Dots")
+            .text_body("Hello\n.hidden line\n..\n.")
+            .write_options(crate::mime::WriteOptions::new().deterministic(true))
+            .write_smtp_data(&mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("\r\n..hidden line\r\n"), "{}", output);
+        assert!(output.ends_with("\r\n.\r\n"), "{}", output);
+        assert_eq!(
+            output
+                .split("\r\n")
+                .filter(|line| line.starts_with('.'))
+                .filter(|line| !line.starts_with(".."))
+                .count(),
+            1,
+            "{}",
+            output
+        );
+        assert!(MessageParser::new()
+            .parse(output.replace("\r\n..", "\r\n.").trim_end_matches(".\r\n"))
+            .unwrap()
+            .body_text(0)
+            .unwrap()
+            .contains(".hidden line"));
+    }
+
+    #[test]
+    fn set_header_raw() {
+        let output = MessageBuilder::new()
+            .from("john@doe.com")
+            .header("X-Spam-Score", Raw::new("9.9"))
+            .set_header_raw("Errors-To", "bounces@doe.com")
+            .unwrap()
+            .set_header_raw("x-spam-score", "0.1\r\n")
+            .unwrap()
+            .text_body("Hello")
+            .write_to_string()
+            .unwrap();
+        assert!(
+            output.contains("Errors-To: bounces@doe.com\r\n"),
+            "{}",
+            output
+        );
+        assert!(output.contains("x-spam-score: 0.1\r\n"), "{}", output);
+        assert!(!output.contains(": 9.9"), "{}", output);
+
+        for (name, value, expected) in [
+            (
+                "Return:Path",
+                "<a@b>",
+                Error::InvalidHeaderName("Return:Path".into()),
+            ),
+            (
+                "Bad\r\nName",
+                "x",
+                Error::InvalidHeaderName("Bad\r\nName".into()),
+            ),
+            ("X-Nul\0", "x", Error::InvalidHeaderName("X-Nul\0".into())),
+            (
+                "X-Injected",
+                "x\r\nBcc: victim@example.com",
+                Error::InvalidHeaderValue,
+            ),
+        ] {
+            assert_eq!(
+                MessageBuilder::new()
+                    .set_header_raw(name, value)
+                    .unwrap_err(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn smime_wrap() {
+        let output = MessageBuilder::new()
+            .from("john@doe.com")
+            .to("jane@doe.com")
+            .subject("Encrypted")
+            .text_body("Secret")
+            .attachment("application/pdf", "secret.pdf", b"%PDF-1.7".to_vec())
+            .smime_wrap(SmimeType::EnvelopedData, vec![0x30, 0x82, 0x01, 0x00])
+            .write_to_string()
+            .unwrap();
+
+        let (headers, body) = output.split_once("\r\n\r\n").unwrap();
+        for header in [
+            "From: <john@doe.com>",
+            "To: <jane@doe.com>",
+            "Subject: Encrypted",
+        ] {
+            assert!(headers.contains(header), "{}", output);
+        }
+        assert!(
+            headers.contains("smime-type=\"enveloped-data\""),
+            "{}",
+            output
+        );
+        assert!(
+            !output.contains("Secret") && !output.contains("multipart"),
+            "{}",
+            output
+        );
+        assert_eq!(body, "MIIBAA==\r\n");
+    }
+
+    #[test]
+    fn pgp_signed_and_encrypted() {
+        let signature = b"-----BEGIN PGP SIGNATURE-----\r\n\r\niQEzBAEBCAAdFiEE\r\n-----END PGP SIGNATURE-----\r\n";
+        let mut builder = MessageBuilder::new()
+            .from("john@doe.com")
+            .to("jane@doe.com")
+            .subject("Signed")
+            .text_body("Hello, this message is signed.")
+            .attachment("application/pdf", "report.pdf", b"%PDF-1.7".to_vec());
+        let content = builder.pgp_signed_content().unwrap();
+        let content = String::from_utf8(content).unwrap();
+        assert!(
+            content.starts_with("Content-Type: multipart/mixed;"),
+            "{}",
+            content
+        );
+
+        let output = builder
+            .pgp_signed(signature.to_vec())
+            .unwrap()
+            .write_to_string()
+            .unwrap();
+        let message = MessageParser::new().parse(output.as_bytes()).unwrap();
+        assert_eq!(message.content_type().unwrap().ctype(), "multipart");
+        assert_eq!(message.content_type().unwrap().subtype(), Some("signed"));
+        assert_eq!(
+            message.content_type().unwrap().attribute("protocol"),
+            Some("application/pgp-signature")
+        );
+        assert_eq!(
+            message.content_type().unwrap().attribute("micalg"),
+            Some("pgp-sha256")
+        );
+
+        // The signed part is preserved byte by byte and followed by the
+        // signature.
+        let boundary = message
+            .content_type()
+            .unwrap()
+            .attribute("boundary")
+            .unwrap();
+        let parts = output
+            .split(&format!("\r\n--{}", boundary))
+            .collect::<Vec<_>>();
+        assert_eq!(parts.len(), 4, "{}", output);
+        assert_eq!(parts[1].strip_prefix("\r\n").unwrap(), content);
+        assert!(
+            parts[2].starts_with("\r\nContent-Type: application/pgp-signature\r\n"),
+            "{}",
+            output
+        );
+        assert!(parts[2].contains("-----BEGIN PGP SIGNATURE-----"));
+
+        let output = MessageBuilder::new()
+            .from("john@doe.com")
+            .text_body("Secret")
+            .pgp_encrypted(
+                b"-----BEGIN PGP MESSAGE-----\r\n\r\nhQEMA\r\n-----END PGP MESSAGE-----\r\n"
+                    .to_vec(),
+            )
+            .write_to_string()
+            .unwrap();
+        let message = MessageParser::new().parse(output.as_bytes()).unwrap();
+        assert_eq!(message.content_type().unwrap().subtype(), Some("encrypted"));
+        assert_eq!(
+            message.content_type().unwrap().attribute("protocol"),
+            Some("application/pgp-encrypted")
+        );
+        assert!(!output.contains("Secret"), "{}", output);
+        let version = message.part(1).unwrap();
+        assert_eq!(
+            version.content_type().unwrap().subtype(),
+            Some("pgp-encrypted")
+        );
+        assert_eq!(version.text_contents(), Some("Version: 1\r\n"));
+        let encrypted = message.part(2).unwrap();
+        assert_eq!(
+            encrypted.content_type().unwrap().subtype(),
+            Some("octet-stream")
+        );
+        assert!(std::str::from_utf8(encrypted.contents())
+            .unwrap()
+            .starts_with("-----BEGIN PGP MESSAGE-----"));
+    }
+
+    #[cfg(feature = "lettre")]
+    #[test]
+    fn into_lettre() {
+        let (envelope, message) = MessageBuilder::new()
+            .from(vec!["john@doe.com", "jim@doe.com"])
+            .sender("list@doe.com")
+            .to(Address::new_group(
+                "Team".into(),
+                vec!["jane@doe.com".into(), ("Joe", "joe@doe.com").into()],
+            ))
+            .cc("jake@doe.com")
+            .bcc("hidden@doe.com")
+            .text_body("Hello")
+            .into_lettre()
+            .unwrap();
+        assert_eq!(envelope.from().unwrap().to_string(), "list@doe.com");
+        assert_eq!(
+            envelope
+                .to()
+                .iter()
+                .map(|address| address.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "jane@doe.com",
+                "joe@doe.com",
+                "jake@doe.com",
+                "hidden@doe.com"
+            ]
+        );
+        let message = String::from_utf8(message).unwrap();
+        assert!(
+            message.contains("To: \"Team\": <jane@doe.com>"),
+            "{}",
+            message
+        );
+        assert!(!message.contains("hidden@doe.com"), "{}", message);
+
+        // Bcc-only recipients and no Sender header
+        let (envelope, message) = MessageBuilder::new()
+            .from(("John", "john@doe.com"))
+            .bcc(vec!["jane@doe.com", "jim@doe.com"])
+            .text_body("Hello")
+            .into_lettre()
+            .unwrap();
+        assert_eq!(envelope.from().unwrap().to_string(), "john@doe.com");
+        assert_eq!(envelope.to().len(), 2);
+        assert!(!message.windows(4).any(|w| w == b"Bcc:"));
+
+        for (builder, expected) in [
+            (
+                MessageBuilder::new().from("john@doe.com"),
+                Error::NoRecipients,
+            ),
+            (
+                MessageBuilder::new().to(vec!["jane@doe.com", "not an address"]),
+                Error::InvalidAddress("not an address".into()),
+            ),
+            (
+                MessageBuilder::new()
+                    .to("jane@doe.com")
+                    .header("Cc", Raw::new("a@b, c")),
+                Error::InvalidAddress("a@b, c".into()),
+            ),
+            (
+                MessageBuilder::new()
+                    .to("jane@doe.com")
+                    .header("Bad Name", Raw::new("value")),
+                Error::InvalidHeaderName("Bad Name".into()),
+            ),
+        ] {
+            assert_eq!(builder.text_body("Hi").into_lettre().unwrap_err(), expected);
+        }
+    }
+
+    #[cfg(feature = "mail-send")]
+    #[test]
+    fn into_mail_send_message() {
+        use mail_send::smtp::message::IntoMessage;
+
+        let builder = MessageBuilder::new()
+            .sender("list@doe.com")
+            .from(("John", "john@doe.com"))
+            .to(Address::new_group(
+                "Team".into(),
+                vec![Address::new_address(None::<&str>, "jane@doe.com")],
+            ))
+            .cc("joe@doe.com")
+            .subject("Hello")
+            .date(0i64)
+            .message_id("hello@doe.com")
+            .text_body("Hello, world!");
+        let expected = builder.clone().write_to_vec().unwrap();
+
+        let message = builder.bcc("hidden@doe.com").into_message().unwrap();
+        assert_eq!(message.mail_from.email, "list@doe.com");
+        assert_eq!(
+            message
+                .rcpt_to
+                .iter()
+                .map(|address| address.email.as_ref())
+                .collect::<Vec<_>>(),
+            ["jane@doe.com", "joe@doe.com", "hidden@doe.com"]
+        );
+        assert_eq!(message.body.as_ref(), expected.as_slice());
+
+        assert!(matches!(
+            MessageBuilder::new()
+                .from("john@doe.com")
+                .text_body("Hi")
+                .into_message(),
+            Err(mail_send::Error::MissingRcptTo)
+        ));
+        assert!(matches!(
+            MessageBuilder::new()
+                .to("jane@doe.com")
+                .text_body("Hi")
+                .into_message(),
+            Err(mail_send::Error::MissingMailFrom)
+        ));
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn markdown_body() {
+        let markdown = "## Status\n\n[Dashboard](https://example.com/) is <em>up</em>.\n";
+        for (builder, html) in [
+            (
+                MessageBuilder::new().markdown_body(markdown),
+                "<h2>Status</h2>\n<p><a href=\"https://example.com/\">Dashboard</a> is <em>up</em>.</p>\n",
+            ),
+            (
+                MessageBuilder::new().markdown_body_without_html(markdown),
+                "<h2>Status</h2>\n<p><a href=\"https://example.com/\">Dashboard</a> is up.</p>\n",
+            ),
+        ] {
+            let output = builder
+                .from("john@doe.com")
+                .to("jane@doe.com")
+                .write_to_vec()
+                .unwrap();
+            let message = MessageParser::new().parse(&output).unwrap();
+            assert_eq!(
+                message.content_type().unwrap().subtype(),
+                Some("alternative")
+            );
+            assert_eq!(message.part(1).unwrap().content_type().unwrap().subtype(), Some("plain"));
+            assert_eq!(message.part(2).unwrap().content_type().unwrap().subtype(), Some("html"));
+            assert_eq!(message.body_text(0).unwrap(), markdown.replace('\n', "\r\n"));
+            assert_eq!(message.body_html(0).unwrap(), html.replace('\n', "\r\n"));
+        }
+    }
+
+    #[test]
+    fn alternative_bodies() {
+        let subtypes = |builder: MessageBuilder| {
+            let output = builder.write_to_vec().unwrap();
+            let message = MessageParser::new().parse(&output).unwrap();
+            message.parts[1..]
+                .iter()
+                .map(|part| {
+                    let ct = part.content_type().unwrap();
+                    format!("{}/{}", ct.ctype(), ct.subtype().unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for builder in [
+            MessageBuilder::new()
+                .alternative_body("text/markdown", "*Hi*")
+                .text_body("Hi")
+                .html_body("<em>Hi</em>"),
+            MessageBuilder::new()
+                .html_body("<em>Hi</em>")
+                .alternative_body("text/markdown", "*Hi*")
+                .text_body("Hi"),
+            MessageBuilder::new()
+                .html_body("<em>Hi</em>")
+                .text_body("Hi")
+                .alternative_body("text/markdown", "*Hi*"),
+        ] {
+            assert_eq!(
+                subtypes(builder),
+                ["text/plain", "text/markdown", "text/html"]
+            );
+        }
+
+        assert_eq!(
+            subtypes(
+                MessageBuilder::new()
+                    .alternative_body_at(
+                        AlternativePosition::AfterHtml,
+                        "text/x-amp-html",
+                        "<html amp4email></html>"
+                    )
+                    .html_body("<em>Hi</em>")
+                    .alternative_body("text/markdown", "*Hi*")
+                    .attach_event(
+                        "BEGIN:VCALENDAR\r\nMETHOD:REQUEST\r\nEND:VCALENDAR\r\n",
+                        CalendarMethod::Request
+                    )
+                    .alternative_body_at(
+                        AlternativePosition::BeforeText,
+                        "text/enriched",
+                        "<bold>Hi</bold>"
+                    )
+                    .alternative_body("text/x-markdown; charset=us-ascii", "_Hi_")
+                    .text_body("Hi")
+            ),
+            [
+                "multipart/alternative",
+                "text/enriched",
+                "text/plain",
+                "text/markdown",
+                "text/x-markdown",
+                "text/html",
+                "text/x-amp-html",
+                "text/calendar",
+                "application/ics"
+            ]
+        );
+
+        let output = MessageBuilder::new()
+            .alternative_body("text/markdown; variant=GFM", "Grüße")
+            .write_to_string()
+            .unwrap();
+        assert!(
+            output.contains("Content-Type: text/markdown; variant=\"GFM\"; charset=\"utf-8\""),
+            "{}",
+            output
+        );
+        let output = MessageBuilder::new()
+            .alternative_body("text/x-markdown; charset=us-ascii", "_Hi_")
+            .write_to_string()
+            .unwrap();
+        assert!(
+            output.contains("Content-Type: text/x-markdown; charset=\"us-ascii\"\r\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn interleaved_layout() {
+        fn describe(message: &mail_parser::Message, part_id: usize, output: &mut String) {
+            let part = &message.parts[part_id];
+            let ct = part.content_type().unwrap();
+            output.push_str(&format!("{}/{}", ct.ctype(), ct.subtype().unwrap()));
+            if let Some(cd) = part.content_disposition() {
+                output.push_str(&format!(" {}", cd.ctype()));
+                if let Some(filename) = cd.attribute("filename") {
+                    output.push_str(&format!(" {}", filename));
+                }
+            }
+            if let mail_parser::PartType::Multipart(parts) = &part.body {
+                output.push_str(" [");
+                for (pos, part_id) in parts.iter().enumerate() {
+                    if pos > 0 {
+                        output.push_str(", ");
+                    }
+                    describe(message, *part_id, output);
+                }
+                output.push(']');
+            }
+        }
+
+        let (builder, cids) = MessageBuilder::new()
+            .from("john@doe.com")
+            .text_body("Photo:")
+            .inline_images(vec![
+                ("image/png", b"\x89PNG1".as_ref()),
+                ("image/svg+xml", b"<svg></svg>".as_ref()),
+            ]);
+        let builder = builder
+            .html_body(format!(
+                "<p>Photo:</p><img src=\"{}\"><p>Drawing:</p><img src=\"{}\"><p>Bye</p>",
+                cids[0], cids[1]
+            ))
+            .attachment("application/pdf", "doc.pdf", b"%PDF-1.4".as_ref());
+
+        for (layout, expected) in [
+            (
+                Layout::Standard,
+                concat!(
+                    "multipart/mixed [multipart/alternative [text/plain, ",
+                    "multipart/related [text/html, image/png inline, image/svg+xml inline]], ",
+                    "application/pdf attachment doc.pdf]"
+                ),
+            ),
+            (
+                Layout::Interleaved,
+                concat!(
+                    "multipart/mixed [multipart/alternative [text/plain, text/html], ",
+                    "image/png inline image1.png, text/html, ",
+                    "image/svg+xml inline image2.svg, text/html, ",
+                    "application/pdf inline doc.pdf]"
+                ),
+            ),
+        ] {
+            let output = builder.clone().layout(layout).write_to_vec().unwrap();
+            let message = MessageParser::new().parse(&output).unwrap();
+            let mut structure = String::new();
+            describe(&message, 0, &mut structure);
+            assert_eq!(structure, expected, "{:?}", layout);
+
+            // Every image referenced by an HTML part resolves to a part.
+            let mut html = String::new();
+            for part in &message.parts {
+                if part
+                    .content_type()
+                    .is_some_and(|ct| ct.subtype() == Some("html"))
+                {
+                    html.push_str(part.text_contents().unwrap());
+                }
+            }
+            assert_eq!(
+                html.as_bytes(),
+                builder.html_body.as_ref().unwrap().body_bytes().unwrap()
+            );
+            for cid in crate::html::cid_references(&html) {
+                assert!(
+                    message
+                        .parts
+                        .iter()
+                        .any(|part| part.content_id() == Some(cid.as_str())),
+                    "{:?} {}",
+                    layout,
+                    cid
+                );
+            }
+        }
+
+        // Images that are not referenced follow the HTML body.
+        let (builder, _) = MessageBuilder::new()
+            .html_body("<p>Hello</p>")
+            .inline_images(vec![("image/png", b"\x89PNG1".as_ref())]);
+        let output = builder.layout(Layout::Interleaved).write_to_vec().unwrap();
+        let message = MessageParser::new().parse(&output).unwrap();
+        let mut structure = String::new();
+        describe(&message, 0, &mut structure);
+        assert_eq!(
+            structure,
+            "multipart/mixed [text/html, image/png inline image1.png]"
+        );
+
+        // Without inline parts both layouts are the same.
+        let output = MessageBuilder::new()
+            .text_body("Hello")
+            .layout(Layout::Interleaved)
+            .write_to_string()
+            .unwrap();
+        assert!(output.contains("Content-Type: text/plain"), "{}", output);
+        assert!(!output.contains("multipart"), "{}", output);
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn content_hash() {
+        const HEADERS: &[&str] = &["From", "To", "Subject", "Date"];
+        let hash = |builder: MessageBuilder| builder.content_hash(HEADERS).unwrap();
+
+        let builder = MessageBuilder::new()
+            .from(("Jane Doe", "jane@example.com"))
+            .to("john@example.com")
+            .subject("Quarterly report for the finance and operations teams")
+            .date(1_700_000_000_i64)
+            .text_body("Hello")
+            .attachment("application/pdf", "report.pdf", [1u8, 2, 3].as_ref());
+        let reordered = MessageBuilder::new()
+            .date(1_700_000_000_i64)
+            .header(
+                "Subject",
+                Raw::new("Quarterly report for the finance\r\n  and operations teams"),
+            )
+            .to("john@example.com")
+            .from(("Jane Doe", "jane@example.com"))
+            .attachment("application/pdf", "report.pdf", [1u8, 2, 3].as_ref())
+            .text_body("Hello")
+            .message_id("unrelated@example.com");
+        assert_eq!(hash(builder.clone()), hash(reordered));
+
+        for changed in [
+            builder.clone().subject("Quarterly report"),
+            builder.clone().text_body("Hello!"),
+            builder
+                .clone()
+                .attachment("application/pdf", "other.pdf", [1u8, 2].as_ref()),
+        ] {
+            assert_ne!(hash(builder.clone()), hash(changed));
+        }
+
+        // The type and disposition of each part are part of the digest.
+        let attachment = |c_type, filename| {
+            MessageBuilder::new().text_body("Hello").attachment(
+                c_type,
+                filename,
+                [1u8, 2, 3].as_ref(),
+            )
+        };
+        let original = hash(attachment("application/pdf", "report.pdf"));
+        assert_ne!(original, hash(attachment("image/png", "report.pdf")));
+        assert_ne!(original, hash(attachment("application/pdf", "other.pdf")));
+        assert_ne!(
+            original,
+            hash(MessageBuilder::new().text_body("Hello").inline(
+                "application/pdf",
+                "report.pdf",
+                [1u8, 2, 3].as_ref()
+            ))
+        );
+
+        // So is the multipart/* tree containing them.
+        let tree = |c_type: &'static str, boundary: &'static str| {
+            MessageBuilder::new().body(
+                MimePart::new(
+                    c_type,
+                    vec![
+                        MimePart::new("text/plain", "Hello"),
+                        MimePart::new("text/html", "<p>Hello</p>"),
+                    ],
+                )
+                .with_custom_boundary(boundary)
+                .unwrap(),
+            )
+        };
+        let alternative = hash(tree("multipart/alternative", "a"));
+        assert_eq!(alternative, hash(tree("multipart/alternative", "b")));
+        assert_ne!(alternative, hash(tree("multipart/mixed", "a")));
+        let nested = MessageBuilder::new().body(MimePart::new(
+            "multipart/alternative",
+            vec![MimePart::new(
+                "multipart/alternative",
+                vec![
+                    MimePart::new("text/plain", "Hello"),
+                    MimePart::new("text/html", "<p>Hello</p>"),
+                ],
+            )],
+        ));
+        assert_ne!(alternative, hash(nested));
+
+        let mut invalid = builder;
+        invalid
+            .headers
+            .push(("Sub ject".into(), Raw::new("Hello").into()));
+        assert!(invalid.content_hash(&["Sub ject"]).is_err());
+    }
+
+    #[test]
+    fn cloned_template_keeps_boundaries() {
+        let template = MessageBuilder::new()
+            .from("newsletter@example.com")
+            .subject("Weekly digest")
+            .body(
+                MimePart::new(
+                    "multipart/mixed",
+                    vec![
+                        MimePart::new(
+                            "multipart/alternative",
+                            vec![
+                                MimePart::new("text/plain", "Hello"),
+                                MimePart::new("text/html", "<p>Hello</p>"),
+                            ],
+                        )
+                        .with_custom_boundary("digest-alternative")
+                        .unwrap(),
+                        MimePart::new("application/pdf", [1u8, 2, 3].as_ref())
+                            .attachment("digest.pdf"),
+                    ],
+                )
+                .with_custom_boundary("digest-mixed")
+                .unwrap(),
+            );
+
+        let bodies = ["jane@example.com", "john@example.com"].map(|rcpt| {
+            let output = template.clone().to(rcpt).write_to_vec().unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains(rcpt), "{}", output);
+            output.split_once("\r\n\r\n").unwrap().1.to_string()
+        });
+        assert_eq!(bodies[0], bodies[1]);
+        for boundary in ["digest-mixed", "digest-alternative"] {
+            assert!(
+                bodies[0].contains(&format!("\r\n--{}--", boundary)),
+                "{}",
+                bodies[0]
+            );
+        }
+    }
+
+    #[test]
+    fn mime_version_header() {
+        for builder in [
+            MessageBuilder::new().text_body("Hello"),
+            MessageBuilder::new()
+                .header("MIME-Version", Raw::new("1.0"))
+                .text_body("Hello"),
+        ] {
+            let output = builder.from("john@doe.com").write_to_string().unwrap();
+            let (headers, _) = output.split_once("\r\n\r\n").unwrap();
+            assert_eq!(
+                headers.matches("MIME-Version: 1.0\r\n").count(),
+                1,
+                "{}",
+                output
+            );
+            assert!(
+                headers.find("MIME-Version") < headers.find("Content-Type"),
+                "{}",
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn dangling_cid_references() {
+        let builder = MessageBuilder::new()
+            .text_body("Hello")
+            .html_body(concat!(
+                "<p><img src=\"cid:logo@example.com\">",
+                "<img src=\"cid:missing@example.com\"></p>"
+            ))
+            .inline("image/png", "logo@example.com", [0u8, 1, 2].as_ref());
+        assert_eq!(builder.dangling_cid_references(), ["missing@example.com"]);
+        assert!(builder
+            .clone()
+            .inline("image/png", "missing@example.com", [3u8, 4].as_ref())
+            .dangling_cid_references()
+            .is_empty());
+
+        // Only Content-ID headers identify a part.
+        let mut described = builder.clone();
+        described.attachments.get_or_insert_with(Vec::new).push(
+            MimePart::new("image/png", [3u8, 4].as_ref())
+                .header("Content-Description", Text::new("<missing@example.com>"))
+                .header("content-id", Raw::new("<other@example.com>")),
+        );
+        assert_eq!(described.dangling_cid_references(), ["missing@example.com"]);
+        let mut lowercase = builder;
+        lowercase.attachments.get_or_insert_with(Vec::new).push(
+            MimePart::new("image/png", [3u8, 4].as_ref())
+                .header("content-id", Raw::new("<missing@example.com>")),
+        );
+        assert!(lowercase.dangling_cid_references().is_empty());
+    }
+
+    #[test]
+    fn write_normalized_to() {
+        let normalize = |builder: MessageBuilder| {
+            let mut output = Vec::new();
+            builder.write_normalized_to(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let builder = MessageBuilder::new()
+            .from(("Jane Doe", "jane@example.com"))
+            .to("john@example.com")
+            .subject("Quarterly report for the finance and operations teams of the company")
+            .header("X-Mailer", Raw::new("mail-builder"))
+            .text_body("Hello")
+            .html_body("<p>Hello</p>")
+            .attachment("application/pdf", "report.pdf", [1u8, 2, 3].as_ref());
+        let reordered = MessageBuilder::new()
+            .attachment("application/pdf", "report.pdf", [1u8, 2, 3].as_ref())
+            .header("X-Mailer", Raw::new("mail-builder"))
+            .html_body("<p>Hello</p>")
+            .text_body("Hello")
+            .subject("Quarterly report for the finance and operations teams of the company")
+            .to("john@example.com")
+            .from(("Jane Doe", "jane@example.com"))
+            .header_order(vec!["Subject".to_string()]);
+
+        let output = normalize(builder.clone());
+        assert_eq!(output, normalize(reordered));
+        assert_ne!(output, normalize(builder.clone().text_body("Goodbye")));
+
+        let (headers, body) = output.split_once("\r\n\r\n").unwrap();
+        let names = headers
+            .lines()
+            .map(|line| line.split_once(':').unwrap().0.to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        let mut sorted_names = names.clone();
+        sorted_names.sort();
+        assert_eq!(names, sorted_names);
+        assert!(headers.contains("\r\nDate: DATE\r\n"), "{}", output);
+        assert!(headers.contains("boundary=\"BOUNDARY-1\""), "{}", output);
+        assert!(body.contains("\r\n--BOUNDARY-1\r\nContent-Type: multipart/alternative;"));
+        assert!(body.contains("\r\n--BOUNDARY-2--\r\n"), "{}", output);
+        assert!(body.ends_with("\r\n--BOUNDARY-1--\r\n"), "{}", output);
+
+        assert!(
+            normalize(builder.date(0_i64)).contains("\r\nDate: Thu, 1 Jan 1970 00:00:00 +0000\r\n")
+        );
+
+        // Only boundaries are renamed, not text that contains them.
+        let output = normalize(
+            MessageBuilder::new().from("bob@example.com").body(
+                MimePart::new(
+                    "multipart/mixed",
+                    vec![
+                        MimePart::new("text/plain", "bob builds boats"),
+                        MimePart::new("application/octet-stream", b"b--b".as_ref()),
+                    ],
+                )
+                .with_custom_boundary("b")
+                .unwrap(),
+            ),
+        );
+        assert!(
+            output.contains("\r\nFrom: <bob@example.com>\r\n"),
+            "{}",
+            output
+        );
+        assert!(output.contains("boundary=\"BOUNDARY-1\""), "{}", output);
+        assert!(output.contains("\r\n\r\nbob builds boats\r\n--BOUNDARY-1\r\n"));
+        assert!(output.ends_with("\r\n--BOUNDARY-1--\r\n"), "{}", output);
+        assert!(!output.contains("--b\r\n"), "{}", output);
+    }
+
+    #[test]
+    fn canonical_headers_match_written_message() {
+        let builders = [
+            MessageBuilder::new()
+                .from("john@doe.com")
+                .subject("Text only")
+                .text_body("Hola señor"),
+            MessageBuilder::new()
+                .from("john@doe.com")
+                .subject("No body"),
+            MessageBuilder::new()
+                .from("john@doe.com")
+                .header("MIME-Version", Raw::new("1.0"))
+                .body(MimePart::new("text/plain", b"Hello".to_vec())),
+            MessageBuilder::new()
+                .from("john@doe.com")
+                .subject("A subject long enough to be folded across more than a single line")
+                .text_body("Hello")
+                .attachment("image/png", "image.png", vec![0u8; 16])
+                .write_options(WriteOptions::new().fold_with_tab(true)),
+        ];
+
+        for mut builder in builders {
+            let header_block = builder.frozen_header_block().unwrap();
+            let output = builder.clone().write_to_vec().unwrap();
+            assert!(output.starts_with(&header_block));
+            assert!(output[header_block.len()..].starts_with(b"\r\n"));
+
+            for field in super::header_fields(&header_block) {
+                let field = std::str::from_utf8(field).unwrap();
+                let name = field.split_once(':').unwrap().0;
+                assert_eq!(
+                    builder
+                        .canonical_header(name, Canonicalization::Simple)
+                        .unwrap(),
+                    Some(format!("{}\r\n", field))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn error_from_io_error() {
+        assert_eq!(
+            Error::from(std::io::Error::from(Error::NoRecipients)),
+            Error::NoRecipients
+        );
+        assert_eq!(
+            Error::from(std::io::Error::other("disk full")),
+            Error::Io("disk full".into())
+        );
+    }
+
+    #[test]
+    fn custom_root_part() {
+        let root = MimePart::new(
+            "multipart/mixed",
+            vec![
+                MimePart::new(
+                    "multipart/alternative",
+                    vec![
+                        MimePart::new("text/plain", "Hello"),
+                        MimePart::new("text/html", "<p>Hello</p>"),
+                    ],
+                ),
+                MimePart::new("application/pdf", b"%PDF-1.4".as_ref()).attachment("doc.pdf"),
+            ],
+        )
+        .header("Content-Language", Text::new("en"));
+        let output = MessageBuilder::new()
+            .from("john@doe.com")
+            .to("jane@doe.com")
+            .subject("Custom root")
+            .date(0i64)
+            .header("Content-Language", Text::new("es"))
+            .body(root)
+            .write_to_string()
+            .unwrap();
+
+        let (headers, _) = output.split_once("\r\n\r\n").unwrap();
+        for header in [
+            "From: <john@doe.com>\r\n",
+            "To: <jane@doe.com>\r\n",
+            "Subject: Custom root\r\n",
+            "Date: Thu, 1 Jan 1970 00:00:00 +0000\r\n",
+            "Content-Type: multipart/mixed;\r\n boundary=",
+        ] {
+            assert!(headers.contains(header), "{:?} {}", header, output);
+        }
+        assert_eq!(output.matches("Content-Language").count(), 1, "{}", output);
+        assert!(headers.contains("Content-Language: en"), "{}", output);
+
+        let message = MessageParser::new().parse(&output).unwrap();
+        assert_eq!(message.subject(), Some("Custom root"));
+        assert_eq!(message.body_text(0).unwrap(), "Hello");
+        assert_eq!(message.body_html(0).unwrap(), "<p>Hello</p>");
+        assert_eq!(
+            message.attachment(0).unwrap().attachment_name(),
+            Some("doc.pdf")
+        );
+    }
+
+    #[test]
+    fn canonicalized_headers_for_signing() {
+        let mut builder = MessageBuilder::new()
+            .from(("John Doe", "john@doe.com"))
+            .to(vec![
+                ("Jane Doe", "jane@doe.com"),
+                ("James Smith", "james@smith.com"),
+                ("Josephine Johnson", "josephine@johnson.com"),
+            ])
+            .subject("Hello,   world!")
+            .header("X-Tag", Raw::new("first"))
+            .header("X-Tag", Raw::new("second"))
+            .sign_headers(
+                [
+                    "From",
+                    "to",
+                    "Subject",
+                    "X-Tag",
+                    "X-Tag",
+                    "X-Tag",
+                    "Reply-To",
+                    "Message-ID",
+                    "Date",
+                    "MIME-Version",
+                    "Content-Type",
+                ]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            )
+            .text_body("Hello");
+
+        // Generated headers are signed with the values later written.
+        let signed = builder.canonicalized_headers_for_signing().unwrap();
+        let message = builder.write_to_string().unwrap();
+        let field = |name: &str| {
+            message
+                .split("\r\n")
+                .find_map(|line| line.strip_prefix(name))
+                .unwrap()
+        };
+        assert_eq!(
+            signed,
+            [
+                ("from", "\"John Doe\" <john@doe.com>"),
+                (
+                    "to",
+                    concat!(
+                        "\"Jane Doe\" <jane@doe.com>, \"James Smith\" <james@smith.com>, ",
+                        "\"Josephine Johnson\" <josephine@johnson.com>"
+                    )
+                ),
+                ("subject", "Hello, world!"),
+                ("x-tag", "second"),
+                ("x-tag", "first"),
+                ("message-id", field("Message-ID: ")),
+                ("date", field("Date: ")),
+                ("mime-version", "1.0"),
+                ("content-type", "text/plain; charset=\"utf-8\""),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+        );
+
+        assert!(MessageBuilder::new()
+            .subject("Hi")
+            .canonicalized_headers_for_signing()
+            .unwrap()
+            .is_empty());
+        let err = MessageBuilder::new()
+            .header("Bad Name", Raw::new("value"))
+            .sign_headers(vec!["Bad Name".into()])
+            .canonicalized_headers_for_signing()
+            .unwrap_err();
+        assert_eq!(
+            err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+            Some(&Error::InvalidHeaderName("Bad Name".into()))
+        );
+    }
+
+    #[cfg(feature = "mime-types")]
+    #[test]
+    fn attach_file_auto_content_type() {
+        let path = std::env::temp_dir().join("mail-builder-attachment.txt");
+        std::fs::write(&path, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        let output = MessageBuilder::new()
+            .from("john@doe.com")
+            .text_body("See attached")
+            .attach_file(&path)
+            .unwrap()
+            .write_to_string()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let message = MessageParser::new().parse(&output).unwrap();
+        let attachment = message.attachment(0).unwrap();
+        assert_eq!(
+            attachment.attachment_name(),
+            Some("mail-builder-attachment.txt")
+        );
+        assert_eq!(attachment.content_type().unwrap().subtype(), Some("png"));
+    }
+
+    #[test]
+    fn inline_images() {
+        let (builder, cids) = MessageBuilder::new()
+            .from("john@doe.com")
+            .inline_images(vec![
+                ("image/png", b"\x89PNG1".as_ref()),
+                ("image/jpeg", b"\xff\xd8\xff2".as_ref()),
+                ("image/gif", b"GIF893".as_ref()),
+            ]);
+        assert_eq!(cids.len(), 3);
+        assert!(cids.iter().all(|cid| cid.starts_with("cid:")), "{:?}", cids);
+        assert!(
+            cids[0] != cids[1] && cids[1] != cids[2] && cids[0] != cids[2],
+            "{:?}",
+            cids
+        );
+
+        let html = cids
+            .iter()
+            .map(|cid| format!("<img src=\"{}\">", cid))
+            .collect::<String>();
+        let output = builder.html_body(html).write_to_string().unwrap();
+        let message = MessageParser::new().parse(&output).unwrap();
+        for (pos, (cid, content_type)) in cids.iter().zip(["png", "jpeg", "gif"]).enumerate() {
+            let part = message.attachment(pos).unwrap();
+            assert_eq!(part.content_id(), Some(&cid[4..]));
+            assert_eq!(part.content_type().unwrap().subtype(), Some(content_type));
+        }
+        assert!(output.contains("multipart/related"), "{}", output);
+    }
+
+    #[test]
+    fn forward_message_global() {
+        let ascii = b"From: ana@example.com\r\nSubject: Report\r\n\r\nHello\r\n".as_slice();
+        let utf8 = "From: José <jose@example.com>\r\nSubject: Año nuevo\r\n\r\nHola\r\n";
+
+        let output = MessageBuilder::new()
+            .from("john@doe.com")
+            .forward_message(ascii, "Report")
+            .text_body("See below.")
+            .write_to_string()
+            .unwrap();
+        assert!(
+            output.contains("Content-Type: message/rfc822\r\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("Content-Transfer-Encoding: 7bit\r\n"),
+            "{}",
+            output
+        );
+
+        let output = MessageBuilder::new()
+            .from("john@doe.com")
+            .forward_message(utf8.as_bytes(), "Año nuevo")
+            .text_body("See below.")
+            .write_to_vec()
+            .unwrap();
+        let text = std::str::from_utf8(&output).unwrap();
+        assert!(
+            text.contains("Content-Type: message/global\r\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("Content-Transfer-Encoding: base64\r\n"),
+            "{}",
+            text
+        );
+        assert!(!text.contains("message/rfc822"), "{}", text);
+
+        let message = MessageParser::new().parse(&output).unwrap();
+        assert_eq!(message.attachment(0).unwrap().contents(), utf8.as_bytes());
+    }
+
+    #[test]
+    fn add_authentication_results() {
+        let output = MessageBuilder::new()
+            .from("john@example.net")
+            .subject("Hello")
+            .add_authentication_results(
+                AuthenticationResults::new("mx.example.com")
+                    .spf("pass", "example.net")
+                    .dkim("pass", "example.net"),
+            )
+            .text_body("Hello")
+            .write_to_string()
+            .unwrap();
+        assert!(
+            output.starts_with(concat!(
+                "Authentication-Results: mx.example.com; spf=pass smtp.mailfrom=example.net;\r\n",
+                " dkim=pass header.d=example.net\r\n",
+                "From: <john@example.net>\r\n"
+            )),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn attach_event() {
+        let ics = |method: &str| {
+            format!(
+                concat!(
+                    "BEGIN:VCALENDAR\r\n",
+                    "VERSION:2.0\r\n",
+                    "PRODID:-//Example//Scheduler//EN\r\n",
+                    "METHOD:{}\r\n",
+                    "BEGIN:VEVENT\r\n",
+                    "UID:standup-42@example.com\r\n",
+                    "DTSTAMP:20240101T090000Z\r\n",
+                    "DTSTART;TZID=Europe/Madrid:20240102T100000\r\n",
+                    "DTEND;TZID=Europe/Madrid:20240102T101500\r\n",
+                    "SUMMARY:Daily stand-up\r\n",
+                    "ORGANIZER:mailto:john@doe.com\r\n",
+                    "END:VEVENT\r\n",
+                    "END:VCALENDAR\r\n"
+                ),
+                method
+            )
+        };
+
+        for method in [CalendarMethod::Request, CalendarMethod::Cancel] {
+            let ics = ics(method.as_str());
+            let output = MessageBuilder::new()
+                .from("john@doe.com")
+                .to("jane@doe.com")
+                .subject("Daily stand-up")
+                .text_body("You are invited.")
+                .html_body("<p>You are invited.</p>")
+                .attach_event(ics.as_str(), method)
+                .write_to_vec()
+                .unwrap();
+            let text = std::str::from_utf8(&output).unwrap();
+            assert!(
+                text.contains(&format!(
+                    "Content-Type: text/calendar; method=\"{}\"",
+                    method.as_str()
+                )),
+                "{}",
+                text
+            );
+
+            let message = MessageParser::new().parse(&output).unwrap();
+            let root = message.root_part();
+            assert_eq!(root.content_type().unwrap().subtype(), Some("mixed"));
+            let alternative = message.part(1).unwrap();
+            assert_eq!(
+                alternative.content_type().unwrap().subtype(),
+                Some("alternative")
+            );
+            let subtypes = (2..5)
+                .map(|pos| {
+                    message
+                        .part(pos)
+                        .and_then(|part| part.content_type())
+                        .and_then(|ct| ct.subtype())
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(subtypes, ["plain", "html", "calendar"]);
+            assert_eq!(message.part(4).unwrap().text_contents(), Some(ics.as_str()));
+
+            let attachment = message.part(5).unwrap();
+            assert_eq!(
+                attachment.attachment_name(),
+                Some("standup-42@example.com.ics")
+            );
+            assert_eq!(attachment.contents(), ics.as_bytes());
+        }
+
+        for (uid_line, filename) in [
+            (
+                "UID:standup-42@exam\r\n ple.com",
+                "standup-42@example.com.ics",
+            ),
+            (
+                "UID;X-SOURCE=\"a:b\":weekly@example.com",
+                "weekly@example.com.ics",
+            ),
+            ("X-UID:other@example.com", "invite.ics"),
+            ("UID: ", "invite.ics"),
+        ] {
+            let ics = format!(
+                "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\n{}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+                uid_line
+            );
+            let output = MessageBuilder::new()
+                .text_body("You are invited.")
+                .attach_event(ics, CalendarMethod::Request)
+                .write_to_vec()
+                .unwrap();
+            let message = MessageParser::new().parse(&output).unwrap();
+            assert_eq!(
+                message
+                    .attachments()
+                    .filter_map(|part| part.attachment_name())
+                    .collect::<Vec<_>>(),
+                [filename],
+                "{}",
+                uid_line
+            );
+        }
+    }
+
+    #[test]
+    fn avoid_unsafe_line_starts() {
+        let output = MessageBuilder::new()
+            .from(("John Doe", "john@doe.com"))
+            .to("jane@doe.com")
+            .subject(format!("{} From here to there", "Subject".repeat(10)))
+            .header("X-Dot", Raw::new(format!("{} .", "x".repeat(80))))
+            .text_body("Hola señor,\nFrom here\n.\nBye\n")
+            .write_to_string()
+            .unwrap();
+        assert!(output.contains("Content-Transfer-Encoding: quoted-printable"));
+        for line in output.split("\r\n") {
+            assert!(!line.starts_with("From ") && line != ".", "{}", output);
+        }
+    }
 }
@@ -13,8 +13,11 @@ use std::{
     borrow::Cow,
     cell::Cell,
     collections::hash_map::DefaultHasher,
+    fmt::Display,
     hash::{Hash, Hasher},
     io::{self, Write},
+    path::Path,
+    sync::OnceLock,
     thread,
 };
 
@@ -24,12 +27,15 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use crate::{
     encoders::{
         base64::base64_encode_mime,
-        encode::{get_encoding_type, EncodingType},
+        encode::{detect_charset, get_encoding_type, EncodingType},
         quoted_printable::quoted_printable_encode,
     },
     headers::{
-        content_type::ContentType, message_id::MessageId, raw::Raw, text::Text, Header, HeaderType,
+        content_type::ContentType, cow_memory_usage, message_id::MessageId, raw::Raw, text::Text,
+        vec_memory_usage, write_header_name, Header, HeaderType, FOLD, FOLD_WITH_TAB,
     },
+    parser::{parse_part, ParseError},
+    Error,
 };
 
 /// MIME part of an e-mail.
@@ -88,6 +94,15 @@ impl<'x> From<Vec<MimePart<'x>>> for BodyPart<'x> {
     }
 }
 
+/// Builds a multipart body from a borrowed slice of parts. Every part is
+/// cloned, including its contents, so prefer passing a `Vec` when the
+/// parts are no longer needed.
+impl<'x> From<&[MimePart<'x>]> for BodyPart<'x> {
+    fn from(value: &[MimePart<'x>]) -> Self {
+        BodyPart::Multipart(value.to_vec())
+    }
+}
+
 impl<'x> From<&'x str> for ContentType<'x> {
     fn from(value: &'x str) -> Self {
         ContentType::new(value)
@@ -106,52 +121,485 @@ impl<'x> From<&'x String> for ContentType<'x> {
     }
 }
 
-thread_local!(static COUNTER: Cell<u64> = Cell::new(0));
+/// Type of an opaque S/MIME application/pkcs7-mime part (RFC 8551).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmimeType {
+    EnvelopedData,
+    SignedData,
+    CertsOnly,
+}
 
+impl SmimeType {
+    /// Returns the value of the smime-type parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SmimeType::EnvelopedData => "enveloped-data",
+            SmimeType::SignedData => "signed-data",
+            SmimeType::CertsOnly => "certs-only",
+        }
+    }
 
-#[cfg(target_arch = "wasm32")]
-pub fn make_boundary(separator: &str) -> String {
-    let mut s = DefaultHasher::new();
-    "localhost".hash(&mut s);
-    thread::current().id().hash(&mut s);
-    let hash = s.finish();
+    /// Returns the file name recommended by RFC 8551 for this type.
+    pub fn filename(&self) -> &'static str {
+        match self {
+            SmimeType::EnvelopedData | SmimeType::SignedData => "smime.p7m",
+            SmimeType::CertsOnly => "smime.p7c",
+        }
+    }
+}
+
+/// Content-Transfer-Encoding of contents that are already encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransferEncoding {
+    SevenBit,
+    EightBit,
+    Binary,
+    QuotedPrintable,
+    Base64,
+}
+
+impl TransferEncoding {
+    /// Returns the Content-Transfer-Encoding header value.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferEncoding::SevenBit => "7bit",
+            TransferEncoding::EightBit => "8bit",
+            TransferEncoding::Binary => "binary",
+            TransferEncoding::QuotedPrintable => "quoted-printable",
+            TransferEncoding::Base64 => "base64",
+        }
+    }
+}
+
+/// iTIP method of a calendar object (RFC 5546), written as the method
+/// parameter of text/calendar parts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalendarMethod {
+    Publish,
+    Request,
+    Reply,
+    Add,
+    Cancel,
+    Refresh,
+    Counter,
+    DeclineCounter,
+}
+
+impl CalendarMethod {
+    /// Returns the value of the method parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CalendarMethod::Publish => "PUBLISH",
+            CalendarMethod::Request => "REQUEST",
+            CalendarMethod::Reply => "REPLY",
+            CalendarMethod::Add => "ADD",
+            CalendarMethod::Cancel => "CANCEL",
+            CalendarMethod::Refresh => "REFRESH",
+            CalendarMethod::Counter => "COUNTER",
+            CalendarMethod::DeclineCounter => "DECLINECOUNTER",
+        }
+    }
+}
+
+thread_local!(static COUNTER: Cell<u64> = const { Cell::new(0) });
+
+/// Options controlling how a MIME part is serialized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Generate reproducible boundaries instead of unique ones,
+    /// useful for snapshot testing.
+    pub deterministic: bool,
+    /// Return an error instead of silently fixing invalid MIME structures.
+    pub strict: bool,
+    /// Content types of binary parts that are encoded like text parts
+    /// (7bit, quoted-printable or base64, whichever fits best) instead of
+    /// always using base64. text/* parts are always treated this way.
+    pub text_content_types: Vec<Cow<'static, str>>,
+    /// Maximum nesting depth of multipart/* parts.
+    pub max_depth: usize,
+    /// Maximum total number of MIME parts.
+    pub max_parts: usize,
+    /// Fold long header lines with a tab instead of a space.
+    pub fold_with_tab: bool,
+    /// Maximum number of headers of the message or of a single part.
+    pub max_headers: usize,
+    /// Maximum size in bytes of the encoded header block of the message or
+    /// of a single part.
+    pub max_header_size: usize,
+    /// Prefix prepended to generated multipart boundaries, at most
+    /// [`MAX_BOUNDARY_PREFIX_LEN`] characters long.
+    pub boundary_prefix: Cow<'static, str>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            deterministic: false,
+            strict: false,
+            text_content_types: vec![
+                "application/pgp-signature".into(),
+                "application/pgp-keys".into(),
+                "application/pgp-encrypted".into(),
+            ],
+            max_depth: 50,
+            max_parts: 10_000,
+            max_headers: 100,
+            max_header_size: 64 * 1024,
+            fold_with_tab: false,
+            boundary_prefix: "".into(),
+        }
+    }
+}
+
+impl WriteOptions {
+    /// Create a new set of write options with the default values.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the content types of binary parts that are encoded like text
+    /// parts instead of always using base64.
+    pub fn text_content_types<T>(mut self, content_types: impl IntoIterator<Item = T>) -> Self
+    where
+        T: Into<Cow<'static, str>>,
+    {
+        self.text_content_types = content_types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the maximum nesting depth of multipart/* parts.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Set the maximum total number of MIME parts.
+    pub fn max_parts(mut self, max_parts: usize) -> Self {
+        self.max_parts = max_parts;
+        self
+    }
+
+    /// Set the maximum number of headers of the message or of a single part.
+    pub fn max_headers(mut self, max_headers: usize) -> Self {
+        self.max_headers = max_headers;
+        self
+    }
+
+    /// Set the maximum size in bytes of the encoded header block of the
+    /// message or of a single part.
+    pub fn max_header_size(mut self, max_header_size: usize) -> Self {
+        self.max_header_size = max_header_size;
+        self
+    }
+
+    /// Fold long header lines with a tab instead of a space.
+    pub fn fold_with_tab(mut self, fold_with_tab: bool) -> Self {
+        self.fold_with_tab = fold_with_tab;
+        self
+    }
+
+    /// Set a prefix, such as `----=_Part_`, prepended to generated
+    /// multipart boundaries. Writing fails with [`Error::InvalidBoundary`]
+    /// if the prefix is longer than [`MAX_BOUNDARY_PREFIX_LEN`] characters
+    /// or contains characters not allowed in a boundary.
+    pub fn boundary_prefix(mut self, prefix: impl Into<Cow<'static, str>>) -> Self {
+        self.boundary_prefix = prefix.into();
+        self
+    }
+
+    /// Returns the folding whitespace passed to the header writers.
+    pub(crate) fn fold(&self) -> &'static [u8] {
+        if self.fold_with_tab {
+            FOLD_WITH_TAB
+        } else {
+            FOLD
+        }
+    }
+
+    fn is_text_content_type(&self, content_type: &ContentType) -> bool {
+        content_type.is_text()
+            || self
+                .text_content_types
+                .iter()
+                .any(|ct| ct.eq_ignore_ascii_case(&content_type.c_type))
+    }
+
+    /// Generate reproducible boundaries.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
 
+    /// Return an error instead of silently fixing invalid MIME structures.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    fn make_boundary(&self, separator: &str, seq: &mut u64) -> io::Result<String> {
+        let boundary = if self.deterministic {
+            *seq += 1;
+            make_deterministic_boundary(separator, *seq)
+        } else {
+            make_boundary(separator)
+        };
+        if self.boundary_prefix.is_empty() {
+            debug_assert_eq!(validate_boundary(&boundary), Ok(()), "{}", boundary);
+            return Ok(boundary);
+        } else if self.boundary_prefix.len() > MAX_BOUNDARY_PREFIX_LEN {
+            return Err(Error::InvalidBoundary(InvalidBoundaryError::TooLong).into());
+        }
+
+        // Drop the leading timestamp digits first when the prefixed boundary
+        // would be too long, the remaining digits are unique per boundary.
+        let max_len = 70 - self.boundary_prefix.len();
+        let boundary = format!(
+            "{}{}",
+            self.boundary_prefix,
+            &boundary[boundary.len().saturating_sub(max_len)..]
+        );
+        validate_boundary(&boundary).map_err(Error::InvalidBoundary)?;
+        Ok(boundary)
+    }
+}
+
+/// Maximum length of [`WriteOptions::boundary_prefix`], leaving room for at
+/// least 34 generated boundary characters.
+pub const MAX_BOUNDARY_PREFIX_LEN: usize = 36;
+
+/// Reasons a multipart boundary does not conform to RFC 2046.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidBoundaryError {
+    /// The boundary is empty.
+    Empty,
+    /// The boundary is longer than 70 characters.
+    TooLong,
+    /// The boundary contains a character that is not allowed.
+    InvalidCharacter(char),
+    /// The boundary ends with a space.
+    TrailingSpace,
+}
+
+impl Display for InvalidBoundaryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidBoundaryError::Empty => write!(f, "Boundary is empty."),
+            InvalidBoundaryError::TooLong => write!(f, "Boundary is longer than 70 characters."),
+            InvalidBoundaryError::InvalidCharacter(ch) => {
+                write!(f, "Boundary contains invalid character {:?}.", ch)
+            }
+            InvalidBoundaryError::TrailingSpace => write!(f, "Boundary ends with a space."),
+        }
+    }
+}
+
+impl std::error::Error for InvalidBoundaryError {}
+
+/// Checks that a boundary is 1 to 70 characters long, only contains the
+/// characters allowed by RFC 2046 and does not end with a space.
+pub fn validate_boundary(boundary: &str) -> Result<(), InvalidBoundaryError> {
+    if boundary.is_empty() {
+        Err(InvalidBoundaryError::Empty)
+    } else if let Some(ch) = boundary.chars().find(|&ch| {
+        !(ch.is_ascii_alphanumeric()
+            || matches!(
+                ch,
+                '\'' | '(' | ')' | '+' | '_' | ',' | '-' | '.' | '/' | ':' | '=' | '?' | ' '
+            ))
+    }) {
+        Err(InvalidBoundaryError::InvalidCharacter(ch))
+    } else if boundary.len() > 70 {
+        Err(InvalidBoundaryError::TooLong)
+    } else if boundary.ends_with(' ') {
+        Err(InvalidBoundaryError::TrailingSpace)
+    } else {
+        Ok(())
+    }
+}
+
+/// Generates a reproducible boundary from a sequence number.
+pub fn make_deterministic_boundary(separator: &str, seq: u64) -> String {
     format!(
         "{:x}{}{:x}{}{:x}",
         0,
         separator,
-        COUNTER.with(|c| {
-            hash.wrapping_add(c.replace(c.get() + 1))
-                .wrapping_mul(11400714819323198485u64)
-        }),
+        seq.wrapping_mul(11400714819323198485u64),
         separator,
-        hash,
+        0,
+    )
+}
+
+/// Default maximum length, in characters, of sanitized attachment filenames.
+pub const MAX_FILENAME_LEN: usize = 100;
+
+/// Cleans up a user supplied attachment filename: any directory components
+/// and control characters are removed, whitespace is collapsed and the name
+/// is truncated to `max_len` characters keeping its extension. Names that
+/// end up empty are replaced with "attachment.bin" and names consisting of
+/// only an extension are prefixed with "attachment".
+pub fn sanitize_filename(filename: &str, max_len: usize) -> String {
+    let filename = filename
+        .rsplit(['/', '\\'])
+        .find(|name| !name.trim().is_empty() && !matches!(name.trim(), "." | ".."))
+        .unwrap_or_default();
+    let mut name = String::with_capacity(filename.len());
+    for word in filename
+        .split(|ch: char| ch.is_whitespace() || ch.is_control())
+        .filter(|word| !word.is_empty())
+    {
+        if !name.is_empty() {
+            name.push(' ');
+        }
+        name.push_str(word);
+    }
+
+    if name.is_empty() {
+        return "attachment.bin".to_string();
+    } else if name.starts_with('.') && !name[1..].contains('.') {
+        name.insert_str(0, "attachment");
+    }
+
+    if name.chars().count() > max_len {
+        let extension = name
+            .rsplit_once('.')
+            .filter(|(stem, ext)| !stem.is_empty() && ext.chars().count() < max_len / 2)
+            .map(|(_, ext)| format!(".{}", ext))
+            .unwrap_or_default();
+        let stem_len = max_len.saturating_sub(extension.chars().count());
+        name = name
+            .chars()
+            .take(stem_len)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+            + &extension;
+    }
+
+    name
+}
+
+/// Guesses the content type of a file from its extension, defaulting to
+/// application/octet-stream.
+pub(crate) fn guess_content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "txt" | "text" | "log" => "text/plain",
+        "htm" | "html" => "text/html",
+        "css" => "text/css",
+        "csv" => "text/csv",
+        "ics" => "text/calendar",
+        "vcf" => "text/vcard",
+        "md" => "text/markdown",
+        "xml" => "application/xml",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn make_boundary(separator: &str) -> String {
+    format_boundary(
+        separator,
+        0,
+        boundary_hash("localhost", process_seed()),
+        COUNTER.with(|c| c.replace(c.get() + 1)),
     )
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 pub fn make_boundary(separator: &str) -> String {
+    make_boundary_with_host(separator, &gethostname::gethostname())
+}
+
+/// Generates a boundary for the given host name. When the host name is
+/// not available, the process id and the current time are used instead.
+#[cfg(not(target_arch = "wasm32"))]
+fn make_boundary_with_host(separator: &str, host: &std::ffi::OsStr) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::new(0, 0))
+        .as_nanos();
+    let hash = if !host.is_empty() {
+        boundary_hash(host, process_seed())
+    } else {
+        boundary_hash((std::process::id(), time), process_seed())
+    };
+
+    format_boundary(
+        separator,
+        time,
+        hash,
+        COUNTER.with(|c| c.replace(c.get() + 1)),
+    )
+}
+
+/// Hashes the host name, thread and process ids together with a random
+/// seed, so that processes forked on the same host at the same instant
+/// still generate different boundaries.
+fn boundary_hash(host: impl Hash, seed: u64) -> u64 {
     let mut s = DefaultHasher::new();
-    gethostname::gethostname().hash(&mut s);
+    host.hash(&mut s);
     thread::current().id().hash(&mut s);
-    let hash = s.finish();
+    #[cfg(not(target_arch = "wasm32"))]
+    std::process::id().hash(&mut s);
+    seed.hash(&mut s);
+    s.finish()
+}
 
+fn format_boundary(separator: &str, time: u128, hash: u64, counter: u64) -> String {
     format!(
         "{:x}{}{:x}{}{:x}",
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_else(|_| Duration::new(0, 0))
-            .as_nanos(),
+        time,
         separator,
-        COUNTER.with(|c| {
-            hash.wrapping_add(c.replace(c.get() + 1))
-                .wrapping_mul(11400714819323198485u64)
-        }),
+        hash.wrapping_add(counter)
+            .wrapping_mul(11400714819323198485u64),
         separator,
         hash,
     )
 }
 
+/// Returns a random value generated once per process.
+fn process_seed() -> u64 {
+    static SEED: OnceLock<u64> = OnceLock::new();
+    *SEED.get_or_init(random_seed)
+}
+
+#[cfg(all(feature = "getrandom", not(target_arch = "wasm32")))]
+fn random_seed() -> u64 {
+    getrandom::u64().unwrap_or(0)
+}
+
+#[cfg(not(all(feature = "getrandom", not(target_arch = "wasm32"))))]
+fn random_seed() -> u64 {
+    0
+}
+
 impl<'x> MimePart<'x> {
     /// Create a new MIME part.
     pub fn new(
@@ -173,6 +621,150 @@ impl<'x> MimePart<'x> {
         }
     }
 
+    /// Create an application/octet-stream attachment holding data that was
+    /// encrypted by the caller, naming the algorithm used in an
+    /// X-Encryption-Algorithm header. The contents are always base64 encoded.
+    pub fn new_encrypted_attachment(
+        ciphertext: Vec<u8>,
+        filename: impl Into<Cow<'x, str>>,
+        algorithm: &str,
+    ) -> Self {
+        MimePart::new("application/octet-stream", ciphertext)
+            .attachment(filename)
+            .header("X-Encryption-Algorithm", Text::new(algorithm.to_string()))
+    }
+
+    /// Create an opaque S/MIME application/pkcs7-mime attachment from the
+    /// DER encoded CMS object. The contents are always base64 encoded.
+    pub fn new_smime(smime_type: SmimeType, der_bytes: Vec<u8>) -> Self {
+        MimePart::new(
+            ContentType::new("application/pkcs7-mime")
+                .attribute("smime-type", smime_type.as_str())
+                .attribute("name", smime_type.filename()),
+            der_bytes,
+        )
+        .attachment(smime_type.filename())
+    }
+
+    /// Create a message/rfc822 part embedding a message, or a message/global
+    /// part (RFC 6532) when its header section contains non-ASCII bytes.
+    /// Message/rfc822 parts are written as 7bit or 8bit, since RFC 2046
+    /// does not allow other encodings for them.
+    pub fn new_message(message: impl Into<Cow<'x, [u8]>>) -> Self {
+        let message = message.into();
+        let header_len = message
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .or_else(|| message.windows(2).position(|window| window == b"\n\n"))
+            .unwrap_or(message.len());
+        if !message[..header_len].is_ascii() {
+            MimePart::new_message_global(message)
+        } else {
+            let transfer_encoding = if message.is_ascii() { "7bit" } else { "8bit" };
+            MimePart::new("message/rfc822", BodyPart::Binary(message))
+                .transfer_encoding(transfer_encoding)
+        }
+    }
+
+    /// Create a message/global part (RFC 6532) embedding an internationalized
+    /// message. Unlike message/rfc822, its contents may be base64 encoded,
+    /// which is done when they are not 7bit.
+    pub fn new_message_global(message: impl Into<Cow<'x, [u8]>>) -> Self {
+        let message = message.into();
+        if message.is_ascii() {
+            MimePart::new("message/global", BodyPart::Binary(message)).transfer_encoding("7bit")
+        } else {
+            MimePart::new("message/global", BodyPart::Binary(message))
+        }
+    }
+
+    /// Create a text/plain MIME part whose body is written by `f`, for
+    /// example with `write!`, into a string buffer. Returns the error
+    /// returned by `f`, if any.
+    pub fn new_text_from(
+        f: impl FnOnce(&mut String) -> std::fmt::Result,
+    ) -> Result<Self, std::fmt::Error> {
+        let mut text = String::new();
+        f(&mut text)?;
+        Ok(MimePart::new("text/plain", BodyPart::Text(text.into())))
+    }
+
+    /// Create a text/html MIME part from an HTML document after removing
+    /// its active content, see [`crate::html::sanitize_html`].
+    #[cfg(feature = "sanitize")]
+    pub fn new_html_sanitized(html: impl AsRef<str>) -> Self {
+        MimePart::new(
+            "text/html",
+            BodyPart::Text(crate::html::sanitize_html(html.as_ref()).into()),
+        )
+    }
+
+    /// Create a `text/enriched; charset=utf-8` part. text/enriched, defined in
+    /// [RFC 1896](https://www.rfc-editor.org/rfc/rfc1896), is a simple rich
+    /// text format in which text is enclosed by formatting commands such as
+    /// `<bold>` and `</bold>`, `<italic>` and `</italic>` or `<fontfamily>`,
+    /// commands take arguments in `<param>` commands and `<<` stands for a
+    /// literal `<`. When the `validate_enriched` feature is enabled, writing
+    /// the part fails with [`Error::InvalidEnrichedText`] if its formatting
+    /// commands are malformed or unbalanced.
+    pub fn new_text_rich(enriched_text: impl Into<String>) -> Self {
+        MimePart::new("text/enriched", BodyPart::Text(enriched_text.into().into()))
+    }
+
+    /// Create a binary MIME part from contents that are already encoded with
+    /// the given Content-Transfer-Encoding. The contents are written as is,
+    /// without detecting or applying an encoding.
+    pub fn new_binary_with_encoding(
+        c_type: impl Into<String>,
+        encoded_data: impl Into<Vec<u8>>,
+        cte: TransferEncoding,
+    ) -> Self {
+        MimePart::new(
+            ContentType::new(c_type.into()),
+            BodyPart::Binary(encoded_data.into().into()),
+        )
+        .transfer_encoding(cte.as_str())
+    }
+
+    /// Create a binary attachment whose content type is picked from the
+    /// filename extension, see [`content_type_for_extension`]. When the
+    /// extension is missing, unknown or does not match the magic number of
+    /// a PNG, JPEG, GIF, PDF or ZIP file, the detected type is used instead,
+    /// falling back to application/octet-stream.
+    #[cfg(feature = "mime-types")]
+    pub fn new_attachment_auto(
+        filename: impl Into<Cow<'x, str>>,
+        contents: impl Into<Cow<'x, [u8]>>,
+    ) -> Self {
+        let filename = filename.into();
+        let contents = contents.into();
+        let by_extension = std::path::Path::new(filename.as_ref())
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(content_type_for_extension);
+        let content_type = match (sniff_content_type(&contents), by_extension) {
+            (Some("application/zip"), Some(by_extension)) if is_zip_based(by_extension) => {
+                by_extension
+            }
+            (Some(sniffed), _) => sniffed,
+            (None, Some(by_extension)) => by_extension,
+            (None, None) => "application/octet-stream",
+        };
+        MimePart::new(content_type, BodyPart::Binary(contents)).attachment(filename)
+    }
+
+    /// Parse a MIME part, or a whole message, from its raw bytes. Only the
+    /// subset of MIME written by this crate is supported: text and binary
+    /// parts encoded as 7bit, 8bit, quoted-printable or base64, and
+    /// multipart/* parts. Bodies are decoded and the Content-Transfer-Encoding
+    /// header is removed, so the part is encoded again when written. Text
+    /// parts in a charset other than UTF-8 are kept as binary contents.
+    /// Content-Type and Content-Disposition headers are parsed into
+    /// [`ContentType`] values and all other headers are kept as raw values.
+    pub fn from_raw_mime(raw: impl Into<Vec<u8>>) -> Result<MimePart<'static>, ParseError> {
+        parse_part(&raw.into(), 0)
+    }
+
     /// Create a new raw MIME part that includes both headers and body.
     pub fn raw(contents: impl Into<BodyPart<'x>>) -> Self {
         Self {
@@ -182,25 +774,60 @@ impl<'x> MimePart<'x> {
     }
 
     /// Set the attachment filename of a MIME part.
+    ///
+    /// Attachment and inline dispositions share a single Content-Disposition
+    /// header: the last call to `attachment` or `inline` sets the disposition
+    /// type, while the filename is kept. Calling `attachment` again replaces
+    /// the filename.
     pub fn attachment(mut self, filename: impl Into<Cow<'x, str>>) -> Self {
-        self.headers.push((
-            "Content-Disposition".into(),
-            ContentType::new("attachment")
-                .attribute("filename", filename)
-                .into(),
-        ));
+        let disposition = self.disposition_mut();
+        disposition.c_type = "attachment".into();
+        let filename = filename.into();
+        if let Some((_, value)) = disposition
+            .attributes
+            .iter_mut()
+            .find(|(key, _)| key.eq_ignore_ascii_case("filename"))
+        {
+            *value = filename;
+        } else {
+            disposition.attributes.push(("filename".into(), filename));
+        }
         self
     }
 
-    /// Set the MIME part as inline.
+    /// Set the attachment filename of a MIME part after cleaning it up with
+    /// [`sanitize_filename`], limiting it to [`MAX_FILENAME_LEN`] characters.
+    /// Use `attachment` to set the filename exactly as given.
+    pub fn attachment_sanitized(self, filename: &str) -> Self {
+        self.attachment(sanitize_filename(filename, MAX_FILENAME_LEN))
+    }
+
+    /// Set the MIME part as inline. Any filename previously set with
+    /// `attachment` is kept.
     pub fn inline(mut self) -> Self {
-        self.headers.push((
-            "Content-Disposition".into(),
-            ContentType::new("inline").into(),
-        ));
+        self.disposition_mut().c_type = "inline".into();
         self
     }
 
+    fn disposition_mut(&mut self) -> &mut ContentType<'x> {
+        let pos = if let Some(pos) = self.headers.iter().position(|(name, value)| {
+            name.eq_ignore_ascii_case("Content-Disposition")
+                && matches!(value, HeaderType::ContentType(_))
+        }) {
+            pos
+        } else {
+            self.headers.push((
+                "Content-Disposition".into(),
+                ContentType::new("inline").into(),
+            ));
+            self.headers.len() - 1
+        };
+        match &mut self.headers[pos].1 {
+            HeaderType::ContentType(disposition) => disposition,
+            _ => unreachable!(),
+        }
+    }
+
     /// Set the Content-Language header of a MIME part.
     pub fn language(mut self, value: impl Into<Cow<'x, str>>) -> Self {
         self.headers
@@ -215,6 +842,48 @@ impl<'x> MimePart<'x> {
         self
     }
 
+    /// Returns the identifier of the last Content-ID header of a MIME part,
+    /// without the surrounding angle brackets.
+    pub(crate) fn content_id(&self) -> Option<&str> {
+        self.headers
+            .iter()
+            .rev()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Content-ID"))
+            .find_map(|(_, value)| match value {
+                HeaderType::MessageId(id) => id.id.first().map(|id| id.as_ref()),
+                HeaderType::Raw(Raw { raw: id }) | HeaderType::Text(Text { text: id }) => {
+                    Some(id.trim().trim_start_matches('<').trim_end_matches('>'))
+                }
+                _ => None,
+            })
+    }
+
+    /// Set the Content-ID header of a MIME part and return the `cid:` URL
+    /// used to reference it, for example from an HTML `<img src="...">`.
+    /// Surrounding angle brackets, a `cid:` prefix and characters not allowed
+    /// in a Content-ID are removed; an empty result is replaced with a
+    /// generated identifier.
+    pub fn set_cid(&mut self, cid: &str) -> String {
+        let cid = cid.trim();
+        let cid = cid
+            .get(..4)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("cid:"))
+            .map_or(cid, |_| &cid[4..]);
+        let mut cid = cid
+            .chars()
+            .filter(|ch| ch.is_ascii_graphic() && !matches!(ch, '<' | '>' | '"' | '\\'))
+            .collect::<String>();
+        if cid.is_empty() {
+            cid = format!("{}@localhost", make_boundary("."));
+        }
+
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case("Content-ID"));
+        self.headers
+            .push(("Content-ID".into(), MessageId::new(cid.clone()).into()));
+        format!("cid:{}", cid)
+    }
+
     /// Set the Content-Location header of a MIME part.
     pub fn location(mut self, value: impl Into<Cow<'x, str>>) -> Self {
         self.headers
@@ -222,6 +891,16 @@ impl<'x> MimePart<'x> {
         self
     }
 
+    /// Set the Content-Duration header (RFC 2424) of an audio or video
+    /// part, in seconds.
+    pub fn content_duration(mut self, seconds: u32) -> Self {
+        self.headers.push((
+            "Content-Duration".into(),
+            Raw::new(seconds.to_string()).into(),
+        ));
+        self
+    }
+
     /// Disable automatic Content-Transfer-Encoding detection and treat this as a raw MIME part
     pub fn transfer_encoding(mut self, value: impl Into<Cow<'x, str>>) -> Self {
         self.headers
@@ -229,6 +908,78 @@ impl<'x> MimePart<'x> {
         self
     }
 
+    /// Generate a fresh boundary every time this multipart/* part is
+    /// written instead of reusing the boundary stored in its Content-Type
+    /// header, for example when sending a cloned part several times. The
+    /// stored boundary is removed, so a boundary set afterwards with
+    /// [`MimePart::with_custom_boundary`] is reused again. With `false` the
+    /// part is left unchanged.
+    pub fn regenerate_boundary(mut self, regenerate_boundary: bool) -> Self {
+        if regenerate_boundary {
+            for (name, value) in &mut self.headers {
+                if name.eq_ignore_ascii_case("Content-Type") {
+                    if let HeaderType::Raw(raw) = value {
+                        *value = ContentType::parse(&raw.raw).into();
+                    }
+                    if let HeaderType::ContentType(ct) = value {
+                        ct.remove_attribute("boundary");
+                    }
+                }
+            }
+        }
+        self
+    }
+
+    /// Returns true when a fresh boundary is generated every time this part
+    /// is written, which is the case for multipart/* parts without a
+    /// boundary in their Content-Type header, see
+    /// [`MimePart::regenerate_boundary`].
+    pub fn regenerates_boundary(&self) -> bool {
+        matches!(self.contents, BodyPart::Multipart(_))
+            && !self
+                .headers
+                .iter()
+                .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+                .any(|(_, value)| match value {
+                    HeaderType::ContentType(ct) => {
+                        ct.attribute_case_insensitive("boundary").is_some()
+                    }
+                    HeaderType::Raw(raw) => ContentType::parse(&raw.raw)
+                        .attribute_case_insensitive("boundary")
+                        .is_some(),
+                    _ => false,
+                })
+    }
+
+    /// Set the boundary of a multipart/* part, replacing any boundary already
+    /// present in its Content-Type header. Returns an error when the boundary
+    /// does not conform to RFC 2046, see [`validate_boundary`].
+    ///
+    /// The boundary is stored in the part, so clones of it are written with
+    /// the same boundary until [`MimePart::regenerate_boundary`] is called.
+    pub fn with_custom_boundary(
+        mut self,
+        boundary: impl Into<String>,
+    ) -> Result<Self, InvalidBoundaryError> {
+        let boundary = boundary.into();
+        validate_boundary(&boundary)?;
+
+        let pos = self
+            .headers
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case("Content-Type"));
+        let mut ct = match pos.map(|pos| self.headers.remove(pos).1) {
+            Some(HeaderType::ContentType(ct)) => ct,
+            Some(HeaderType::Raw(raw)) => ContentType::parse(&raw.raw),
+            _ => ContentType::new("multipart/mixed"),
+        };
+        ct.remove_attribute("boundary");
+        ct.attributes.push(("boundary".into(), boundary.into()));
+        self.headers
+            .insert(pos.unwrap_or(0), ("Content-Type".into(), ct.into()));
+        Ok(self)
+    }
+
     /// Set custom headers of a MIME part.
     pub fn header(
         mut self,
@@ -239,172 +990,582 @@ impl<'x> MimePart<'x> {
         self
     }
 
+    /// Returns the contents of a text or binary part before encoding, or
+    /// `None` for multipart/* parts.
+    pub fn body_bytes(&self) -> Option<&[u8]> {
+        match &self.contents {
+            BodyPart::Text(text) => Some(text.as_bytes()),
+            BodyPart::Binary(binary) => Some(binary.as_ref()),
+            BodyPart::Multipart(_) => None,
+        }
+    }
+
     /// Returns the part's size
-    pub fn size(&self) -> usize {
+    pub fn size(&self) -> u64 {
         match &self.contents {
-            BodyPart::Text(b) => b.len(),
-            BodyPart::Binary(b) => b.len(),
+            BodyPart::Text(b) => b.len() as u64,
+            BodyPart::Binary(b) => b.len() as u64,
             BodyPart::Multipart(bl) => bl.iter().map(|b| b.size()).sum(),
         }
     }
 
-    /// Add a body part to a multipart/* MIME part.
-    pub fn add_part(&mut self, part: MimePart<'x>) {
-        if let BodyPart::Multipart(ref mut parts) = self.contents {
-            parts.push(part);
+    /// Returns the size in bytes of this part's own headers as they will be
+    /// written, including folding and line endings. Child parts are not
+    /// included, nor are the headers generated at write time such as
+    /// Content-Transfer-Encoding or a multipart boundary parameter.
+    pub fn encoded_header_size(&self) -> usize {
+        let mut output = CountingWriter::new(io::sink());
+        for (header_name, header_value) in &self.headers {
+            let _ = output.write_all(header_name.as_bytes());
+            let _ = output.write_all(b": ");
+            let _ = header_value.write_header(&mut output, header_name.len() + 2);
         }
+        output.bytes_written as usize
     }
 
-    /// Write the MIME part to a writer.
-    pub fn write_part(self, mut output: impl Write) -> io::Result<usize> {
-        let mut stack = Vec::new();
-        let mut it = vec![self].into_iter();
-        let mut boundary: Option<Cow<str>> = None;
+    /// Returns the number of parts in this MIME tree, including this part,
+    /// with an attachment Content-Disposition.
+    pub fn count_attachments(&self) -> usize {
+        self.count_parts(|cd| cd.is_attachment())
+    }
 
-        loop {
-            while let Some(part) = it.next() {
-                if let Some(boundary) = boundary.as_ref() {
-                    output.write_all(b"\r\n--")?;
-                    output.write_all(boundary.as_bytes())?;
-                    output.write_all(b"\r\n")?;
-                }
-                match part.contents {
-                    BodyPart::Text(text) => {
-                        let mut is_attachment = false;
-                        let mut is_raw = part.headers.is_empty();
-
-                        for (header_name, header_value) in &part.headers {
-                            output.write_all(header_name.as_bytes())?;
-                            output.write_all(b": ")?;
-                            if !is_attachment && header_name == "Content-Disposition" {
-                                is_attachment = header_value
-                                    .as_content_type()
-                                    .map(|v| v.is_attachment())
-                                    .unwrap_or(false);
-                            } else if !is_raw && header_name == "Content-Transfer-Encoding" {
-                                is_raw = true;
-                            }
-                            header_value.write_header(&mut output, header_name.len() + 2)?;
-                        }
-                        if !is_raw {
-                            detect_encoding(text.as_bytes(), &mut output, !is_attachment)?;
-                        } else {
-                            if !part.headers.is_empty() {
-                                output.write_all(b"\r\n")?;
-                            }
-                            output.write_all(text.as_bytes())?;
-                        }
-                    }
-                    BodyPart::Binary(binary) => {
-                        let mut is_text = false;
-                        let mut is_attachment = false;
-                        let mut is_raw = part.headers.is_empty();
-
-                        for (header_name, header_value) in &part.headers {
-                            output.write_all(header_name.as_bytes())?;
-                            output.write_all(b": ")?;
-                            if !is_text && header_name == "Content-Type" {
-                                is_text = header_value
-                                    .as_content_type()
-                                    .map(|v| v.is_text())
-                                    .unwrap_or(false);
-                            } else if !is_attachment && header_name == "Content-Disposition" {
-                                is_attachment = header_value
-                                    .as_content_type()
-                                    .map(|v| v.is_attachment())
-                                    .unwrap_or(false);
-                            } else if !is_raw && header_name == "Content-Transfer-Encoding" {
-                                is_raw = true;
-                            }
-                            header_value.write_header(&mut output, header_name.len() + 2)?;
-                        }
+    /// Returns the number of parts in this MIME tree, including this part,
+    /// with an inline Content-Disposition.
+    pub fn count_inline(&self) -> usize {
+        self.count_parts(|cd| cd.is_inline())
+    }
 
-                        if !is_raw {
-                            if !is_text {
-                                output.write_all(b"Content-Transfer-Encoding: base64\r\n\r\n")?;
-                                base64_encode_mime(binary.as_ref(), &mut output, false)?;
-                            } else {
-                                detect_encoding(binary.as_ref(), &mut output, !is_attachment)?;
-                            }
-                        } else {
-                            if !part.headers.is_empty() {
-                                output.write_all(b"\r\n")?;
-                            }
-                            output.write_all(binary.as_ref())?;
-                        }
-                    }
-                    BodyPart::Multipart(parts) => {
-                        if boundary.is_some() {
-                            stack.push((it, boundary.take()));
-                        }
+    fn count_parts(&self, matches: impl Fn(&ContentType) -> bool) -> usize {
+        let mut count = 0;
+        let mut stack = vec![self];
+        while let Some(part) = stack.pop() {
+            if part.content_disposition().is_some_and(&matches) {
+                count += 1;
+            }
+            if let BodyPart::Multipart(parts) = &part.contents {
+                stack.extend(parts);
+            }
+        }
+        count
+    }
 
-                        let mut found_ct = false;
-                        for (header_name, header_value) in part.headers {
-                            output.write_all(header_name.as_bytes())?;
-                            output.write_all(b": ")?;
+    /// Returns true when the part is an image/* part with an inline
+    /// Content-Disposition.
+    pub fn is_inline_image(&self) -> bool {
+        self.content_type().is_some_and(|ct| ct.is_inline_image())
+            && self.content_disposition().is_some_and(|cd| cd.is_inline())
+    }
 
-                            if !found_ct && header_name.eq_ignore_ascii_case("Content-Type") {
-                                boundary = match header_value {
-                                    HeaderType::ContentType(mut ct) => {
-                                        let bpos = if let Some(pos) = ct
-                                            .attributes
-                                            .iter()
-                                            .position(|(a, _)| a.eq_ignore_ascii_case("boundary"))
-                                        {
-                                            pos
-                                        } else {
-                                            let pos = ct.attributes.len();
-                                            ct.attributes.push((
-                                                "boundary".into(),
-                                                make_boundary("_").into(),
-                                            ));
-                                            pos
-                                        };
-                                        ct.write_header(&mut output, 14)?;
-                                        ct.attributes.swap_remove(bpos).1.into()
-                                    }
-                                    HeaderType::Raw(raw) => {
-                                        if let Some(pos) = raw.raw.find("boundary=\"") {
-                                            if let Some(boundary) = raw.raw[pos..].split('"').nth(1)
-                                            {
-                                                Some(boundary.to_string().into())
-                                            } else {
-                                                Some(make_boundary("_").into())
-                                            }
-                                        } else {
-                                            let boundary = make_boundary("_");
-                                            output.write_all(raw.raw.as_bytes())?;
-                                            output.write_all(b"; boundary=\"")?;
-                                            output.write_all(boundary.as_bytes())?;
-                                            output.write_all(b"\"\r\n")?;
-                                            Some(boundary.into())
-                                        }
-                                    }
-                                    _ => panic!("Unsupported Content-Type header value."),
-                                };
-                                found_ct = true;
-                            } else {
-                                header_value.write_header(&mut output, header_name.len() + 2)?;
-                            }
-                        }
+    /// Returns true when the part is a text/plain or text/html part that
+    /// is not an attachment.
+    pub fn is_text_body(&self) -> bool {
+        self.content_type()
+            .is_some_and(|ct| ct.c_type == "text/plain" || ct.c_type == "text/html")
+            && !self
+                .content_disposition()
+                .is_some_and(|cd| cd.is_attachment())
+    }
 
-                        if !found_ct {
-                            output.write_all(b"Content-Type: ")?;
-                            let boundary_ = make_boundary("_");
-                            ContentType::new("multipart/mixed")
-                                .attribute("boundary", &boundary_)
-                                .write_header(&mut output, 14)?;
-                            boundary = Some(boundary_.into());
-                        }
+    /// Returns true when the part has a MIME-Version header, which is only
+    /// present on top-level messages.
+    pub fn is_top_level_message(&self) -> bool {
+        self.headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("MIME-Version"))
+    }
 
-                        output.write_all(b"\r\n")?;
-                        it = parts.into_iter();
-                    }
-                }
-            }
-            if let Some(boundary) = boundary {
-                output.write_all(b"\r\n--")?;
-                output.write_all(boundary.as_bytes())?;
+    /// Returns true when the part is multipart/signed.
+    pub fn is_signed(&self) -> bool {
+        self.content_type()
+            .is_some_and(|ct| ct.c_type.eq_ignore_ascii_case("multipart/signed"))
+    }
+
+    /// Returns true when the part is multipart/encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.content_type()
+            .is_some_and(|ct| ct.c_type.eq_ignore_ascii_case("multipart/encrypted"))
+    }
+
+    /// Returns the Content-Transfer-Encoding this part will be written with,
+    /// or `None` for multipart/* and raw parts without one. This is the
+    /// encoding `write_part` selects, computed without serializing the part.
+    pub fn content_transfer_encoding(&self) -> Option<&str> {
+        self.content_transfer_encoding_with_options(&WriteOptions::default())
+    }
+
+    /// Returns the Content-Transfer-Encoding this part will be written with
+    /// using the provided options.
+    pub fn content_transfer_encoding_with_options(&self, options: &WriteOptions) -> Option<&str> {
+        let contents = match &self.contents {
+            BodyPart::Text(text) => text.as_bytes(),
+            BodyPart::Binary(binary) => binary.as_ref(),
+            BodyPart::Multipart(_) => return None,
+        };
+        match leaf_encoding(
+            &self.headers,
+            contents,
+            matches!(self.contents, BodyPart::Binary(_)),
+            options,
+        ) {
+            LeafEncoding::Raw => self
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("Content-Transfer-Encoding"))
+                .and_then(|(_, value)| match value {
+                    HeaderType::Raw(raw) => Some(raw.raw.as_ref()),
+                    HeaderType::Text(text) => Some(text.text.as_ref()),
+                    _ => None,
+                }),
+            LeafEncoding::Encoded { encoding, .. } => Some(encoding.as_str()),
+        }
+    }
+
+    /// Returns the MIME type of the part, such as `text/html`, without its
+    /// parameters.
+    pub fn content_type_str(&self) -> Option<&str> {
+        self.content_type().map(|ct| ct.c_type.as_ref())
+    }
+
+    /// Returns the value of the charset parameter of the Content-Type header.
+    pub fn charset(&self) -> Option<&str> {
+        self.content_type()
+            .and_then(|ct| ct.attribute_case_insensitive("charset"))
+    }
+
+    /// Returns the value of the filename parameter of the Content-Disposition
+    /// header.
+    pub fn filename(&self) -> Option<&str> {
+        self.content_disposition().and_then(|cd| {
+            cd.attribute_case_insensitive("filename")
+                .or_else(|| cd.attribute_case_insensitive("filename*"))
+        })
+    }
+
+    pub(crate) fn content_type(&self) -> Option<&ContentType<'x>> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+            .and_then(|(_, value)| value.as_content_type())
+    }
+
+    fn content_disposition(&self) -> Option<&ContentType<'x>> {
+        self.headers
+            .iter()
+            .rev()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Disposition"))
+            .and_then(|(_, value)| value.as_content_type())
+    }
+
+    /// Returns an error when a part declared as text/* contains data that
+    /// looks binary, such as a file attached with the wrong content type.
+    fn check_binary_text(&self) -> io::Result<()> {
+        let contents = match &self.contents {
+            BodyPart::Text(text) => text.as_bytes(),
+            BodyPart::Binary(binary) => binary.as_ref(),
+            BodyPart::Multipart(_) => return Ok(()),
+        };
+        if !self.content_type().is_some_and(|ct| ct.is_text()) {
+            return Ok(());
+        }
+
+        if let Some(signature) = sniff_binary(contents) {
+            Err(Error::BinaryTextPart {
+                part: self.name(),
+                signature,
+            }
+            .into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns an error when the part has headers but no Content-Type. Parts
+    /// without headers are written as is, so their contents may start with
+    /// their own headers.
+    fn check_content_type(&self) -> io::Result<()> {
+        if !self.headers.is_empty()
+            && !self
+                .headers
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+        {
+            Err(Error::MissingContentType.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns an error when an address header of the part, such as the From
+    /// header of a message/rfc822 part, has an empty display name.
+    fn check_display_names(&self) -> io::Result<()> {
+        if self.headers.iter().any(
+            |(_, value)| matches!(value, HeaderType::Address(address) if address.has_empty_name()),
+        ) {
+            Err(Error::EmptyDisplayName.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns an error when a text/enriched part contains malformed or
+    /// unbalanced formatting commands.
+    #[cfg(feature = "validate_enriched")]
+    fn check_enriched(&self) -> io::Result<()> {
+        let contents = match &self.contents {
+            BodyPart::Text(text) => text.as_ref(),
+            BodyPart::Binary(binary) => match std::str::from_utf8(binary) {
+                Ok(text) => text,
+                Err(_) => return Ok(()),
+            },
+            BodyPart::Multipart(_) => return Ok(()),
+        };
+        if !self
+            .content_type_str()
+            .is_some_and(|c_type| c_type.eq_ignore_ascii_case("text/enriched"))
+        {
+            return Ok(());
+        }
+
+        match invalid_enriched_command(contents) {
+            Some(command) => Err(Error::InvalidEnrichedText(command).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns an error when the part has more headers or a larger header
+    /// block than allowed by the write options.
+    fn check_header_limits(&self, options: &WriteOptions) -> io::Result<()> {
+        if self.headers.len() > options.max_headers {
+            Err(Error::TooManyHeaders(self.name()).into())
+        } else if !self.headers.is_empty() && self.encoded_header_size() > options.max_header_size {
+            Err(Error::HeadersTooLarge(self.name()).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Adds the charset detected from the contents of a text/* part without
+    /// one to its Content-Type, see [`detect_charset`], or changes its type to
+    /// application/octet-stream when no charset is detected.
+    pub(crate) fn detect_text_charset(mut self) -> Self {
+        let charset = match &self.contents {
+            BodyPart::Text(text) => detect_charset(text.as_bytes()),
+            BodyPart::Binary(contents) => detect_charset(contents),
+            BodyPart::Multipart(_) => return self,
+        };
+        for (name, value) in &mut self.headers {
+            if let HeaderType::ContentType(ct) = value {
+                if name.eq_ignore_ascii_case("Content-Type")
+                    && ct.c_type.starts_with("text/")
+                    && ct.attribute_case_insensitive("charset").is_none()
+                {
+                    match charset {
+                        Some(charset) => ct.attributes.push(("charset".into(), charset.into())),
+                        None => ct.c_type = "application/octet-stream".into(),
+                    }
+                }
+            }
+        }
+        self
+    }
+
+    /// Returns a name identifying the part in errors: its filename if any,
+    /// otherwise its content type.
+    fn name(&self) -> String {
+        self.filename()
+            .or_else(|| self.content_type_str())
+            .unwrap_or("part")
+            .to_string()
+    }
+
+    /// Returns an estimate of the heap bytes owned by the part tree: the
+    /// capacity of owned header names and values, text and binary contents
+    /// and the vectors holding headers and child parts. Borrowed data is
+    /// not counted and allocator overhead is ignored, so the result is a
+    /// lower bound.
+    pub fn memory_usage(&self) -> usize {
+        let mut usage = 0;
+        let mut stack = vec![self];
+        while let Some(part) = stack.pop() {
+            usage += vec_memory_usage(&part.headers)
+                + part
+                    .headers
+                    .iter()
+                    .map(|(name, value)| cow_memory_usage(name) + value.memory_usage())
+                    .sum::<usize>();
+            usage += match &part.contents {
+                BodyPart::Text(text) => cow_memory_usage(text),
+                BodyPart::Binary(Cow::Owned(binary)) => binary.capacity(),
+                BodyPart::Binary(Cow::Borrowed(_)) => 0,
+                BodyPart::Multipart(parts) => {
+                    stack.extend(parts);
+                    vec_memory_usage(parts)
+                }
+            };
+        }
+        usage
+    }
+
+    /// Add a body part to a multipart/* MIME part, returning a reference to
+    /// the newly added part or `None` if this part is not multipart.
+    pub fn add_part(&mut self, part: MimePart<'x>) -> Option<&mut MimePart<'x>> {
+        if let BodyPart::Multipart(ref mut parts) = self.contents {
+            parts.push(part);
+            parts.last_mut()
+        } else {
+            None
+        }
+    }
+
+    /// Remove the body part at the given position from a multipart/* MIME part.
+    pub fn remove_part(&mut self, index: usize) -> Option<MimePart<'x>> {
+        match self.contents {
+            BodyPart::Multipart(ref mut parts) if index < parts.len() => Some(parts.remove(index)),
+            _ => None,
+        }
+    }
+
+    /// Generate the boundaries of all multipart/* parts that do not have one
+    /// yet, in the same order as they would be generated when writing, so
+    /// that writing the part again produces the same output.
+    pub(crate) fn assign_boundaries(&mut self, options: &WriteOptions) -> io::Result<()> {
+        let mut boundary_seq = 0;
+        let mut stack = vec![self];
+
+        while let Some(part) = stack.pop() {
+            let MimePart { headers, contents } = part;
+            let BodyPart::Multipart(parts) = contents else {
+                continue;
+            };
+
+            match headers
+                .iter_mut()
+                .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+            {
+                Some((_, value)) => {
+                    let mut ct = match value {
+                        HeaderType::ContentType(ct) => ct.clone(),
+                        HeaderType::Raw(raw) => ContentType::parse(&raw.raw),
+                        _ => return Err(Error::UnsupportedContentType.into()),
+                    };
+                    if !ct
+                        .attributes
+                        .iter()
+                        .any(|(a, _)| a.eq_ignore_ascii_case("boundary"))
+                    {
+                        ct.attributes.push((
+                            "boundary".into(),
+                            options.make_boundary("_", &mut boundary_seq)?.into(),
+                        ));
+                    }
+                    *value = ct.into();
+                }
+                None => headers.push((
+                    "Content-Type".into(),
+                    ContentType::new("multipart/mixed")
+                        .attribute("boundary", options.make_boundary("_", &mut boundary_seq)?)
+                        .into(),
+                )),
+            }
+            stack.extend(parts.iter_mut().rev());
+        }
+
+        Ok(())
+    }
+
+    /// Write the headers of the part exactly as `write_part_with_options`
+    /// writes them, without the blank line that ends them and without
+    /// encoding the contents. The boundaries of multipart/* parts must
+    /// already be assigned with `assign_boundaries`.
+    pub(crate) fn write_headers_with_options(
+        &self,
+        mut output: impl Write,
+        options: &WriteOptions,
+    ) -> io::Result<()> {
+        let mut headers = self.headers.clone();
+        normalize_content_type(&mut headers, options.strict)?;
+        let (contents, is_binary) = match &self.contents {
+            BodyPart::Text(text) => (text.as_bytes(), false),
+            BodyPart::Binary(binary) => (binary.as_ref(), true),
+            BodyPart::Multipart(_) => {
+                for (header_name, header_value) in &headers {
+                    if !header_name.eq_ignore_ascii_case("Content-Transfer-Encoding") {
+                        write_header_name(header_name, &mut output)?;
+                        header_value.write_header_with_fold(
+                            &mut output,
+                            header_name.len() + 2,
+                            options.fold(),
+                        )?;
+                    }
+                }
+                return Ok(());
+            }
+        };
+        let encoding = leaf_encoding(&headers, contents, is_binary, options);
+        write_leaf_headers(&headers, &encoding, output, options)
+    }
+
+    /// Write the MIME part to a writer.
+    pub fn write_part(self, output: impl Write) -> io::Result<u64> {
+        self.write_part_with_options(output, &WriteOptions::default())
+    }
+
+    /// Write the MIME part as a complete message, preceded by a
+    /// `MIME-Version: 1.0` header unless the part already has one. Use
+    /// `write_part` for parts embedded in a multipart/* part.
+    pub fn write_as_top_level_message(self, output: impl Write) -> io::Result<u64> {
+        self.write_as_top_level_message_with_options(output, &WriteOptions::default())
+    }
+
+    /// Write the MIME part as a complete message using the provided options,
+    /// see [`MimePart::write_as_top_level_message`].
+    pub fn write_as_top_level_message_with_options(
+        self,
+        mut output: impl Write,
+        options: &WriteOptions,
+    ) -> io::Result<u64> {
+        if self.is_top_level_message() {
+            return self.write_part_with_options(output, options);
+        }
+        const MIME_VERSION: &[u8] = b"MIME-Version: 1.0\r\n";
+        output.write_all(MIME_VERSION)?;
+        Ok(MIME_VERSION.len() as u64 + self.write_part_with_options(output, options)?)
+    }
+
+    /// Write the MIME part to a writer using the provided options.
+    ///
+    /// Parts own their children, so a MIME tree cannot reference itself and
+    /// writing always terminates; the nesting depth and number of parts are
+    /// further bounded by [`WriteOptions::max_depth`] and
+    /// [`WriteOptions::max_parts`]. In strict mode, body parts of multipart/*
+    /// parts that have headers must include a Content-Type, see
+    /// [`Error::MissingContentType`].
+    ///
+    /// Content-Transfer-Encoding headers of multipart/* parts are not
+    /// written, since RFC 2045 section 6.4 does not allow encoding them.
+    pub fn write_part_with_options(
+        self,
+        output: impl Write,
+        options: &WriteOptions,
+    ) -> io::Result<u64> {
+        let mut output = CountingWriter::new(output);
+        let mut boundary_seq = 0;
+        let mut stack = Vec::new();
+        let mut it = vec![self].into_iter();
+        let mut boundary: Option<Cow<str>> = None;
+        let mut part_count = 0;
+
+        loop {
+            while let Some(mut part) = it.next() {
+                part_count += 1;
+                if part_count > options.max_parts {
+                    return Err(Error::TooManyParts.into());
+                } else if stack.len() + usize::from(boundary.is_some()) > options.max_depth {
+                    return Err(Error::NestingTooDeep.into());
+                }
+                #[cfg(feature = "log")]
+                log::debug!(
+                    "Writing {} part at depth {}",
+                    content_type_name(&part.headers).unwrap_or("<none>"),
+                    stack.len() + usize::from(boundary.is_some())
+                );
+                if let Some(boundary) = boundary.as_ref() {
+                    #[cfg(feature = "log")]
+                    log::debug!("Writing boundary {:?}", boundary);
+                    output.write_all(b"\r\n--")?;
+                    output.write_all(boundary.as_bytes())?;
+                    output.write_all(b"\r\n")?;
+                }
+                part.check_header_limits(options)?;
+                normalize_content_type(&mut part.headers, options.strict)?;
+                if options.strict {
+                    part.check_binary_text()?;
+                    part.check_display_names()?;
+                    if boundary.is_some() {
+                        part.check_content_type()?;
+                    }
+                }
+                #[cfg(feature = "validate_enriched")]
+                part.check_enriched()?;
+                match part.contents {
+                    BodyPart::Text(text) => {
+                        write_leaf(&part.headers, text.as_bytes(), false, &mut output, options)?;
+                    }
+                    BodyPart::Binary(binary) => {
+                        write_leaf(&part.headers, binary.as_ref(), true, &mut output, options)?;
+                    }
+                    BodyPart::Multipart(mut parts) => {
+                        if parts.is_empty() {
+                            if options.strict {
+                                return Err(Error::EmptyMultipart.into());
+                            }
+                            parts.push(MimePart::new("text/plain", ""));
+                        }
+                        if boundary.is_some() {
+                            stack.push((it, boundary.take()));
+                        }
+
+                        let mut found_ct = false;
+                        for (header_name, header_value) in part.headers {
+                            if header_name.eq_ignore_ascii_case("Content-Transfer-Encoding") {
+                                continue;
+                            }
+                            #[cfg(feature = "log")]
+                            log::trace!("Writing header {:?}", header_name);
+                            write_header_name(&header_name, &mut output)?;
+
+                            if !found_ct && header_name.eq_ignore_ascii_case("Content-Type") {
+                                let mut ct = match header_value {
+                                    HeaderType::ContentType(ct) => ct,
+                                    HeaderType::Raw(raw) => ContentType::parse(&raw.raw),
+                                    _ => return Err(Error::UnsupportedContentType.into()),
+                                };
+                                let bpos = if let Some(pos) = ct
+                                    .attributes
+                                    .iter()
+                                    .position(|(a, _)| a.eq_ignore_ascii_case("boundary"))
+                                {
+                                    validate_boundary(&ct.attributes[pos].1)
+                                        .map_err(Error::InvalidBoundary)?;
+                                    pos
+                                } else {
+                                    ct.attributes.push((
+                                        "boundary".into(),
+                                        options.make_boundary("_", &mut boundary_seq)?.into(),
+                                    ));
+                                    ct.attributes.len() - 1
+                                };
+                                ct.write_header_with_fold(&mut output, 14, options.fold())?;
+                                boundary = Some(ct.attributes.swap_remove(bpos).1);
+                                found_ct = true;
+                            } else {
+                                header_value.write_header_with_fold(
+                                    &mut output,
+                                    header_name.len() + 2,
+                                    options.fold(),
+                                )?;
+                            }
+                        }
+
+                        if !found_ct {
+                            output.write_all(b"Content-Type: ")?;
+                            let boundary_ = options.make_boundary("_", &mut boundary_seq)?;
+                            ContentType::new("multipart/mixed")
+                                .attribute("boundary", &boundary_)
+                                .write_header_with_fold(&mut output, 14, options.fold())?;
+                            boundary = Some(boundary_.into());
+                        }
+
+                        output.write_all(b"\r\n")?;
+                        it = parts.into_iter();
+                    }
+                }
+            }
+            if let Some(boundary) = boundary {
+                #[cfg(feature = "log")]
+                log::debug!("Writing closing boundary {:?}", boundary);
+                output.write_all(b"\r\n--")?;
+                output.write_all(boundary.as_bytes())?;
                 output.write_all(b"--\r\n")?;
             }
             if let Some((prev_it, prev_boundary)) = stack.pop() {
@@ -414,30 +1575,471 @@ impl<'x> MimePart<'x> {
                 break;
             }
         }
-        Ok(0)
+
+        if output.bytes_written > 0 && output.last_bytes != *b"\r\n" {
+            output.write_all(b"\r\n")?;
+        }
+
+        output.flush()?;
+
+        #[cfg(feature = "log")]
+        log::debug!("Wrote MIME part ({} bytes)", output.bytes_written);
+
+        Ok(output.bytes_written)
+    }
+
+    /// Write the MIME part to a `futures::io::AsyncWrite` writer.
+    ///
+    /// The part is first serialized into memory and then copied to `output`
+    /// in fixed-size chunks, so the whole encoded part is held in memory
+    /// while it is written.
+    #[cfg(feature = "futures")]
+    pub async fn write_part_async_futures(
+        self,
+        output: impl futures::io::AsyncWrite + Unpin,
+    ) -> io::Result<u64> {
+        self.write_part_async_futures_with_options(output, &WriteOptions::default())
+            .await
+    }
+
+    /// Write the MIME part to a `futures::io::AsyncWrite` writer using the
+    /// provided options. Like [`MimePart::write_part_async_futures`], this
+    /// buffers the encoded part in memory before writing it.
+    #[cfg(feature = "futures")]
+    pub async fn write_part_async_futures_with_options(
+        self,
+        mut output: impl futures::io::AsyncWrite + Unpin,
+        options: &WriteOptions,
+    ) -> io::Result<u64> {
+        use futures::io::AsyncWriteExt;
+
+        let buffer = self.write_part_buffered(options)?;
+        let mut bytes_written = 0;
+        for chunk in buffer.chunks(CHUNK_SIZE) {
+            output.write_all(chunk).await?;
+            bytes_written += chunk.len() as u64;
+        }
+        output.flush().await?;
+        Ok(bytes_written)
+    }
+
+    /// Serialize the whole MIME part into an in-memory buffer, which the
+    /// async writers then copy out in `CHUNK_SIZE` pieces.
+    #[cfg(feature = "futures")]
+    fn write_part_buffered(self, options: &WriteOptions) -> io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(self.size().saturating_add(1024) as usize);
+        self.write_part_with_options(&mut output, options)?;
+        Ok(output)
+    }
+}
+
+#[cfg(feature = "futures")]
+const CHUNK_SIZE: usize = 8192;
+
+#[cfg(feature = "log")]
+fn content_type_name<'y>(headers: &'y [(Cow<str>, HeaderType)]) -> Option<&'y str> {
+    headers.iter().find_map(|(name, value)| {
+        if name.eq_ignore_ascii_case("Content-Type") {
+            match value {
+                HeaderType::ContentType(ct) => Some(ct.c_type.as_ref()),
+                HeaderType::Raw(raw) => Some(raw.raw.as_ref()),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    })
+}
+
+struct CountingWriter<W: Write> {
+    inner: W,
+    bytes_written: u64,
+    last_bytes: [u8; 2],
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            bytes_written: 0,
+            last_bytes: [0, 0],
+        }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes_written = self.inner.write(buf)?;
+        match bytes_written {
+            0 => (),
+            1 => self.last_bytes = [self.last_bytes[1], buf[0]],
+            _ => self
+                .last_bytes
+                .copy_from_slice(&buf[bytes_written - 2..bytes_written]),
+        }
+        self.bytes_written += bytes_written as u64;
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Formats the part exactly as written by `write_part`, replacing invalid
+/// UTF-8 sequences. The whole part is serialized in memory, so this is only
+/// practical for small to medium sized parts, for example in tests and
+/// logs. Errors are formatted in place of the part.
+impl Display for MimePart<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut output = Vec::new();
+        match self.clone().write_part(&mut output) {
+            Ok(_) => f.write_str(&String::from_utf8_lossy(&output)),
+            Err(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Magic numbers of common binary formats that are sometimes passed as text.
+const BINARY_SIGNATURES: &[(&[u8], &str)] = &[
+    (b"%PDF-", "PDF"),
+    (b"PK\x03\x04", "ZIP"),
+    (b"\x89PNG\r\n\x1a\n", "PNG"),
+    (b"\xff\xd8\xff", "JPEG"),
+    (b"GIF8", "GIF"),
+    (b"\x1f\x8b", "gzip"),
+    (b"\x7fELF", "ELF"),
+];
+
+/// Magic numbers of the formats detected by `MimePart::new_attachment_auto`.
+#[cfg(feature = "mime-types")]
+const CONTENT_TYPE_SIGNATURES: &[(&[u8], &str)] = &[
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF8", "image/gif"),
+];
+
+/// Returns the content type registered for a file extension, ignoring case.
+#[cfg(feature = "mime-types")]
+pub fn content_type_for_extension(ext: &str) -> Option<&'static str> {
+    mime_guess::from_ext(ext).first_raw()
+}
+
+/// Returns the content type detected from the magic number of the contents.
+#[cfg(feature = "mime-types")]
+fn sniff_content_type(contents: &[u8]) -> Option<&'static str> {
+    CONTENT_TYPE_SIGNATURES
+        .iter()
+        .find(|(magic, _)| contents.starts_with(magic))
+        .map(|(_, content_type)| *content_type)
+}
+
+/// Returns true for formats stored as ZIP archives, such as OOXML and
+/// OpenDocument files.
+#[cfg(feature = "mime-types")]
+fn is_zip_based(content_type: &str) -> bool {
+    content_type.contains("zip")
+        || content_type.starts_with("application/vnd.openxmlformats-officedocument.")
+        || content_type.starts_with("application/vnd.oasis.opendocument.")
+        || content_type == "application/java-archive"
+}
+
+/// Returns the first malformed, mismatched or unclosed formatting command in
+/// a text/enriched document, as defined in RFC 1896: commands are names of up
+/// to 60 letters, digits and "-", "<<" is a literal "<" and the text of
+/// `<param>` commands is not interpreted.
+#[cfg(feature = "validate_enriched")]
+fn invalid_enriched_command(text: &str) -> Option<String> {
+    let mut open_commands: Vec<String> = Vec::new();
+    let mut rest = text;
+
+    while let Some(pos) = rest.find('<') {
+        rest = &rest[pos + 1..];
+        if let Some(literal) = rest.strip_prefix('<') {
+            rest = literal;
+            continue;
+        }
+
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => return Some(format!("<{}", rest)),
+        };
+        let command = &rest[..end];
+        rest = &rest[end + 1..];
+        let (is_closing, name) = match command.strip_prefix('/') {
+            Some(name) => (true, name),
+            None => (false, command),
+        };
+        if name.is_empty()
+            || name.len() > 60
+            || !name
+                .bytes()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == b'-')
+        {
+            return Some(format!("<{}>", command));
+        }
+
+        let name = name.to_ascii_lowercase();
+        if !is_closing {
+            if name == "param" {
+                // Parameters are not interpreted, skip to their end.
+                match rest.to_ascii_lowercase().find("</param>") {
+                    Some(end) => rest = &rest[end + 8..],
+                    None => return Some(format!("<{}>", command)),
+                }
+            } else {
+                open_commands.push(name);
+            }
+        } else if open_commands.last() == Some(&name) {
+            open_commands.pop();
+        } else {
+            return Some(format!("<{}>", command));
+        }
+    }
+
+    open_commands.pop().map(|name| format!("<{}>", name))
+}
+
+/// Returns the name of the binary signature detected in the contents, if
+/// any: a known magic number, NUL characters or a high ratio of control
+/// characters.
+fn sniff_binary(contents: &[u8]) -> Option<&'static str> {
+    if let Some((_, name)) = BINARY_SIGNATURES
+        .iter()
+        .find(|(magic, _)| contents.starts_with(magic))
+    {
+        return Some(name);
+    }
+
+    let sample = &contents[..contents.len().min(8192)];
+    if sample.contains(&0) {
+        Some("NUL characters")
+    } else if sample
+        .iter()
+        .filter(|&&ch| (ch < 0x20 && !matches!(ch, b'\t' | b'\r' | b'\n' | 0x0c)) || ch == 0x7f)
+        .count()
+        * 10
+        > sample.len()
+    {
+        Some("control characters")
+    } else {
+        None
+    }
+}
+
+/// Returns an error when a part declared as 7bit contains 8bit data, NUL
+/// characters or lines longer than 998 characters.
+fn check_7bit(headers: &[(Cow<str>, HeaderType)], contents: &[u8]) -> io::Result<()> {
+    let is_7bit = headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("Content-Transfer-Encoding")
+            && match value {
+                HeaderType::Raw(raw) => raw.raw.trim().eq_ignore_ascii_case("7bit"),
+                HeaderType::Text(text) => text.text.trim().eq_ignore_ascii_case("7bit"),
+                _ => false,
+            }
+    });
+    if is_7bit
+        && (contents.iter().any(|&ch| ch == 0 || ch >= 128)
+            || contents
+                .split(|&ch| ch == b'\n')
+                .any(|line| line.strip_suffix(b"\r").unwrap_or(line).len() > 998))
+    {
+        Err(Error::Invalid7Bit.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Adds the default subtype to a Content-Type without one, such as "text",
+/// which becomes text/plain. Other types default to multipart/mixed or
+/// application/octet-stream. In strict mode an error is returned instead.
+fn normalize_content_type(headers: &mut [(Cow<str>, HeaderType)], strict: bool) -> io::Result<()> {
+    for (name, value) in headers {
+        if let HeaderType::ContentType(ct) = value {
+            if name.eq_ignore_ascii_case("Content-Type") && !ct.c_type.contains('/') {
+                if strict {
+                    return Err(Error::InvalidContentType(ct.c_type.to_string()).into());
+                }
+                ct.c_type = match ct.c_type.as_ref() {
+                    "text" => "text/plain",
+                    "multipart" => "multipart/mixed",
+                    _ => "application/octet-stream",
+                }
+                .into();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How the contents of a text or binary part are written.
+enum LeafEncoding {
+    /// Written as is, because the part has no headers or declares its own
+    /// Content-Transfer-Encoding.
+    Raw,
+    /// Encoded with `encoding`. `add_charset` is set when a charset=utf-8
+    /// parameter is added to the text/* Content-Type.
+    Encoded {
+        encoding: EncodingType,
+        is_body: bool,
+        add_charset: bool,
+    },
+}
+
+/// Selects the Content-Transfer-Encoding of a text or binary part. This is
+/// the single place where the encoding is chosen, used both when writing the
+/// part and by [`MimePart::content_transfer_encoding`].
+fn leaf_encoding(
+    headers: &[(Cow<str>, HeaderType)],
+    contents: &[u8],
+    is_binary: bool,
+    options: &WriteOptions,
+) -> LeafEncoding {
+    if headers.is_empty()
+        || headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("Content-Transfer-Encoding"))
+    {
+        return LeafEncoding::Raw;
+    }
+    let header = |wanted: &str| {
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+            .and_then(|(_, value)| value.as_content_type())
+    };
+
+    let is_body = !header("Content-Disposition").is_some_and(|cd| cd.is_attachment());
+    let (is_text, add_charset) = match header("Content-Type") {
+        _ if !is_binary => (true, false),
+        Some(ct) if options.is_text_content_type(ct) => {
+            if ct.is_text() && ct.attribute_case_insensitive("charset").is_none() {
+                // Only claim a charset when the contents are valid UTF-8,
+                // otherwise fall back to base64.
+                let is_utf8 = std::str::from_utf8(contents).is_ok();
+                (is_utf8, is_utf8)
+            } else {
+                (true, false)
+            }
+        }
+        _ => (false, false),
+    };
+
+    LeafEncoding::Encoded {
+        encoding: if is_text {
+            get_encoding_type(contents, false, is_body)
+        } else {
+            EncodingType::Base64
+        },
+        is_body,
+        add_charset,
+    }
+}
+
+/// Writes the headers and contents of a text or binary part, encoded as
+/// selected by [`leaf_encoding`].
+fn write_leaf(
+    headers: &[(Cow<str>, HeaderType)],
+    contents: &[u8],
+    is_binary: bool,
+    mut output: impl Write,
+    options: &WriteOptions,
+) -> io::Result<()> {
+    let encoding = leaf_encoding(headers, contents, is_binary, options);
+    write_leaf_headers(headers, &encoding, &mut output, options)?;
+
+    match encoding {
+        LeafEncoding::Encoded {
+            encoding, is_body, ..
+        } => {
+            output.write_all(b"\r\n")?;
+            write_encoded(encoding, contents, output, is_body)
+        }
+        LeafEncoding::Raw => {
+            #[cfg(feature = "log")]
+            log::debug!("Writing raw part without encoding");
+            if options.strict {
+                check_7bit(headers, contents)?;
+            }
+            if !headers.is_empty() {
+                output.write_all(b"\r\n")?;
+            }
+            output.write_all(contents)
+        }
     }
 }
 
-fn detect_encoding(input: &[u8], mut output: impl Write, is_body: bool) -> io::Result<()> {
-    match get_encoding_type(input, false, is_body) {
+/// Writes the headers of a text or binary part, including the
+/// Content-Transfer-Encoding selected by [`leaf_encoding`], without the
+/// blank line that ends them.
+fn write_leaf_headers(
+    headers: &[(Cow<str>, HeaderType)],
+    encoding: &LeafEncoding,
+    mut output: impl Write,
+    options: &WriteOptions,
+) -> io::Result<()> {
+    let mut add_charset = matches!(
+        encoding,
+        LeafEncoding::Encoded {
+            add_charset: true,
+            ..
+        }
+    );
+
+    for (header_name, header_value) in headers {
+        #[cfg(feature = "log")]
+        log::trace!("Writing header {:?}", header_name);
+        write_header_name(header_name, &mut output)?;
+        if add_charset && header_name.eq_ignore_ascii_case("Content-Type") {
+            add_charset = false;
+            if let Some(ct) = header_value.as_content_type() {
+                ct.clone()
+                    .attribute("charset", "utf-8")
+                    .write_header_with_fold(&mut output, 14, options.fold())?;
+                continue;
+            }
+        }
+        header_value.write_header_with_fold(&mut output, header_name.len() + 2, options.fold())?;
+    }
+
+    if let LeafEncoding::Encoded { encoding, .. } = encoding {
+        output.write_all(b"Content-Transfer-Encoding: ")?;
+        output.write_all(encoding.as_str().as_bytes())?;
+        output.write_all(b"\r\n")?;
+    }
+    Ok(())
+}
+
+fn write_encoded(
+    encoding: EncodingType,
+    input: &[u8],
+    mut output: impl Write,
+    is_body: bool,
+) -> io::Result<()> {
+    #[cfg(feature = "log")]
+    log::debug!("Encoding {} bytes as {}", input.len(), encoding.as_str());
+
+    match encoding {
         EncodingType::Base64 => {
-            output.write_all(b"Content-Transfer-Encoding: base64\r\n\r\n")?;
             base64_encode_mime(input, &mut output, false)?;
         }
         EncodingType::QuotedPrintable(_) => {
-            output.write_all(b"Content-Transfer-Encoding: quoted-printable\r\n\r\n")?;
             quoted_printable_encode(input, &mut output, false, is_body)?;
         }
         EncodingType::None => {
-            output.write_all(b"Content-Transfer-Encoding: 7bit\r\n\r\n")?;
             if is_body {
                 let mut prev_ch = 0;
-                for ch in input {
-                    if *ch == b'\n' && prev_ch != b'\r' {
-                        output.write_all(b"\r")?;
+                for (pos, &ch) in input.iter().enumerate() {
+                    if (ch == b'\n' && prev_ch != b'\r')
+                        || (ch == b'\r' && input.get(pos + 1) != Some(&b'\n'))
+                    {
+                        output.write_all(b"\r\n")?;
+                    } else {
+                        output.write_all(&[ch])?;
                     }
-                    output.write_all(&[*ch])?;
-                    prev_ch = *ch;
+                    prev_ch = ch;
                 }
             } else {
                 output.write_all(input)?;
@@ -446,3 +2048,1576 @@ fn detect_encoding(input: &[u8], mut output: impl Write, is_body: bool) -> io::R
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::{
+        validate_boundary, BodyPart, InvalidBoundaryError, MimePart, SmimeType, TransferEncoding,
+        WriteOptions,
+    };
+    use crate::headers::{content_type::ContentType, raw::Raw, text::Text, Header, HeaderType};
+    use crate::{Error, MessageBuilder};
+
+    fn write_body(text: &str) -> String {
+        let mut output = Vec::new();
+        MimePart::new("text/plain", text)
+            .write_part(&mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        output
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap()
+    }
+
+    #[test]
+    fn normalize_line_endings() {
+        for (input, expected) in [
+            ("a\nb\n", "a\r\nb\r\n"),
+            ("a\r\nb\r\n", "a\r\nb\r\n"),
+            ("a\rb\r", "a\r\nb\r\n"),
+            ("a\r\r\nb\n\r", "a\r\n\r\nb\r\n\r\n"),
+            ("a\n\r\n\rb", "a\r\n\r\n\r\nb\r\n"),
+        ] {
+            assert_eq!(write_body(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn write_part_returns_bytes_written() {
+        let mut output = Vec::new();
+        let bytes_written = MimePart::new(
+            "multipart/mixed",
+            vec![
+                MimePart::new("text/plain", "Hello, world!"),
+                MimePart::new("image/png", [0u8, 1, 2, 3].as_ref()),
+            ],
+        )
+        .write_part(&mut output)
+        .unwrap();
+        assert_eq!(bytes_written, output.len() as u64);
+    }
+
+    #[test]
+    fn size_accounting_is_64_bit() {
+        let part = MimePart::new(
+            "multipart/mixed",
+            vec![
+                MimePart::new("text/plain", "Hello, world!"),
+                MimePart::new("image/png", [0u8, 1, 2, 3].as_ref()),
+            ],
+        );
+        let size: u64 = part.size();
+        assert_eq!(size, 17);
+
+        let bytes_written: u64 = part.write_part(std::io::sink()).unwrap();
+        assert!(bytes_written > size);
+        assert_eq!(std::mem::size_of_val(&bytes_written), 8);
+    }
+
+    #[test]
+    fn write_empty_multipart() {
+        let mut output = Vec::new();
+        MimePart::new("multipart/mixed", Vec::<MimePart>::new())
+            .write_part(&mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Content-Type: text/plain; charset=\"utf-8\"\r\n"));
+        assert!(mail_parser::MessageParser::new()
+            .parse(output.as_bytes())
+            .is_some());
+
+        let err = MimePart::new("multipart/mixed", Vec::<MimePart>::new())
+            .write_part_with_options(Vec::new(), &WriteOptions::new().strict(true))
+            .unwrap_err();
+        assert_eq!(
+            err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+            Some(&Error::EmptyMultipart)
+        );
+    }
+
+    #[test]
+    fn write_multipart_emptied_by_remove_part() {
+        let mut part = MimePart::new(
+            "multipart/mixed",
+            vec![MimePart::new("text/plain", "Hello, world!")],
+        );
+        assert!(part.remove_part(0).is_some());
+        assert!(part.remove_part(0).is_none());
+
+        assert!(part
+            .clone()
+            .write_part_with_options(Vec::new(), &WriteOptions::new().strict(true))
+            .is_err());
+        assert!(part.write_part(Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn multipart_from_slice() {
+        let parts = [
+            MimePart::new("text/plain", "Hello, world!"),
+            MimePart::new("image/png", [0u8, 1, 2, 3].as_ref()),
+        ];
+        let options = WriteOptions::new().deterministic(true);
+
+        let mut output = Vec::new();
+        MimePart::new("multipart/mixed", parts.as_ref())
+            .write_part_with_options(&mut output, &options)
+            .unwrap();
+        let mut expected = Vec::new();
+        MimePart::new("multipart/mixed", parts.to_vec())
+            .write_part_with_options(&mut expected, &options)
+            .unwrap();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn preserve_content_type_parameters() {
+        for (content_type, expected) in [
+            (
+                HeaderType::from(
+                    ContentType::new("multipart/related").attribute("type", "text/html"),
+                ),
+                "Content-Type: multipart/related; type=\"text/html\"; boundary=",
+            ),
+            (
+                HeaderType::from(
+                    ContentType::new("multipart/report")
+                        .attribute("report-type", "delivery-status"),
+                ),
+                "Content-Type: multipart/report; report-type=\"delivery-status\"; boundary=",
+            ),
+            (
+                HeaderType::from(Raw::new(
+                    "multipart/related; type=\"text/html\"; start=\"<a@b>\"",
+                )),
+                "Content-Type: multipart/related; type=\"text/html\"; start=\"<a@b>\"; boundary=",
+            ),
+            (
+                HeaderType::from(Raw::new(
+                    "multipart/related; boundary=\"my-boundary\"; type=text/html",
+                )),
+                "Content-Type: multipart/related; boundary=\"my-boundary\"; type=\"text/html\"\r\n",
+            ),
+        ] {
+            let mut part = MimePart::raw(vec![MimePart::new("text/html", "<p>Hello</p>")]);
+            part.headers.push(("Content-Type".into(), content_type));
+
+            let mut output = Vec::new();
+            part.write_part(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(
+                output.replace("\r\n ", " ").starts_with(expected),
+                "{}",
+                output
+            );
+
+            let message = mail_parser::MessageParser::new()
+                .parse(output.as_bytes())
+                .unwrap();
+            assert_eq!(message.parts.len(), 2, "{}", output);
+        }
+    }
+
+    #[test]
+    fn encoded_header_size() {
+        let part = MimePart::new("image/png", [0u8, 1, 2, 3].as_ref())
+            .attachment("my image with a rather long file name that needs folding.png")
+            .cid("image-1@example.com")
+            .header("X-Custom", Text::new("Ñandú"));
+        let header_size = part.encoded_header_size();
+
+        let mut output = Vec::new();
+        part.write_part(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let headers = &output[..output.find("Content-Transfer-Encoding").unwrap()];
+        assert_eq!(header_size, headers.len(), "{}", headers);
+    }
+
+    #[test]
+    fn write_part_ends_with_crlf() {
+        for part in [
+            MimePart::new("text/plain", "No trailing newline"),
+            MimePart::new("text/plain", "Trailing newline\n"),
+            MimePart::new("text/plain", "Trailing CRLF\r\n"),
+            MimePart::new("text/plain", "Quoted-printable ñandú"),
+            MimePart::new("image/png", [0u8, 1, 2, 3].as_ref()),
+            MimePart::new(
+                "multipart/mixed",
+                vec![
+                    MimePart::new("text/plain", "No trailing newline"),
+                    MimePart::new("image/png", [0u8, 1, 2, 3].as_ref()),
+                ],
+            ),
+        ] {
+            let mut output = Vec::new();
+            part.write_part(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.ends_with("\r\n"), "{:?}", output);
+            assert!(!output.ends_with("\r\n\r\n"), "{:?}", output);
+        }
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn write_part_async_futures() {
+        let build = || {
+            MimePart::new(
+                "multipart/mixed",
+                vec![
+                    MimePart::new("text/plain", "Hello, world!\n".repeat(1000)),
+                    MimePart::new("image/png", vec![0u8; 10000]),
+                ],
+            )
+        };
+        let options = WriteOptions::new().deterministic(true);
+
+        let mut output = Vec::new();
+        let bytes_written = futures::executor::block_on(
+            build().write_part_async_futures_with_options(&mut output, &options),
+        )
+        .unwrap();
+
+        let mut expected = Vec::new();
+        build()
+            .write_part_with_options(&mut expected, &options)
+            .unwrap();
+        assert_eq!(bytes_written, expected.len() as u64);
+        assert_eq!(output, expected);
+    }
+
+    proptest! {
+        #[test]
+        fn normalize_mixed_line_endings(
+            lines in proptest::collection::vec(
+                ("[a-zA-Z0-9.,]{0,40}", prop_oneof![Just("\r\n"), Just("\n"), Just("\r")]),
+                0..20,
+            )
+        ) {
+            let input = lines
+                .iter()
+                .map(|(line, ending)| format!("{}{}", line, ending))
+                .collect::<String>();
+            let expected = input
+                .replace("\r\n", "\n")
+                .replace('\r', "\n")
+                .replace('\n', "\r\n");
+            prop_assert_eq!(write_body(&input), expected);
+        }
+    }
+
+    #[test]
+    fn classify_parts() {
+        for (part, is_inline_image, is_text_body) in [
+            (
+                MimePart::new("image/png", b"".as_ref()).inline(),
+                true,
+                false,
+            ),
+            (
+                MimePart::new("image/png", b"".as_ref()).attachment("a.png"),
+                false,
+                false,
+            ),
+            (MimePart::new("image/png", b"".as_ref()), false, false),
+            (MimePart::new("text/plain", "hello"), false, true),
+            (
+                MimePart::new("text/html", "<p>hello</p>").inline(),
+                false,
+                true,
+            ),
+            (
+                MimePart::new("text/plain", "hello").attachment("a.txt"),
+                false,
+                false,
+            ),
+            (MimePart::new("text/calendar", "hello"), false, false),
+            (
+                MimePart::new("multipart/mixed", vec![MimePart::new("text/plain", "")]),
+                false,
+                false,
+            ),
+        ] {
+            assert_eq!(part.is_inline_image(), is_inline_image, "{:?}", part);
+            assert_eq!(part.is_text_body(), is_text_body, "{:?}", part);
+        }
+    }
+
+    struct FailingWriter {
+        remaining: usize,
+    }
+
+    impl std::io::Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.remaining == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    "writer closed",
+                ));
+            }
+            let len = buf.len().min(self.remaining);
+            self.remaining -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_part_propagates_writer_errors() {
+        let part = MimePart::new(
+            "multipart/mixed",
+            vec![
+                MimePart::new("text/plain", "Hello, world!"),
+                MimePart::new(
+                    "multipart/alternative",
+                    vec![
+                        MimePart::new("text/plain", "¡Hola, mundo!"),
+                        MimePart::new("text/html", "<p>¡Hola, mundo!</p>"),
+                    ],
+                ),
+                MimePart::new("application/octet-stream", b"\x00\x01\x02".as_ref())
+                    .attachment("data.bin"),
+            ],
+        );
+        let options = WriteOptions::new().deterministic(true);
+        let size = part
+            .clone()
+            .write_part_with_options(Vec::new(), &options)
+            .unwrap() as usize;
+
+        for remaining in 0..size {
+            let err = part
+                .clone()
+                .write_part_with_options(FailingWriter { remaining }, &options)
+                .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        }
+        assert_eq!(
+            part.write_part_with_options(FailingWriter { remaining: size }, &options)
+                .unwrap(),
+            size as u64
+        );
+
+        let err = MimePart::raw(vec![MimePart::new("text/plain", "")])
+            .header("Content-Type", Text::new("multipart/mixed"))
+            .write_part(Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+            Some(&Error::UnsupportedContentType)
+        );
+    }
+
+    #[test]
+    fn merge_attachment_and_inline() {
+        for (part, expected) in [
+            (
+                MimePart::new("application/pdf", b"".as_ref())
+                    .attachment("a.pdf")
+                    .inline(),
+                "inline; filename=\"a.pdf\"\r\n",
+            ),
+            (
+                MimePart::new("application/pdf", b"".as_ref())
+                    .inline()
+                    .attachment("a.pdf"),
+                "attachment; filename=\"a.pdf\"\r\n",
+            ),
+            (
+                MimePart::new("application/pdf", b"".as_ref())
+                    .attachment("a.pdf")
+                    .attachment("b.pdf"),
+                "attachment; filename=\"b.pdf\"\r\n",
+            ),
+        ] {
+            let dispositions = part
+                .headers
+                .iter()
+                .filter(|(name, _)| name == "Content-Disposition")
+                .collect::<Vec<_>>();
+            assert_eq!(dispositions.len(), 1);
+            let mut output = Vec::new();
+            dispositions[0].1.write_header(&mut output, 21).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn add_part_returns_new_part() {
+        let mut part = MimePart::new("multipart/mixed", Vec::<MimePart>::new());
+        part.add_part(MimePart::new("image/png", b"".as_ref()))
+            .unwrap()
+            .headers
+            .push(("Content-ID".into(), Raw::new("<image>").into()));
+        match &part.contents {
+            BodyPart::Multipart(parts) => {
+                assert_eq!(parts.len(), 1);
+                assert!(parts[0]
+                    .headers
+                    .iter()
+                    .any(|(name, _)| name == "Content-ID"));
+            }
+            _ => unreachable!(),
+        }
+
+        let mut part = MimePart::new("text/plain", "Hello");
+        assert!(part.add_part(MimePart::new("text/plain", "")).is_none());
+    }
+
+    #[test]
+    fn encode_text_content_types() {
+        let signature = concat!(
+            "-----BEGIN PGP SIGNATURE-----\n\n",
+            "iQEzBAEBCAAdFiEEabcdefghijklmnopqrstuvwxyz0123456789ABCDEF\n",
+            "=abcd\n",
+            "-----END PGP SIGNATURE-----\n"
+        );
+        for (options, expected) in [
+            (WriteOptions::new(), "Content-Transfer-Encoding: 7bit\r\n"),
+            (
+                WriteOptions::new().text_content_types(Vec::<&'static str>::new()),
+                "Content-Transfer-Encoding: base64\r\n",
+            ),
+        ] {
+            let mut output = Vec::new();
+            MimePart::new("application/pgp-signature", signature.as_bytes())
+                .write_part_with_options(&mut output, &options)
+                .unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.contains(expected), "{}", output);
+        }
+
+        let mut output = Vec::new();
+        MimePart::new(
+            "text/calendar",
+            b"BEGIN:VCALENDAR\nEND:VCALENDAR\n".as_ref(),
+        )
+        .write_part_with_options(&mut output, &WriteOptions::new())
+        .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Content-Transfer-Encoding: 7bit\r\n"));
+    }
+
+    #[test]
+    fn write_empty_parts() {
+        let mut output = Vec::new();
+        MimePart::new(
+            "multipart/mixed",
+            vec![
+                MimePart::new("text/plain", ""),
+                MimePart::new("text/html", ""),
+                MimePart::new("application/octet-stream", b"".as_ref()).attachment("empty.bin"),
+                MimePart::new("text/plain", b"".as_ref()).attachment("empty.txt"),
+            ],
+        )
+        .write_part_with_options(&mut output, &WriteOptions::new().deterministic(true))
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        for encoding in ["7bit", "base64"] {
+            assert!(
+                output.contains(&format!(
+                    "Content-Transfer-Encoding: {}\r\n\r\n\r\n--",
+                    encoding
+                )),
+                "{}",
+                output
+            );
+        }
+        assert!(output.ends_with("--\r\n"), "{}", output);
+
+        let message = mail_parser::MessageParser::new()
+            .parse(output.as_bytes())
+            .unwrap();
+        assert_eq!(message.parts.len(), 5);
+        for part in message.parts.iter().skip(1) {
+            assert!(part.contents().is_empty(), "{:?}", part);
+        }
+        assert_eq!(message.body_text(0).as_deref(), Some(""));
+        assert_eq!(message.attachment_count(), 2);
+    }
+
+    #[cfg(feature = "getrandom")]
+    #[test]
+    fn boundary_random_component() {
+        let host = "localhost";
+        let boundary_a =
+            super::format_boundary("_", 1, super::boundary_hash(host, super::random_seed()), 0);
+        let boundary_b =
+            super::format_boundary("_", 1, super::boundary_hash(host, super::random_seed()), 0);
+        assert_ne!(boundary_a, boundary_b);
+        assert_eq!(boundary_a.split('_').count(), 3);
+    }
+
+    #[test]
+    fn write_binary_text_charset() {
+        for (part, expected, not_expected) in [
+            (
+                MimePart::new(
+                    "text/csv",
+                    "name,city\nJiří,Plzeň\nJohn,London\nJane,Paris\n"
+                        .as_bytes()
+                        .to_vec(),
+                ),
+                "Content-Type: text/csv; charset=\"utf-8\"\r\nContent-Transfer-Encoding: quoted-printable\r\n",
+                "base64",
+            ),
+            (
+                MimePart::new(
+                    ContentType::new("text/csv").attribute("charset", "iso-8859-2"),
+                    b"name,city\nJi\xf8\xed,Plze\xf2\nJohn,London\nJane,Paris\n".to_vec(),
+                ),
+                "Content-Type: text/csv; charset=\"iso-8859-2\"\r\nContent-Transfer-Encoding: quoted-printable\r\n",
+                "utf-8",
+            ),
+            (
+                MimePart::new("text/plain", b"\xff\xfe\x00\x01junk\x80\x81".to_vec()),
+                "Content-Type: text/plain\r\nContent-Transfer-Encoding: base64\r\n",
+                "charset",
+            ),
+        ] {
+            let mut output = Vec::new();
+            part.write_part(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.starts_with(expected), "{}", output);
+            assert!(!output.contains(not_expected), "{}", output);
+        }
+    }
+
+    #[test]
+    fn write_encrypted_attachment() {
+        let mut output = Vec::new();
+        MimePart::new_encrypted_attachment(
+            b"plain ascii ciphertext".to_vec(),
+            "secret.pdf",
+            "AES-256-CTR",
+        )
+        .write_part(&mut output)
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            concat!(
+                "Content-Type: application/octet-stream\r\n",
+                "Content-Disposition: attachment; filename=\"secret.pdf\"\r\n",
+                "X-Encryption-Algorithm: AES-256-CTR\r\n",
+                "Content-Transfer-Encoding: base64\r\n\r\n",
+                "cGxhaW4gYXNjaWkgY2lwaGVydGV4dA==\r\n",
+            )
+        );
+    }
+
+    #[test]
+    fn reject_invalid_header_names() {
+        for name in [
+            "X-Data: foo\r\nX-Inject",
+            "X-Data\r\nX-Inject",
+            "X Data",
+            "X-Data:",
+            "X-Dätä",
+            "",
+        ] {
+            let err = MimePart::new("text/plain", "Hello")
+                .header(name, Raw::new("value"))
+                .write_part(Vec::new())
+                .unwrap_err();
+            assert_eq!(
+                err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+                Some(&Error::InvalidHeaderName(name.to_string()))
+            );
+        }
+
+        let name = "X-".repeat(50);
+        assert!(MimePart::new("text/plain", "Hello")
+            .header(name, Raw::new("value"))
+            .write_part(Vec::new())
+            .is_ok());
+        assert!(MimePart::new("text/plain", "Hello")
+            .header("X-Valid_Name.1", Raw::new("value"))
+            .write_part(Vec::new())
+            .is_ok());
+    }
+
+    #[test]
+    fn content_type_accessors() {
+        let part = MimePart::new("text/html", "<p>Hello</p>");
+        assert_eq!(part.content_type_str(), Some("text/html"));
+        assert_eq!(part.charset(), Some("utf-8"));
+
+        let part = MimePart::new(
+            ContentType::new("text/csv").attribute("Charset", "iso-8859-2"),
+            b"a,b".as_ref(),
+        );
+        assert_eq!(part.content_type_str(), Some("text/csv"));
+        assert_eq!(part.charset(), Some("iso-8859-2"));
+
+        let part = MimePart::new("image/png", b"".as_ref());
+        assert_eq!(part.content_type_str(), Some("image/png"));
+        assert_eq!(part.charset(), None);
+
+        let part = MimePart::raw("Subject: raw\r\n\r\nbody");
+        assert_eq!(part.content_type_str(), None);
+        assert_eq!(part.charset(), None);
+    }
+
+    #[test]
+    fn sanitize_attachment_filenames() {
+        for (filename, expected) in [
+            ("..\\..\\evil.exe", "evil.exe".to_string()),
+            ("../../etc/passwd", "passwd".to_string()),
+            ("reports/2023/", "2023".to_string()),
+            (
+                "in\u{0}voice\r\n  final\t.pdf",
+                "in voice final .pdf".to_string(),
+            ),
+            (".pdf", "attachment.pdf".to_string()),
+            ("..", "attachment.bin".to_string()),
+            (" \u{7} ", "attachment.bin".to_string()),
+            ("", "attachment.bin".to_string()),
+            (
+                &format!("{}.docx", "ñ".repeat(500)),
+                format!("{}.docx", "ñ".repeat(95)),
+            ),
+            (&"x".repeat(150), "x".repeat(100)),
+        ] {
+            let sanitized = super::sanitize_filename(filename, super::MAX_FILENAME_LEN);
+            assert_eq!(sanitized, expected, "{:?}", filename);
+        }
+
+        let mut output = Vec::new();
+        MimePart::new("text/plain", "Hello")
+            .attachment_sanitized("../../secret.txt")
+            .write_part(&mut output)
+            .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Content-Disposition: attachment; filename=\"secret.txt\"\r\n"));
+    }
+
+    #[test]
+    fn set_cid_url() {
+        for (cid, expected) in [
+            ("logo@example.com", "cid:logo@example.com"),
+            ("<logo@example.com>", "cid:logo@example.com"),
+            ("  cid:logo@example.com ", "cid:logo@example.com"),
+            ("my logo\r\n@example.com", "cid:mylogo@example.com"),
+        ] {
+            let mut part = MimePart::new("image/png", b"".as_ref()).inline();
+            let url = part.set_cid(cid);
+            assert_eq!(url, expected);
+
+            let mut output = Vec::new();
+            part.write_part(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            let content_id = output
+                .split("\r\n")
+                .find_map(|line| line.strip_prefix("Content-ID: "))
+                .unwrap();
+            assert_eq!(
+                content_id.trim_start_matches('<').trim_end_matches('>'),
+                url.strip_prefix("cid:").unwrap()
+            );
+            assert_eq!(output.matches("Content-ID").count(), 1);
+        }
+
+        let mut part = MimePart::new("image/png", b"".as_ref());
+        assert!(part.set_cid("<>").ends_with("@localhost"));
+    }
+
+    #[test]
+    fn classify_messages() {
+        let signed = MimePart::new(
+            ContentType::new("multipart/signed").attribute("protocol", "application/pgp-signature"),
+            vec![
+                MimePart::new("text/plain", "Hello"),
+                MimePart::new("application/pgp-signature", b"".as_ref()),
+            ],
+        );
+        assert!(signed.is_signed());
+        assert!(!signed.is_encrypted());
+        assert!(!signed.is_top_level_message());
+
+        let encrypted = MimePart::new(
+            "multipart/encrypted",
+            vec![MimePart::new("application/octet-stream", b"".as_ref())],
+        )
+        .header("MIME-Version", Raw::new("1.0"));
+        assert!(encrypted.is_encrypted());
+        assert!(!encrypted.is_signed());
+        assert!(encrypted.is_top_level_message());
+
+        let text = MimePart::new("text/plain", "Hello");
+        assert!(!text.is_signed() && !text.is_encrypted() && !text.is_top_level_message());
+    }
+
+    #[test]
+    fn limit_nesting_depth() {
+        let mut part = MimePart::new("text/plain", "Hello");
+        for _ in 0..100 {
+            part = MimePart::new("multipart/mixed", vec![part]);
+        }
+
+        let err = part.clone().write_part(Vec::new()).unwrap_err();
+        assert_eq!(
+            err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+            Some(&Error::NestingTooDeep)
+        );
+        assert!(part
+            .clone()
+            .write_part_with_options(Vec::new(), &WriteOptions::new().max_depth(100))
+            .is_ok());
+
+        let err = part
+            .write_part_with_options(
+                Vec::new(),
+                &WriteOptions::new().max_depth(100).max_parts(50),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+            Some(&Error::TooManyParts)
+        );
+    }
+
+    #[test]
+    fn boundary_without_hostname() {
+        let host = std::ffi::OsStr::new("");
+        let boundaries = (0..100)
+            .map(|_| super::make_boundary_with_host("_", host))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(boundaries.len(), 100);
+        for boundary in boundaries {
+            assert_eq!(boundary.split('_').count(), 3);
+            assert_ne!(boundary.rsplit('_').next(), Some("0"));
+        }
+    }
+
+    #[test]
+    fn write_uppercase_charset() {
+        let mut output = Vec::new();
+        MimePart::new(
+            ContentType::new("TEXT/PLAIN").attribute("CHARSET", "UTF-8"),
+            "Hello, this is a longer text with a single ñ character.".as_bytes(),
+        )
+        .write_part(&mut output)
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.starts_with(
+                "Content-Type: text/plain; charset=\"UTF-8\"\r\nContent-Transfer-Encoding: quoted-printable\r\n"
+            ),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn strict_invalid_7bit() {
+        for contents in ["Hola señor".to_string(), "x".repeat(1000)] {
+            let part = MimePart::new("text/plain", contents.clone()).transfer_encoding("7bit");
+            let mut output = Vec::new();
+            part.clone().write_part(&mut output).unwrap();
+            assert!(String::from_utf8(output)
+                .unwrap()
+                .ends_with(&format!("{}\r\n", contents)));
+
+            let err = part
+                .write_part_with_options(Vec::new(), &WriteOptions::new().strict(true))
+                .unwrap_err();
+            assert_eq!(
+                err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+                Some(&Error::Invalid7Bit)
+            );
+        }
+
+        assert!(MimePart::new("text/plain", "Hello\r\n")
+            .transfer_encoding("7bit")
+            .write_part_with_options(Vec::new(), &WriteOptions::new().strict(true))
+            .is_ok());
+        assert!(MimePart::new("text/plain", "Hola señor")
+            .transfer_encoding("8bit")
+            .write_part_with_options(Vec::new(), &WriteOptions::new().strict(true))
+            .is_ok());
+    }
+
+    #[test]
+    fn predict_content_transfer_encoding() {
+        for part in [
+            MimePart::new("text/plain", "Hello, world!\n"),
+            MimePart::new("text/plain", "Hola señor, ¿cómo está usted hoy?".repeat(3)),
+            MimePart::new("text/plain", "¡Ñandú!"),
+            MimePart::new("text/html", "ハロー・ワールド"),
+            MimePart::new("image/png", b"\x89PNG".as_ref()),
+            MimePart::new("text/plain", b"\xff\xfe junk".as_ref()),
+            MimePart::new(
+                "application/pgp-signature",
+                b"-----BEGIN PGP-----\n".as_ref(),
+            ),
+            MimePart::new("text/plain", "Hello").attachment("a.txt"),
+            MimePart::new("text/plain", "=?raw?=").transfer_encoding("quoted-printable"),
+        ] {
+            let expected = part.content_transfer_encoding().unwrap().to_string();
+            let mut output = Vec::new();
+            part.write_part(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(
+                output.contains(&format!("Content-Transfer-Encoding: {}\r\n", expected)),
+                "{}",
+                output
+            );
+        }
+
+        let part = MimePart::new("text/plain", "Ol\u{e1}")
+            .header("content-transfer-encoding", Raw::new("8bit"));
+        assert_eq!(part.content_transfer_encoding(), Some("8bit"));
+        let mut output = Vec::new();
+        part.write_part(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("content-transfer-encoding: 8bit\r\n")
+                && !output.contains("Content-Transfer-Encoding"),
+            "{}",
+            output
+        );
+
+        assert_eq!(
+            MimePart::new("multipart/mixed", vec![MimePart::new("text/plain", "")])
+                .content_transfer_encoding(),
+            None
+        );
+    }
+
+    #[test]
+    fn part_body_bytes() {
+        assert_eq!(
+            MimePart::new("text/plain", "¡Hola!").body_bytes(),
+            Some("¡Hola!".as_bytes())
+        );
+        assert_eq!(
+            MimePart::new("image/png", [0u8, 1, 2].as_ref()).body_bytes(),
+            Some([0u8, 1, 2].as_ref())
+        );
+        assert_eq!(
+            MimePart::new("multipart/mixed", vec![MimePart::new("text/plain", "")]).body_bytes(),
+            None
+        );
+    }
+
+    #[test]
+    fn validate_boundaries() {
+        for (boundary, expected) in [
+            ("simple-boundary", Ok(())),
+            ("'()+_,-./:=? with spaces", Ok(())),
+            ("", Err(InvalidBoundaryError::Empty)),
+            (&"a".repeat(90), Err(InvalidBoundaryError::TooLong)),
+            ("ends with space ", Err(InvalidBoundaryError::TrailingSpace)),
+            (
+                "semi;colon",
+                Err(InvalidBoundaryError::InvalidCharacter(';')),
+            ),
+            ("quote\"", Err(InvalidBoundaryError::InvalidCharacter('"'))),
+            ("ñ", Err(InvalidBoundaryError::InvalidCharacter('ñ'))),
+        ] {
+            assert_eq!(validate_boundary(boundary), expected, "{:?}", boundary);
+
+            let result = MimePart::new(
+                ContentType::new("multipart/mixed").attribute("boundary", boundary),
+                vec![MimePart::new("text/plain", "Hello")],
+            )
+            .write_part(Vec::new());
+            match expected {
+                Ok(()) => assert!(result.is_ok()),
+                Err(err) => assert_eq!(
+                    result
+                        .unwrap_err()
+                        .get_ref()
+                        .and_then(|err| err.downcast_ref::<Error>()),
+                    Some(&Error::InvalidBoundary(err))
+                ),
+            }
+        }
+
+        let err = MimePart::raw(vec![MimePart::new("text/plain", "Hello")])
+            .header(
+                "Content-Type",
+                Raw::new(format!("multipart/mixed; boundary=\"{}\"", "b".repeat(90))),
+            )
+            .write_part(Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+            Some(&Error::InvalidBoundary(InvalidBoundaryError::TooLong))
+        );
+    }
+
+    #[test]
+    fn reject_binary_text_parts() {
+        for (part, expected_part, expected_signature) in [
+            (
+                MimePart::new(
+                    "text/plain",
+                    b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n1 0 obj".to_vec(),
+                )
+                .attachment("report.txt"),
+                "report.txt",
+                "PDF",
+            ),
+            (
+                MimePart::new("text/csv", b"PK\x03\x04\x14\x00\x00\x00\x08\x00".to_vec()),
+                "text/csv",
+                "ZIP",
+            ),
+            (
+                MimePart::new("text/plain", "a\u{1}\u{2}\u{3}b"),
+                "text/plain",
+                "control characters",
+            ),
+        ] {
+            part.clone().write_part(Vec::new()).unwrap();
+            let err = part
+                .write_part_with_options(Vec::new(), &WriteOptions::new().strict(true))
+                .unwrap_err();
+            assert_eq!(
+                err.get_ref().and_then(|e| e.downcast_ref::<Error>()),
+                Some(&Error::BinaryTextPart {
+                    part: expected_part.to_string(),
+                    signature: expected_signature,
+                })
+            );
+        }
+
+        let text = "Día de campo — 3 km\r\n\tPrecio: 5 €\r\n".repeat(20);
+        for part in [
+            MimePart::new("text/plain", text.as_str()),
+            MimePart::new("text/plain", text.as_bytes().to_vec()),
+            MimePart::new("application/pdf", b"%PDF-1.7".to_vec()),
+        ] {
+            part.write_part_with_options(Vec::new(), &WriteOptions::new().strict(true))
+                .unwrap();
+        }
+        MimePart::new("text/plain", b"%PDF-1.7".to_vec())
+            .write_part(Vec::new())
+            .unwrap();
+    }
+
+    #[test]
+    fn boundary_prefix() {
+        for (prefix, deterministic) in [
+            ("----=_Part_", false),
+            ("----=_Part_", true),
+            ("=_ThisIsAVeryLongPrefixForDebugging_", false),
+        ] {
+            let mut output = Vec::new();
+            MimePart::new(
+                "multipart/mixed",
+                vec![
+                    MimePart::new("text/plain", "Hello"),
+                    MimePart::new(
+                        "multipart/alternative",
+                        vec![MimePart::new("text/plain", "Nested")],
+                    ),
+                ],
+            )
+            .write_part_with_options(
+                &mut output,
+                &WriteOptions::new()
+                    .deterministic(deterministic)
+                    .boundary_prefix(prefix),
+            )
+            .unwrap();
+            let output = String::from_utf8(output).unwrap();
+
+            let boundaries = output
+                .split("boundary=\"")
+                .skip(1)
+                .map(|rest| rest.split('"').next().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(boundaries.len(), 2, "{}", output);
+            assert_ne!(boundaries[0], boundaries[1]);
+            for boundary in boundaries {
+                assert!(boundary.starts_with(prefix), "{}", boundary);
+                assert!(boundary.len() > prefix.len(), "{}", boundary);
+                assert_eq!(validate_boundary(boundary), Ok(()));
+            }
+        }
+
+        for (prefix, expected) in [
+            ("x".repeat(37), InvalidBoundaryError::TooLong),
+            (
+                "part@".to_string(),
+                InvalidBoundaryError::InvalidCharacter('@'),
+            ),
+        ] {
+            let err = MimePart::new("multipart/mixed", vec![MimePart::new("text/plain", "Hi")])
+                .write_part_with_options(Vec::new(), &WriteOptions::new().boundary_prefix(prefix))
+                .unwrap_err();
+            assert_eq!(
+                err.get_ref().and_then(|e| e.downcast_ref::<Error>()),
+                Some(&Error::InvalidBoundary(expected))
+            );
+        }
+    }
+
+    #[test]
+    fn count_attachments_and_inline() {
+        let message = MimePart::new(
+            "multipart/mixed",
+            vec![
+                MimePart::new(
+                    "multipart/related",
+                    vec![
+                        MimePart::new("text/html", "<img src=\"cid:logo\">"),
+                        MimePart::new("image/png", vec![0u8; 16])
+                            .inline()
+                            .cid("logo"),
+                    ],
+                ),
+                MimePart::new("application/pdf", b"%PDF-1.7".to_vec()).attachment("a.pdf"),
+                MimePart::new("text/csv", "a,b\n1,2").attachment("b.csv"),
+            ],
+        );
+        assert_eq!(message.count_attachments(), 2);
+        assert_eq!(message.count_inline(), 1);
+        assert_eq!(MimePart::new("text/plain", "Hi").count_attachments(), 0);
+    }
+
+    #[test]
+    fn write_smime_parts() {
+        for (smime_type, expected) in [
+            (
+                SmimeType::EnvelopedData,
+                concat!(
+                    "Content-Type: application/pkcs7-mime; smime-type=\"enveloped-data\";\r\n",
+                    " name=\"smime.p7m\"\r\n",
+                    "Content-Disposition: attachment; filename=\"smime.p7m\"\r\n",
+                    "Content-Transfer-Encoding: base64\r\n\r\n",
+                ),
+            ),
+            (
+                SmimeType::SignedData,
+                concat!(
+                    "Content-Type: application/pkcs7-mime; smime-type=\"signed-data\";\r\n",
+                    " name=\"smime.p7m\"\r\n",
+                    "Content-Disposition: attachment; filename=\"smime.p7m\"\r\n",
+                    "Content-Transfer-Encoding: base64\r\n\r\n",
+                ),
+            ),
+            (
+                SmimeType::CertsOnly,
+                concat!(
+                    "Content-Type: application/pkcs7-mime; smime-type=\"certs-only\";\r\n",
+                    " name=\"smime.p7c\"\r\n",
+                    "Content-Disposition: attachment; filename=\"smime.p7c\"\r\n",
+                    "Content-Transfer-Encoding: base64\r\n\r\n",
+                ),
+            ),
+        ] {
+            // Plain ASCII contents would be written as 7bit by detection.
+            let mut output = Vec::new();
+            MimePart::new_smime(smime_type, b"plain ascii der".to_vec())
+                .write_part(&mut output)
+                .unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(
+                output,
+                format!("{}cGxhaW4gYXNjaWkgZGVy\r\n", expected),
+                "{:?}",
+                smime_type
+            );
+        }
+    }
+
+    #[test]
+    fn write_part_flushes_output() {
+        let mut output = std::io::BufWriter::new(Vec::new());
+        let bytes_written = MimePart::new("text/plain", "Hello, world!")
+            .write_part(&mut output)
+            .unwrap();
+        assert_eq!(output.buffer().len(), 0);
+        assert_eq!(output.get_ref().len() as u64, bytes_written);
+    }
+
+    #[test]
+    fn regenerate_boundary() {
+        let boundary_of = |part: MimePart| {
+            let mut output = Vec::new();
+            part.write_part(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            output
+                .split("boundary=\"")
+                .nth(1)
+                .and_then(|rest| rest.split('"').next())
+                .unwrap()
+                .to_string()
+        };
+
+        for regenerate in [false, true] {
+            let part = MimePart::new(
+                ContentType::new("multipart/mixed").attribute("boundary", "stored-boundary"),
+                vec![MimePart::new("text/plain", "Hello")],
+            )
+            .regenerate_boundary(regenerate);
+            assert_eq!(part.regenerates_boundary(), regenerate);
+            let first = boundary_of(part.clone());
+            let second = boundary_of(part.clone());
+            if regenerate {
+                assert_ne!(first, "stored-boundary");
+                assert_ne!(first, second);
+            } else {
+                assert_eq!(first, "stored-boundary");
+                assert_eq!(first, second);
+            }
+
+            // A boundary set afterwards is stored and reused.
+            let part = part.with_custom_boundary("custom-boundary").unwrap();
+            assert!(!part.regenerates_boundary());
+            assert_eq!(boundary_of(part), "custom-boundary");
+        }
+
+        // The flag is kept in the Content-Type header, not in the part.
+        let part = MimePart {
+            headers: vec![(
+                "Content-Type".into(),
+                Raw::new("multipart/mixed; boundary=\"stored-boundary\"").into(),
+            )],
+            contents: BodyPart::Multipart(vec![MimePart::new("text/plain", "Hello")]),
+        };
+        assert!(!part.regenerates_boundary());
+        assert!(part.regenerate_boundary(true).regenerates_boundary());
+    }
+
+    #[test]
+    fn with_custom_boundary() {
+        let mut output = Vec::new();
+        MimePart::new(
+            ContentType::new("multipart/alternative").attribute("boundary", "old"),
+            vec![MimePart::new("text/plain", "Hello")],
+        )
+        .with_custom_boundary("my boundary")
+        .unwrap()
+        .write_part(&mut output)
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.starts_with("Content-Type: multipart/alternative; boundary=\"my boundary\""),
+            "{}",
+            output
+        );
+        assert!(output.contains("\r\n--my boundary\r\n"), "{}", output);
+        assert!(output.contains("\r\n--my boundary--"), "{}", output);
+        assert!(!output.contains("old"), "{}", output);
+
+        for (boundary, expected) in [
+            ("x".repeat(71), InvalidBoundaryError::TooLong),
+            (
+                "a;b".to_string(),
+                InvalidBoundaryError::InvalidCharacter(';'),
+            ),
+            ("space ".to_string(), InvalidBoundaryError::TrailingSpace),
+        ] {
+            assert_eq!(
+                MimePart::new("multipart/mixed", Vec::<MimePart>::new())
+                    .with_custom_boundary(boundary)
+                    .unwrap_err(),
+                expected
+            );
+        }
+    }
+
+    #[cfg(feature = "mime-types")]
+    #[test]
+    fn new_attachment_auto() {
+        use super::content_type_for_extension;
+
+        assert_eq!(content_type_for_extension("pdf"), Some("application/pdf"));
+        assert_eq!(content_type_for_extension("PNG"), Some("image/png"));
+        assert_eq!(content_type_for_extension("notanextension"), None);
+
+        const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        for (filename, contents, expected) in [
+            ("report.pdf", b"%PDF-1.7".as_ref(), "application/pdf"),
+            ("notes.txt", b"Hello".as_ref(), "text/plain"),
+            ("photo.JPG", b"\xff\xd8\xff\xe0".as_ref(), "image/jpeg"),
+            (
+                "sheet.xlsx",
+                b"PK\x03\x04".as_ref(),
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            ),
+            ("archive.zip", b"PK\x03\x04".as_ref(), "application/zip"),
+            (
+                "data.notanextension",
+                b"\x01\x02".as_ref(),
+                "application/octet-stream",
+            ),
+            ("README", b"Hello".as_ref(), "application/octet-stream"),
+            ("scan", b"%PDF-1.4".as_ref(), "application/pdf"),
+            ("image.txt", PNG, "image/png"),
+            ("document.docx", PNG, "image/png"),
+        ] {
+            let part = MimePart::new_attachment_auto(filename, contents);
+            assert_eq!(part.content_type_str().unwrap(), expected, "{}", filename);
+            assert!(part.content_disposition().unwrap().is_attachment());
+        }
+    }
+
+    #[test]
+    fn new_binary_with_encoding() {
+        let encoded = "SGVsbG8sIHdvcmxkIQ==\r\n";
+        let mut output = Vec::new();
+        MimePart::new_binary_with_encoding(
+            "application/octet-stream",
+            encoded,
+            TransferEncoding::Base64,
+        )
+        .write_part(&mut output)
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "Content-Type: application/octet-stream\r\n",
+                "Content-Transfer-Encoding: base64\r\n\r\n",
+                "SGVsbG8sIHdvcmxkIQ==\r\n"
+            )
+        );
+
+        let mut output = Vec::new();
+        MimePart::new_binary_with_encoding(
+            "text/plain",
+            "caf=C3=A9 =3D 100%",
+            TransferEncoding::QuotedPrintable,
+        )
+        .write_part(&mut output)
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.ends_with(
+                "Content-Transfer-Encoding: quoted-printable\r\n\r\ncaf=C3=A9 =3D 100%\r\n"
+            ),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn content_type_without_subtype() {
+        for (c_type, contents, expected) in [
+            ("text", BodyPart::Text("Hello".into()), "text/plain"),
+            ("TEXT", BodyPart::Text("Hello".into()), "text/plain"),
+            (
+                "image",
+                BodyPart::Binary(vec![0, 1, 2].into()),
+                "application/octet-stream",
+            ),
+            (
+                "multipart",
+                BodyPart::Multipart(vec![MimePart::new("text/plain", "Hi")]),
+                "multipart/mixed",
+            ),
+        ] {
+            let mut output = Vec::new();
+            MimePart::new(c_type, contents.clone())
+                .write_part(&mut output)
+                .unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(
+                output.starts_with(&format!("Content-Type: {}", expected)),
+                "{}",
+                output
+            );
+
+            let err = MimePart::new(c_type, contents)
+                .write_part_with_options(Vec::new(), &WriteOptions::new().strict(true))
+                .unwrap_err();
+            assert_eq!(
+                err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+                Some(&Error::InvalidContentType(c_type.to_ascii_lowercase()))
+            );
+        }
+    }
+
+    #[test]
+    fn memory_usage() {
+        let text = String::with_capacity(1000) + "Hello";
+        let binary = Vec::<u8>::with_capacity(2000);
+        let part = MimePart::new(
+            "multipart/mixed",
+            vec![
+                MimePart::new("text/plain", text),
+                MimePart::new("application/octet-stream", binary).attachment("file.bin"),
+                MimePart::new("text/plain", "borrowed"),
+            ],
+        );
+        let usage = part.memory_usage();
+        assert!(usage >= 3000, "{}", usage);
+        assert!(usage < 5000, "{}", usage);
+
+        // Borrowed contents are not counted
+        let contents = "a".repeat(10_000);
+        let borrowed = MimePart::new("text/plain", contents.as_str());
+        assert!(borrowed.memory_usage() < 1000);
+        let owned = MimePart::new("text/plain", contents.clone());
+        assert!(owned.memory_usage() >= 10_000);
+        assert!(owned.memory_usage() < 11_000);
+    }
+
+    #[test]
+    fn display() {
+        let part = MimePart::new(
+            "multipart/mixed",
+            vec![
+                MimePart::new("text/plain", "Hello, world!"),
+                MimePart::new("application/octet-stream", vec![0, 1, 2]).attachment("a.bin"),
+            ],
+        )
+        .with_custom_boundary("display-boundary")
+        .unwrap();
+        let mut output = Vec::new();
+        part.clone().write_part(&mut output).unwrap();
+        assert_eq!(part.to_string(), String::from_utf8(output).unwrap());
+        assert_eq!(format!("{}", part), part.to_string());
+
+        let invalid = MimePart {
+            headers: vec![("Content-Type".into(), Text::new("multipart/mixed").into())],
+            contents: BodyPart::Multipart(vec![MimePart::new("text/plain", "Hi")]),
+        };
+        assert_eq!(
+            invalid.to_string(),
+            Error::UnsupportedContentType.to_string()
+        );
+    }
+
+    #[test]
+    fn new_text_from() {
+        use std::fmt::Write;
+
+        let (name, total) = ("Jane", 42.5);
+        let part = MimePart::new_text_from(|w| {
+            writeln!(w, "Hello {},", name)?;
+            write!(w, "Your total is {:.2} EUR.", total)
+        })
+        .unwrap();
+        assert_eq!(
+            part.to_string(),
+            MimePart::new(
+                "text/plain",
+                format!("Hello {},\nYour total is {:.2} EUR.", name, total)
+            )
+            .to_string()
+        );
+
+        assert!(MimePart::new_text_from(|_| Err(std::fmt::Error)).is_err());
+    }
+
+    #[cfg(feature = "sanitize")]
+    #[test]
+    fn new_html_sanitized() {
+        let part = MimePart::new_html_sanitized(
+            "<p onclick=\"x()\">Hi <a href=\"javascript:void(0)\">there</a></p><script>x()</script>",
+        );
+        assert_eq!(
+            part.to_string(),
+            MimePart::new("text/html", "<p onclick=\"x()\">Hi <a>there</a></p>",).to_string()
+        );
+    }
+
+    #[test]
+    fn new_text_rich() {
+        let part = MimePart::new_text_rich("<bold>Hello</bold>, <<world>!");
+        assert_eq!(part.content_type_str(), Some("text/enriched"));
+        assert_eq!(part.charset(), Some("utf-8"));
+
+        let text = concat!(
+            "<bold>Now</bold> is <italic><bigger>the</bigger> time</italic>\r\n",
+            "<fontfamily><param>Times <New> Roman</param>for</fontfamily> <<tags>.",
+        );
+        let mut output = Vec::new();
+        MimePart::new_text_rich(text)
+            .write_part(&mut output)
+            .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with("Content-Type: text/enriched; charset=\"utf-8\"\r\n"));
+
+        #[cfg(feature = "validate_enriched")]
+        for (text, command) in [
+            ("<bold>unclosed", "<bold>"),
+            ("<bold><italic>x</bold></italic>", "</bold>"),
+            ("x</bold>", "</bold>"),
+            ("<bold x>y</bold>", "<bold x>"),
+            ("<fontfamily><param>Times", "<param>"),
+            ("trailing <bold", "<bold"),
+        ] {
+            let err = MimePart::new_text_rich(text)
+                .write_part(&mut Vec::new())
+                .unwrap_err();
+            assert_eq!(
+                err.into_inner()
+                    .unwrap()
+                    .downcast::<Error>()
+                    .unwrap()
+                    .as_ref(),
+                &Error::InvalidEnrichedText(command.into()),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn reject_deeply_nested_multiparts() {
+        let mut part = MimePart::new("text/plain", "Hello");
+        for _ in 0..200 {
+            part = MimePart::new("multipart/mixed", vec![part]);
+        }
+
+        for err in [
+            part.clone().write_part(Vec::new()).unwrap_err(),
+            MessageBuilder::new()
+                .from("john@doe.com")
+                .body(part)
+                .write_to_vec()
+                .unwrap_err(),
+        ] {
+            assert_eq!(
+                err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+                Some(&Error::NestingTooDeep)
+            );
+        }
+    }
+
+    #[test]
+    fn skip_multipart_transfer_encoding() {
+        let mut output = Vec::new();
+        MimePart::new(
+            "multipart/mixed",
+            vec![MimePart::new("text/plain", "Héllo").transfer_encoding("base64")],
+        )
+        .header("Content-Transfer-Encoding", Raw::new("quoted-printable"))
+        .header("X-Custom", Raw::new("kept"))
+        .write_part(&mut output)
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let (headers, body) = output.split_once("\r\n\r\n").unwrap();
+        assert!(
+            headers.starts_with("Content-Type: multipart/mixed"),
+            "{}",
+            output
+        );
+        assert!(headers.ends_with("X-Custom: kept"), "{}", output);
+        assert!(!headers.contains("Content-Transfer-Encoding"), "{}", output);
+        assert_eq!(
+            body.matches("Content-Transfer-Encoding: base64").count(),
+            1,
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn content_duration() {
+        let mut output = Vec::new();
+        MimePart::new("audio/basic", vec![0u8, 1, 2])
+            .attachment("voicemail.au")
+            .content_duration(33)
+            .write_part(&mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("\r\nContent-Duration: 33\r\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn write_as_top_level_message() {
+        let part = MimePart::new(
+            "multipart/mixed",
+            vec![MimePart::new("text/plain", "Hello")],
+        );
+        let mut output = Vec::new();
+        let bytes_written = part
+            .clone()
+            .write_as_top_level_message(&mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(bytes_written, output.len() as u64);
+        assert!(
+            output.starts_with("MIME-Version: 1.0\r\nContent-Type: multipart/mixed"),
+            "{}",
+            output
+        );
+        assert_eq!(output.matches("MIME-Version").count(), 1, "{}", output);
+
+        let mut output = Vec::new();
+        part.clone().write_part(&mut output).unwrap();
+        assert!(!String::from_utf8(output).unwrap().contains("MIME-Version"));
+
+        let mut output = Vec::new();
+        part.header("MIME-Version", Raw::new("1.0"))
+            .write_as_top_level_message(&mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output)
+                .unwrap()
+                .matches("MIME-Version")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn strict_mode_unaffected_fixes() {
+        let mut part = MimePart::new("text/plain", "Hello");
+        assert!(part
+            .add_part(MimePart::new("text/plain", "World"))
+            .is_none());
+        assert!(matches!(&part.contents, BodyPart::Text(text) if text == "Hello"));
+
+        for options in [WriteOptions::new(), WriteOptions::new().strict(true)] {
+            let err = MimePart::new(
+                ContentType::new("multipart/mixed").attribute("boundary", "a b "),
+                vec![MimePart::new("text/plain", "Hello")],
+            )
+            .write_part_with_options(Vec::new(), &options)
+            .unwrap_err();
+            assert_eq!(
+                err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+                Some(&Error::InvalidBoundary(InvalidBoundaryError::TrailingSpace))
+            );
+        }
+    }
+
+    #[test]
+    fn strict_missing_content_type() {
+        let part = |child: MimePart<'static>| {
+            MimePart::new(
+                "multipart/mixed",
+                vec![MimePart::new("text/plain", "Hello"), child],
+            )
+        };
+        let untyped = MimePart::raw("Hello").header("Content-Disposition", Raw::new("inline"));
+        assert!(part(untyped.clone()).write_part(Vec::new()).is_ok());
+        let err = part(untyped.clone())
+            .write_part_with_options(Vec::new(), &WriteOptions::new().strict(true))
+            .unwrap_err();
+        assert_eq!(
+            err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+            Some(&Error::MissingContentType)
+        );
+
+        // Top-level parts and parts without headers are not checked.
+        assert!(untyped
+            .write_part_with_options(Vec::new(), &WriteOptions::new().strict(true))
+            .is_ok());
+        assert!(part(MimePart::raw("Content-Type: text/plain\r\n\r\nHello"))
+            .write_part_with_options(Vec::new(), &WriteOptions::new().strict(true))
+            .is_ok());
+    }
+}
@@ -0,0 +1,418 @@
+/*
+ * Copyright Stalwart Labs Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+//! A minimal MIME parser reading back the subset of MIME produced by this
+//! crate: text and binary parts encoded as 7bit, 8bit, quoted-printable or
+//! base64, and multipart/* parts. See [`MimePart::from_raw_mime`].
+
+use std::fmt::Display;
+
+use crate::{
+    headers::{content_type::ContentType, raw::Raw, HeaderType},
+    mime::{BodyPart, MimePart},
+};
+
+/// Maximum nesting depth of multipart/* parts accepted by the parser.
+const MAX_DEPTH: usize = 100;
+
+/// Errors raised while parsing a MIME part.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A header line is neither a header field nor a continuation line.
+    InvalidHeader(String),
+    /// A multipart/* part has no boundary parameter.
+    MissingBoundary,
+    /// A base64 encoded body contains characters outside of the base64
+    /// alphabet.
+    InvalidBase64,
+    /// Multipart/* parts are nested deeper than the parser allows.
+    NestingTooDeep,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::InvalidHeader(line) => write!(f, "Invalid header line {:?}.", line),
+            ParseError::MissingBoundary => write!(f, "Multipart without a boundary."),
+            ParseError::InvalidBase64 => write!(f, "Invalid base64 encoded body."),
+            ParseError::NestingTooDeep => write!(f, "Maximum MIME nesting depth exceeded."),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a MIME part, see [`MimePart::from_raw_mime`].
+pub(crate) fn parse_part(raw: &[u8], depth: usize) -> Result<MimePart<'static>, ParseError> {
+    if depth > MAX_DEPTH {
+        return Err(ParseError::NestingTooDeep);
+    }
+
+    let (headers, body) = split_header_section(raw);
+    let mut part = MimePart::raw(BodyPart::Binary(Vec::new().into()));
+    let mut transfer_encoding = None;
+    for (name, value) in parse_headers(headers)? {
+        if name.eq_ignore_ascii_case("Content-Transfer-Encoding") {
+            transfer_encoding = Some(value.trim().to_ascii_lowercase());
+        } else if name.eq_ignore_ascii_case("Content-Type")
+            || name.eq_ignore_ascii_case("Content-Disposition")
+        {
+            part.headers.push((
+                name.into(),
+                HeaderType::ContentType(ContentType::parse(&value)),
+            ));
+        } else {
+            part.headers.push((name.into(), Raw::new(value).into()));
+        }
+    }
+
+    let content_type = part
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+        .and_then(|(_, value)| value.as_content_type());
+    let c_type = content_type
+        .map(|ct| ct.c_type.to_ascii_lowercase())
+        .unwrap_or_else(|| "text/plain".to_string());
+
+    part.contents = if c_type.starts_with("multipart/") {
+        let boundary = content_type
+            .and_then(|ct| ct.attribute_case_insensitive("boundary"))
+            .ok_or(ParseError::MissingBoundary)?;
+        BodyPart::Multipart(
+            split_multipart(body, boundary)
+                .into_iter()
+                .map(|part| parse_part(part, depth + 1))
+                .collect::<Result<_, _>>()?,
+        )
+    } else {
+        let contents = match transfer_encoding.as_deref() {
+            Some("base64") => base64_decode(body)?,
+            Some("quoted-printable") => quoted_printable_decode(body),
+            _ => body.to_vec(),
+        };
+        // `Option::is_none_or` requires Rust 1.82.
+        #[allow(clippy::unnecessary_map_or)]
+        let is_utf8_text = c_type.starts_with("text/")
+            && content_type
+                .and_then(|ct| ct.attribute_case_insensitive("charset"))
+                .map_or(true, |charset| {
+                    charset.eq_ignore_ascii_case("utf-8")
+                        || charset.eq_ignore_ascii_case("us-ascii")
+                });
+        match String::from_utf8(contents) {
+            Ok(text) if is_utf8_text => BodyPart::Text(text.into()),
+            Ok(text) => BodyPart::Binary(text.into_bytes().into()),
+            Err(err) => BodyPart::Binary(err.into_bytes().into()),
+        }
+    };
+
+    Ok(part)
+}
+
+/// Splits a part into its header section and its body, separated by the
+/// first empty line.
+fn split_header_section(raw: &[u8]) -> (&[u8], &[u8]) {
+    let mut pos = 0;
+    for line in raw.split_inclusive(|&ch| ch == b'\n') {
+        if line == b"\r\n" || line == b"\n" {
+            return (&raw[..pos], &raw[pos + line.len()..]);
+        }
+        pos += line.len();
+    }
+    (raw, &[])
+}
+
+/// Parses a header section into unfolded header names and values.
+fn parse_headers(headers: &[u8]) -> Result<Vec<(String, String)>, ParseError> {
+    let mut result: Vec<(String, String)> = Vec::new();
+
+    for line in headers.split_inclusive(|&ch| ch == b'\n') {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.starts_with([' ', '\t']) {
+            match result.last_mut() {
+                Some((_, value)) => value.push_str(line),
+                None => return Err(ParseError::InvalidHeader(line.to_string())),
+            }
+        } else if let Some((name, value)) = line
+            .split_once(':')
+            .filter(|(name, _)| !name.trim().is_empty())
+        {
+            result.push((name.trim().to_string(), value.trim_start().to_string()));
+        } else {
+            return Err(ParseError::InvalidHeader(line.to_string()));
+        }
+    }
+
+    for (_, value) in &mut result {
+        value.truncate(value.trim_end().len());
+    }
+    Ok(result)
+}
+
+/// Returns the body parts of a multipart body, ignoring its preamble and
+/// epilogue. The line break preceding a delimiter belongs to the delimiter.
+fn split_multipart<'y>(body: &'y [u8], boundary: &str) -> Vec<&'y [u8]> {
+    let mut parts = Vec::new();
+    let mut part_start = None;
+    let mut pos = 0;
+
+    for line in body.split_inclusive(|&ch| ch == b'\n') {
+        let line_start = pos;
+        pos += line.len();
+        let Some(rest) = line
+            .strip_prefix(b"--")
+            .and_then(|line| line.strip_prefix(boundary.as_bytes()))
+        else {
+            continue;
+        };
+        let is_closing = rest.starts_with(b"--");
+        let rest = if is_closing { &rest[2..] } else { rest };
+        if !rest.iter().all(|ch| ch.is_ascii_whitespace()) {
+            continue;
+        }
+
+        if let Some(part_start) = part_start {
+            let mut part_end = line_start;
+            if body[..part_end].ends_with(b"\n") {
+                part_end -= 1;
+                if body[..part_end].ends_with(b"\r") {
+                    part_end -= 1;
+                }
+            }
+            parts.push(&body[part_start..part_end.max(part_start)]);
+        }
+        if is_closing {
+            return parts;
+        }
+        part_start = Some(pos);
+    }
+
+    if let Some(part_start) = part_start {
+        parts.push(&body[part_start..]);
+    }
+    parts
+}
+
+fn base64_decode(input: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buf = 0u32;
+    let mut bits = 0;
+
+    for &ch in input {
+        let value = match ch {
+            b'A'..=b'Z' => ch - b'A',
+            b'a'..=b'z' => ch - b'a' + 26,
+            b'0'..=b'9' => ch - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ if ch.is_ascii_whitespace() => continue,
+            _ => return Err(ParseError::InvalidBase64),
+        };
+        buf = (buf << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buf >> bits) as u8);
+            buf &= (1 << bits) - 1;
+        }
+    }
+
+    Ok(output)
+}
+
+fn quoted_printable_decode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut pos = 0;
+
+    while pos < input.len() {
+        let ch = input[pos];
+        if ch != b'=' {
+            output.push(ch);
+            pos += 1;
+        } else if input[pos + 1..].starts_with(b"\r\n") {
+            pos += 3;
+        } else if input[pos + 1..].starts_with(b"\n") {
+            pos += 2;
+        } else if let Some(&[hi, lo]) = input
+            .get(pos + 1..pos + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+        {
+            output.push(hex_value(hi) << 4 | hex_value(lo));
+            pos += 3;
+        } else {
+            output.push(ch);
+            pos += 1;
+        }
+    }
+
+    output
+}
+
+fn hex_value(ch: u8) -> u8 {
+    match ch {
+        b'0'..=b'9' => ch - b'0',
+        _ => (ch | 0x20) - b'a' + 10,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        headers::{Header, HeaderType},
+        mime::{BodyPart, MimePart},
+        MessageBuilder,
+    };
+
+    use super::ParseError;
+
+    #[test]
+    fn parse_written_message() {
+        let message = MessageBuilder::new()
+            .from(("Jöhn Doe", "john@doe.com"))
+            .to("jane@doe.com")
+            .subject("Hello, world!")
+            .text_body("Hello, world!\r\nGrüße =)\r\n")
+            .html_body("<p>Hello, world!</p>")
+            .attachment(
+                "image/png",
+                "image.png",
+                b"\x89PNG\r\n\x1a\n\0\x01\x02".as_ref(),
+            )
+            .write_to_vec()
+            .unwrap();
+
+        let part = MimePart::from_raw_mime(message.clone()).unwrap();
+        assert_eq!(part.content_type_str(), Some("multipart/mixed"));
+        let header = |part: &MimePart, name: &str| {
+            part.headers
+                .iter()
+                .find(|(header_name, _)| header_name == name)
+                .map(|(_, value)| {
+                    let mut output = Vec::new();
+                    value.write_header(&mut output, 0).unwrap();
+                    String::from_utf8(output).unwrap()
+                })
+        };
+        assert_eq!(header(&part, "Subject").unwrap(), "Hello, world!\r\n");
+        assert!(header(&part, "Content-Transfer-Encoding").is_none());
+
+        let BodyPart::Multipart(parts) = &part.contents else {
+            panic!("expected multipart: {:?}", part.contents);
+        };
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].content_type_str(), Some("multipart/alternative"));
+        let BodyPart::Multipart(alternatives) = &parts[0].contents else {
+            panic!("expected multipart: {:?}", parts[0].contents);
+        };
+        assert!(matches!(
+            &alternatives[0].contents,
+            BodyPart::Text(text) if text == "Hello, world!\r\nGrüße =)\r\n"
+        ));
+        assert!(matches!(
+            &alternatives[1].contents,
+            BodyPart::Text(text) if text == "<p>Hello, world!</p>"
+        ));
+        assert!(matches!(
+            &parts[1].contents,
+            BodyPart::Binary(binary) if binary.as_ref() == b"\x89PNG\r\n\x1a\n\0\x01\x02"
+        ));
+        assert_eq!(
+            header(&parts[1], "Content-Disposition").unwrap(),
+            "attachment; filename=\"image.png\"\r\n"
+        );
+
+        // Writing the parsed message again produces the same output.
+        let mut output = Vec::new();
+        part.write_part(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(message).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_lf_line_endings() {
+        let part = MimePart::from_raw_mime(
+            concat!(
+                "Subject: Hello,\n\tworld\n",
+                "Content-Type: multipart/mixed;\n boundary=\"b1\"\n\n",
+                "preamble\n--b1\n",
+                "Content-Type: text/plain; charset=iso-8859-1\n",
+                "Content-Transfer-Encoding: quoted-printable\n\n",
+                "Gr=FC=DFe, soft=\nbreak\n",
+                "--b1\n\n",
+                "No headers\n",
+                "--b1--\nepilogue\n",
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        assert!(matches!(
+            &part.headers[0],
+            (name, HeaderType::Raw(raw)) if name == "Subject" && raw.raw == "Hello,\tworld"
+        ));
+        let BodyPart::Multipart(parts) = &part.contents else {
+            panic!("expected multipart: {:?}", part.contents);
+        };
+        assert!(matches!(
+            &parts[0].contents,
+            BodyPart::Binary(binary) if binary.as_ref() == b"Gr\xfc\xdfe, softbreak"
+        ));
+        assert!(matches!(
+            &parts[1].contents,
+            BodyPart::Text(text) if text == "No headers"
+        ));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (raw, expected) in [
+            (
+                "Subject: Hi\r\nnot a header\r\n\r\nbody".to_string(),
+                ParseError::InvalidHeader("not a header".into()),
+            ),
+            (
+                " continuation\r\n\r\nbody".to_string(),
+                ParseError::InvalidHeader(" continuation".into()),
+            ),
+            (
+                "Content-Type: multipart/mixed\r\n\r\n--b\r\n\r\nx\r\n--b--\r\n".to_string(),
+                ParseError::MissingBoundary,
+            ),
+            (
+                "Content-Type: image/png\r\nContent-Transfer-Encoding: base64\r\n\r\niVBO*w0K\r\n"
+                    .to_string(),
+                ParseError::InvalidBase64,
+            ),
+            (
+                (0..102)
+                    .map(|level| {
+                        format!(
+                            "Content-Type: multipart/mixed; boundary=b{}\r\n\r\n--b{}\r\n",
+                            level, level
+                        )
+                    })
+                    .collect(),
+                ParseError::NestingTooDeep,
+            ),
+        ] {
+            assert_eq!(
+                MimePart::from_raw_mime(raw.as_bytes()).unwrap_err(),
+                expected,
+                "{}",
+                raw
+            );
+        }
+    }
+}
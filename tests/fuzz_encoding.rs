@@ -0,0 +1,97 @@
+/*
+ * Copyright Stalwart Labs Ltd. See the COPYING
+ * file at the top-level directory of this distribution.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use mail_builder::encoders::{
+    base64::base64_encode_mime, encode::get_encoding_type,
+    quoted_printable::quoted_printable_encode,
+};
+use mail_parser::decoders::{base64::base64_decode, quoted_printable::quoted_printable_decode};
+use proptest::prelude::*;
+
+fn assert_line_lengths(output: &[u8]) {
+    for line in output.split(|&ch| ch == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        assert!(
+            line.len() <= 76,
+            "line of {} bytes: {:?}",
+            line.len(),
+            String::from_utf8_lossy(line)
+        );
+    }
+}
+
+proptest! {
+    #[test]
+    fn get_encoding_type_never_panics(
+        input in proptest::collection::vec(any::<u8>(), 0..2048),
+        is_inline in any::<bool>(),
+        is_body in any::<bool>(),
+    ) {
+        get_encoding_type(&input, is_inline, is_body);
+    }
+
+    #[test]
+    fn base64_round_trip(input in proptest::collection::vec(any::<u8>(), 0..2048)) {
+        let mut output = Vec::new();
+        base64_encode_mime(&input, &mut output, false).unwrap();
+        assert_line_lengths(&output);
+
+        let encoded = output
+            .iter()
+            .copied()
+            .filter(|ch| !ch.is_ascii_whitespace())
+            .collect::<Vec<_>>();
+        prop_assert_eq!(base64_decode(&encoded).unwrap_or_default(), input);
+    }
+
+    #[test]
+    fn quoted_printable_ascii_escapes(input in "[ -~]{0,400}") {
+        for is_body in [true, false] {
+            let mut output = Vec::new();
+            quoted_printable_encode(input.as_bytes(), &mut output, false, is_body).unwrap();
+            assert_line_lengths(&output);
+
+            let output = String::from_utf8(output).unwrap();
+            for line in output.split("\r\n") {
+                let mut rest = line;
+                while let Some(pos) = rest.find('=') {
+                    let escape = &rest[pos..];
+                    let is_line_start = pos == 0 && rest.len() == line.len();
+                    prop_assert!(
+                        escape == "="
+                            || escape.starts_with("=3D")
+                            || escape == "=20"
+                            || escape == "=09"
+                            || (is_line_start
+                                && (escape.starts_with("=46rom ") || escape == "=2E")),
+                        "unexpected escape in {:?}",
+                        output
+                    );
+                    rest = &escape[1..];
+                }
+            }
+            prop_assert_eq!(
+                quoted_printable_decode(output.as_bytes()).unwrap_or_default(),
+                input.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn quoted_printable_line_lengths(
+        input in proptest::collection::vec(any::<u8>(), 0..2048),
+        is_body in any::<bool>(),
+    ) {
+        let mut output = Vec::new();
+        quoted_printable_encode(&input, &mut output, false, is_body).unwrap();
+        assert_line_lengths(&output);
+    }
+}